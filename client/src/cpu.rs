@@ -0,0 +1,240 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// the first sample has nothing to compare with, measure over this instead
+const FIRST_SAMPLE_WINDOW: Duration = Duration::from_millis(250);
+
+/// /proc/stat of the previous sample, rates are computed against it
+static PREVIOUS: Mutex<Option<(ProcStat, Instant)>> = Mutex::new(None);
+
+/// Jiffies of one cpu line in /proc/stat.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct CpuTimes {
+    user: u64,
+    nice: u64,
+    system: u64,
+    idle: u64,
+    iowait: u64,
+    irq: u64,
+    softirq: u64,
+    steal: u64,
+}
+
+impl CpuTimes {
+    fn total(&self) -> u64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait
+            + self.irq
+            + self.softirq
+            + self.steal
+    }
+
+    /// Ratios of the time spent since `previous`.
+    fn load_since(&self, previous: &CpuTimes) -> CpuLoad {
+        let total = self.total().saturating_sub(previous.total());
+        let ratio = |now: u64, before: u64| -> f32 {
+            if total == 0 {
+                0.0
+            } else {
+                now.saturating_sub(before) as f32 / total as f32
+            }
+        };
+        CpuLoad {
+            user: ratio(self.user, previous.user),
+            nice: ratio(self.nice, previous.nice),
+            system: ratio(self.system, previous.system),
+            interrupt: ratio(self.irq + self.softirq, previous.irq + previous.softirq),
+            idle: ratio(self.idle, previous.idle),
+            iowait: ratio(self.iowait, previous.iowait),
+            steal: ratio(self.steal, previous.steal),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ProcStat {
+    total: CpuTimes,
+    cores: Vec<CpuTimes>,
+    context_switches: u64,
+    procs_running: u64,
+}
+
+/// Share of time by mode, the modes add up to 1.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct CpuLoad {
+    pub user: f32,
+    pub nice: f32,
+    pub system: f32,
+    pub interrupt: f32,
+    pub idle: f32,
+    pub iowait: f32,
+    pub steal: f32,
+}
+
+impl CpuLoad {
+    /// The aggregate as sent in `Sample.cpu`.
+    pub fn to_map(self) -> HashMap<String, f32> {
+        HashMap::from([
+            (String::from("user"), self.user),
+            (String::from("nice"), self.nice),
+            (String::from("system"), self.system),
+            (String::from("interrupt"), self.interrupt),
+            (String::from("idle"), self.idle),
+            (String::from("iowait"), self.iowait),
+            (String::from("steal"), self.steal),
+        ])
+    }
+}
+
+/// Everything about the cpu beyond the aggregate load.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct CpuDetail {
+    /// load of every core since the previous sample
+    pub cores: Vec<CpuLoad>,
+    /// 1, 5 and 15 minutes
+    pub load_average: [f32; 3],
+    pub processes_running: u64,
+    pub processes_total: u64,
+    pub threads: u64,
+    pub context_switches_per_sec: f64,
+}
+
+fn parse_times(fields: &[&str]) -> CpuTimes {
+    let field = |i: usize| -> u64 { fields.get(i).and_then(|f| f.parse().ok()).unwrap_or(0) };
+    CpuTimes {
+        user: field(0),
+        nice: field(1),
+        system: field(2),
+        idle: field(3),
+        iowait: field(4),
+        irq: field(5),
+        softirq: field(6),
+        steal: field(7),
+    }
+}
+
+fn parse_stat(text: &str) -> ProcStat {
+    let mut stat = ProcStat::default();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.first() {
+            Some(&"cpu") => stat.total = parse_times(&fields[1..]),
+            Some(name) if name.starts_with("cpu") => stat.cores.push(parse_times(&fields[1..])),
+            Some(&"ctxt") => {
+                stat.context_switches = fields.get(1).and_then(|f| f.parse().ok()).unwrap_or(0)
+            }
+            Some(&"procs_running") => {
+                stat.procs_running = fields.get(1).and_then(|f| f.parse().ok()).unwrap_or(0)
+            }
+            _ => (),
+        }
+    }
+    stat
+}
+
+/// "0.52 0.58 0.59 3/1024 12345" -> load averages and the number of threads
+fn parse_loadavg(text: &str) -> Option<([f32; 3], u64)> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    let load = |i: usize| -> Option<f32> { fields.get(i)?.parse().ok() };
+    let threads = fields.get(3)?.split_once('/')?.1.parse().ok()?;
+    Some(([load(0)?, load(1)?, load(2)?], threads))
+}
+
+fn read_stat() -> Result<ProcStat, String> {
+    let text = fs::read_to_string("/proc/stat").map_err(|e| format!("read /proc/stat: {}", e))?;
+    Ok(parse_stat(&text))
+}
+
+fn count_processes() -> u64 {
+    match fs::read_dir("/proc") {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                e.file_name()
+                    .to_string_lossy()
+                    .bytes()
+                    .all(|b| b.is_ascii_digit())
+            })
+            .count() as u64,
+        Err(_) => 0,
+    }
+}
+
+/// Aggregate load and details since the previous call.
+pub fn collect() -> Result<(CpuLoad, CpuDetail), String> {
+    let mut previous = PREVIOUS.lock().unwrap_or_else(|e| e.into_inner());
+    let (before, since) = match previous.take() {
+        Some(p) => p,
+        None => {
+            let first = (read_stat()?, Instant::now());
+            thread::sleep(FIRST_SAMPLE_WINDOW);
+            first
+        }
+    };
+    let now = read_stat()?;
+    let read_at = Instant::now();
+    let elapsed = read_at.duration_since(since).as_secs_f64();
+    let load = now.total.load_since(&before.total);
+    let cores = now
+        .cores
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c.load_since(before.cores.get(i).unwrap_or(c)))
+        .collect();
+    let context_switches_per_sec = if elapsed > 0.0 {
+        now.context_switches.saturating_sub(before.context_switches) as f64 / elapsed
+    } else {
+        0.0
+    };
+    let (load_average, threads) = fs::read_to_string("/proc/loadavg")
+        .ok()
+        .and_then(|t| parse_loadavg(&t))
+        .unwrap_or_default();
+    let detail = CpuDetail {
+        cores,
+        load_average,
+        processes_running: now.procs_running,
+        processes_total: count_processes(),
+        threads,
+        context_switches_per_sec,
+    };
+    *previous = Some((now, read_at));
+    Ok((load, detail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse_stat() {
+        let before = parse_stat(
+            "cpu  100 0 50 800 40 5 5 0 0 0\ncpu0 50 0 25 400 20 0 5 0 0 0\ncpu1 50 0 25 400 20 5 0 0 0 0\nctxt 1000\nprocs_running 3\n",
+        );
+        let after = parse_stat(
+            "cpu  200 0 100 1600 80 10 10 0 0 0\ncpu0 150 0 50 400 20 0 5 0 0 0\ncpu1 50 0 50 1200 60 10 5 0 0 0\nctxt 1500\nprocs_running 2\n",
+        );
+        assert_eq!(after.cores.len(), 2);
+        assert_eq!(after.context_switches, 1500);
+        assert_eq!(after.procs_running, 2);
+        let load = after.total.load_since(&before.total);
+        assert!((load.user - 0.1).abs() < 1e-6);
+        assert!((load.iowait - 0.04).abs() < 1e-6);
+        assert_eq!(after.cores[0].load_since(&before.cores[0]).idle, 0.0);
+    }
+    #[test]
+    fn test_parse_loadavg() {
+        assert_eq!(
+            parse_loadavg("0.52 0.58 0.59 3/1024 12345\n"),
+            Some(([0.52, 0.58, 0.59], 1024))
+        );
+        assert_eq!(parse_loadavg(""), None);
+    }
+}
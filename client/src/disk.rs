@@ -0,0 +1,175 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+use systemstat::BlockDeviceStats;
+use systemstat::Platform;
+use systemstat::System;
+
+/// /proc/diskstats counts 512 byte sectors whatever the device uses
+const SECTOR_SIZE: f64 = 512.0;
+/// pseudo and in-memory filesystems nobody runs out of
+pub const DEFAULT_EXCLUDE_FS: &str = "tmpfs,devtmpfs,overlay,squashfs,proc,sysfs,cgroup,cgroup2,devpts,mqueue,debugfs,tracefs,securityfs,pstore,bpf,configfs,fusectl,hugetlbfs,autofs,binfmt_misc,nsfs,ramfs,efivarfs,rpc_pipefs";
+
+/// block device counters of the previous sample
+static PREVIOUS: Mutex<Option<(BTreeMap<String, BlockDeviceStats>, Instant)>> = Mutex::new(None);
+
+/// Which mounts to report, by filesystem type.
+#[derive(Clone, Debug, Default)]
+pub struct MountFilter {
+    /// only these types if not empty
+    pub include_fs: Vec<String>,
+    pub exclude_fs: Vec<String>,
+}
+
+impl MountFilter {
+    fn accepts(&self, fs_type: &str) -> bool {
+        if !self.include_fs.is_empty() {
+            return self.include_fs.iter().any(|f| f == fs_type);
+        }
+        !self.exclude_fs.iter().any(|f| f == fs_type)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MountUsage {
+    pub mount: String,
+    pub device: String,
+    pub fs_type: String,
+    pub size_bytes: u64,
+    pub used_bytes: u64,
+    /// free for unprivileged users
+    pub avail_bytes: u64,
+    pub inodes_total: u64,
+    pub inodes_used: u64,
+}
+
+/// Throughput of one block device since the previous sample.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct DiskIo {
+    pub device: String,
+    pub read_bytes_per_sec: f64,
+    pub write_bytes_per_sec: f64,
+    pub reads_per_sec: f64,
+    pub writes_per_sec: f64,
+    /// share of the time the device was busy
+    pub utilization: f64,
+}
+
+/// Filesystems of the host that pass the filter, the same device mounted twice counts once.
+pub fn mounts(filter: &MountFilter) -> Result<Vec<MountUsage>, String> {
+    let sys = System::new();
+    let mut mounts: Vec<MountUsage> = Vec::new();
+    for fs in sys.mounts().map_err(|e| e.to_string())? {
+        if !filter.accepts(&fs.fs_type) || fs.total.as_u64() == 0 {
+            continue;
+        }
+        if mounts.iter().any(|m| m.device == fs.fs_mounted_from) {
+            continue;
+        }
+        mounts.push(MountUsage {
+            mount: fs.fs_mounted_on,
+            device: fs.fs_mounted_from,
+            fs_type: fs.fs_type,
+            size_bytes: fs.total.as_u64(),
+            used_bytes: fs.total.as_u64().saturating_sub(fs.free.as_u64()),
+            avail_bytes: fs.avail.as_u64(),
+            inodes_total: fs.files_total as u64,
+            inodes_used: fs.files as u64,
+        });
+    }
+    Ok(mounts)
+}
+
+fn io_since(
+    device: &str,
+    now: &BlockDeviceStats,
+    before: &BlockDeviceStats,
+    seconds: f64,
+) -> DiskIo {
+    let rate = |now: usize, before: usize| now.saturating_sub(before) as f64 / seconds;
+    DiskIo {
+        device: device.to_string(),
+        read_bytes_per_sec: rate(now.read_sectors, before.read_sectors) * SECTOR_SIZE,
+        write_bytes_per_sec: rate(now.write_sectors, before.write_sectors) * SECTOR_SIZE,
+        reads_per_sec: rate(now.read_ios, before.read_ios),
+        writes_per_sec: rate(now.write_ios, before.write_ios),
+        // io_ticks are milliseconds
+        utilization: (rate(now.io_ticks, before.io_ticks) / 1000.0).min(1.0),
+    }
+}
+
+/// Whole disks only, partitions and loop devices are left out.
+fn is_disk(name: &str) -> bool {
+    !name.starts_with("loop")
+        && !name.starts_with("ram")
+        && Path::new("/sys/block").join(name).exists()
+}
+
+/// Rates of every disk since the previous call, empty on the first call.
+pub fn io() -> Result<Vec<DiskIo>, String> {
+    let sys = System::new();
+    let stats: BTreeMap<String, BlockDeviceStats> = sys
+        .block_device_statistics()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|(name, _)| is_disk(name))
+        .collect();
+    let read_at = Instant::now();
+    let mut previous = PREVIOUS.lock().unwrap_or_else(|e| e.into_inner());
+    let io = match previous.as_ref() {
+        Some((before, since)) if read_at > *since => {
+            let seconds = read_at.duration_since(*since).as_secs_f64();
+            stats
+                .iter()
+                .filter_map(|(name, now)| Some(io_since(name, now, before.get(name)?, seconds)))
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    *previous = Some((stats, read_at));
+    Ok(io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn stats(sectors: usize, ios: usize, io_ticks: usize) -> BlockDeviceStats {
+        BlockDeviceStats {
+            name: String::from("sda"),
+            read_ios: ios,
+            read_merges: 0,
+            read_sectors: sectors,
+            read_ticks: 0,
+            write_ios: 0,
+            write_merges: 0,
+            write_sectors: 0,
+            write_ticks: 0,
+            in_flight: 0,
+            io_ticks,
+            time_in_queue: 0,
+        }
+    }
+    #[test]
+    fn test_io_since() {
+        let io = io_since("sda", &stats(4096, 30, 1500), &stats(2048, 10, 500), 2.0);
+        assert_eq!(io.read_bytes_per_sec, 524288.0);
+        assert_eq!(io.reads_per_sec, 10.0);
+        assert_eq!(io.utilization, 0.5);
+    }
+    #[test]
+    fn test_mount_filter() {
+        let filter = MountFilter {
+            include_fs: Vec::new(),
+            exclude_fs: vec![String::from("tmpfs")],
+        };
+        assert!(filter.accepts("ext4"));
+        assert!(!filter.accepts("tmpfs"));
+        let only_nfs = MountFilter {
+            include_fs: vec![String::from("nfs4")],
+            exclude_fs: Vec::new(),
+        };
+        assert!(!only_nfs.accepts("ext4"));
+    }
+}
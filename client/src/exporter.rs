@@ -0,0 +1,653 @@
+use log::error;
+use log::info;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+
+use crate::CollectorStatus;
+use crate::Sample;
+
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const JSON_CONTENT_TYPE: &str = "application/json";
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// Start the embedded HTTP listener in a background thread.
+pub fn serve(addr: &str, latest: Arc<RwLock<Option<Sample>>>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("exporter is listening on {}", addr);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let latest = latest.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, &latest) {
+                            error!("exporter connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("exporter accept error: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn read_request_line(stream: &mut TcpStream) -> std::io::Result<String> {
+    // we only care about the request line, the headers are read and ignored
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < 8192 {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request = String::from_utf8_lossy(&buf);
+    let line = request.lines().next().unwrap_or_default();
+    Ok(line.to_string())
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    latest: &RwLock<Option<Sample>>,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let request_line = read_request_line(&mut stream)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default();
+
+    let sample = match latest.read() {
+        Ok(l) => l.clone(),
+        Err(_) => None,
+    };
+    let (status, content_type, body) = if method != "GET" {
+        (
+            "405 Method Not Allowed",
+            TEXT_CONTENT_TYPE,
+            String::from("method not allowed\n"),
+        )
+    } else {
+        match (path, sample) {
+            ("/metrics", Some(s)) => ("200 OK", OPENMETRICS_CONTENT_TYPE, render_metrics(&s)),
+            ("/sample.json", Some(s)) => match serde_json::to_string(&s) {
+                Ok(j) => ("200 OK", JSON_CONTENT_TYPE, j),
+                Err(e) => (
+                    "500 Internal Server Error",
+                    TEXT_CONTENT_TYPE,
+                    format!("{}\n", e),
+                ),
+            },
+            ("/metrics", None) | ("/sample.json", None) => (
+                "503 Service Unavailable",
+                TEXT_CONTENT_TYPE,
+                String::from("no sample collected yet\n"),
+            ),
+            _ => (
+                "404 Not Found",
+                TEXT_CONTENT_TYPE,
+                String::from("not found\n"),
+            ),
+        }
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+/// "36 %" -> 0.36
+fn parse_percent(input: &str) -> Option<f64> {
+    let value: f64 = input.trim().trim_end_matches('%').trim().parse().ok()?;
+    Some(value / 100.0)
+}
+
+/// "24564 MiB" -> bytes
+fn parse_mib(input: &str) -> Option<f64> {
+    let value: f64 = input.trim().trim_end_matches("MiB").trim().parse().ok()?;
+    Some(value * 1024.0 * 1024.0)
+}
+
+fn escape_label(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+struct MetricFamily {
+    name: &'static str,
+    help: &'static str,
+    samples: Vec<(String, f64)>,
+}
+
+impl MetricFamily {
+    fn new(name: &'static str, help: &'static str) -> MetricFamily {
+        MetricFamily {
+            name,
+            help,
+            samples: Vec::new(),
+        }
+    }
+    fn push(&mut self, labels: &[(&str, &str)], value: f64) {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+            .collect::<Vec<String>>()
+            .join(",");
+        self.samples.push((labels, value));
+    }
+    fn render(&self, output: &mut String) {
+        if self.samples.is_empty() {
+            return;
+        }
+        let _ = writeln!(output, "# TYPE {} gauge", self.name);
+        let _ = writeln!(output, "# HELP {} {}", self.name, self.help);
+        for (labels, value) in &self.samples {
+            if labels.is_empty() {
+                let _ = writeln!(output, "{} {}", self.name, value);
+            } else {
+                let _ = writeln!(output, "{}{{{}}} {}", self.name, labels, value);
+            }
+        }
+    }
+}
+
+/// Render the sample as OpenMetrics text.
+pub fn render_metrics(sample: &Sample) -> String {
+    let mut host_info = MetricFamily::new("watchdog_host_info", "Host reporting this sample.");
+    host_info.push(&[("host", &sample.hostname)], 1.0);
+    let mut boot_time = MetricFamily::new(
+        "watchdog_boot_time_seconds",
+        "Unix time the host booted at.",
+    );
+    if let Some(b) = sample.boot_time {
+        boot_time.push(&[], b as f64);
+    }
+    let mut host_label = MetricFamily::new("watchdog_host_label", "Host labels set with --label.");
+    let mut labels: Vec<(&String, &String)> = sample.labels.iter().collect();
+    labels.sort();
+    for (key, value) in labels {
+        host_label.push(&[("key", key), ("value", value)], 1.0);
+    }
+
+    let mut cpu_usage = MetricFamily::new("watchdog_cpu_usage_ratio", "CPU time ratio by mode.");
+    let mut cpu_temp = MetricFamily::new(
+        "watchdog_cpu_temperature_celsius",
+        "CPU temperature in celsius.",
+    );
+    let mut cpu_modes: Vec<(&String, &f32)> = sample.cpu.iter().collect();
+    cpu_modes.sort_by(|a, b| a.0.cmp(b.0));
+    for (mode, value) in cpu_modes {
+        if mode == "temp" {
+            cpu_temp.push(&[], *value as f64);
+        } else {
+            cpu_usage.push(&[("mode", mode)], *value as f64);
+        }
+    }
+
+    let mut core_usage = MetricFamily::new(
+        "watchdog_cpu_core_usage_ratio",
+        "CPU time ratio by core and mode.",
+    );
+    let mut load_average = MetricFamily::new("watchdog_load_average", "System load average.");
+    let mut processes = MetricFamily::new("watchdog_processes", "Processes by state.");
+    let mut threads = MetricFamily::new("watchdog_threads", "Threads on the host.");
+    let mut context_switches = MetricFamily::new(
+        "watchdog_context_switches_per_second",
+        "Context switches per second since the previous sample.",
+    );
+    if let Some(detail) = &sample.cpu_detail {
+        for (i, core) in detail.cores.iter().enumerate() {
+            let index = i.to_string();
+            let modes: BTreeMap<String, f32> = core.to_map().into_iter().collect();
+            for (mode, value) in modes {
+                core_usage.push(&[("core", &index), ("mode", &mode)], value as f64);
+            }
+        }
+        for (period, value) in ["1m", "5m", "15m"].iter().zip(detail.load_average) {
+            load_average.push(&[("period", period)], value as f64);
+        }
+        processes.push(&[("state", "running")], detail.processes_running as f64);
+        processes.push(&[("state", "total")], detail.processes_total as f64);
+        threads.push(&[], detail.threads as f64);
+        context_switches.push(&[], detail.context_switches_per_sec);
+    }
+
+    let mut memory = MetricFamily::new("watchdog_memory_bytes", "Memory in bytes.");
+    let mut swap = MetricFamily::new("watchdog_swap_bytes", "Swap in bytes.");
+    for (family, hm) in [(&mut memory, &sample.mem), (&mut swap, &sample.swap)] {
+        for state in ["used", "total"] {
+            let value = hm
+                .get(&format!("{}_bytes", state))
+                .and_then(|v| v.parse::<f64>().ok());
+            if let Some(v) = value {
+                family.push(&[("state", state)], v);
+            }
+        }
+    }
+
+    let mut filesystem =
+        MetricFamily::new("watchdog_filesystem_bytes", "Filesystem size in bytes.");
+    let mut inodes = MetricFamily::new("watchdog_filesystem_inodes", "Filesystem inodes.");
+    for m in &sample.disks {
+        for (state, value) in [
+            ("size", m.size_bytes),
+            ("used", m.used_bytes),
+            ("avail", m.avail_bytes),
+        ] {
+            filesystem.push(
+                &[
+                    ("mount", &m.mount),
+                    ("fs_type", &m.fs_type),
+                    ("state", state),
+                ],
+                value as f64,
+            );
+        }
+        for (state, value) in [("total", m.inodes_total), ("used", m.inodes_used)] {
+            inodes.push(&[("mount", &m.mount), ("state", state)], value as f64);
+        }
+    }
+    let mut disk_bytes = MetricFamily::new(
+        "watchdog_disk_bytes_per_second",
+        "Disk throughput since the previous sample.",
+    );
+    let mut disk_ops = MetricFamily::new(
+        "watchdog_disk_operations_per_second",
+        "Disk operations since the previous sample.",
+    );
+    let mut disk_util = MetricFamily::new(
+        "watchdog_disk_utilization_ratio",
+        "Share of the time the disk was busy.",
+    );
+    for d in &sample.disk_io {
+        disk_bytes.push(
+            &[("device", &d.device), ("direction", "read")],
+            d.read_bytes_per_sec,
+        );
+        disk_bytes.push(
+            &[("device", &d.device), ("direction", "write")],
+            d.write_bytes_per_sec,
+        );
+        disk_ops.push(
+            &[("device", &d.device), ("direction", "read")],
+            d.reads_per_sec,
+        );
+        disk_ops.push(
+            &[("device", &d.device), ("direction", "write")],
+            d.writes_per_sec,
+        );
+        disk_util.push(&[("device", &d.device)], d.utilization);
+    }
+
+    let mut net_info = MetricFamily::new(
+        "watchdog_network_info",
+        "Network interface with MAC address and link state.",
+    );
+    let mut net_address = MetricFamily::new(
+        "watchdog_network_address_info",
+        "Address of a network interface.",
+    );
+    let mut net_speed = MetricFamily::new(
+        "watchdog_network_speed_mbps",
+        "Link speed of a network interface.",
+    );
+    let mut net_bytes = MetricFamily::new(
+        "watchdog_network_bytes_per_second",
+        "Network traffic since the previous sample.",
+    );
+    let mut net_packets = MetricFamily::new(
+        "watchdog_network_packets_per_second",
+        "Network packets since the previous sample.",
+    );
+    let mut net_errors = MetricFamily::new(
+        "watchdog_network_errors_per_second",
+        "Network errors since the previous sample.",
+    );
+    let mut net_drops = MetricFamily::new(
+        "watchdog_network_drops_per_second",
+        "Dropped network packets since the previous sample.",
+    );
+    for i in &sample.interfaces {
+        net_info.push(
+            &[("interface", &i.name), ("mac", &i.mac), ("state", &i.state)],
+            1.0,
+        );
+        for address in &i.addresses {
+            net_address.push(&[("interface", &i.name), ("address", address)], 1.0);
+        }
+        if let Some(speed) = i.speed_mbps {
+            net_speed.push(&[("interface", &i.name)], speed as f64);
+        }
+        if let Some(r) = &i.rates {
+            for (family, rx, tx) in [
+                (&mut net_bytes, r.rx_bytes, r.tx_bytes),
+                (&mut net_packets, r.rx_packets, r.tx_packets),
+                (&mut net_errors, r.rx_errors, r.tx_errors),
+                (&mut net_drops, r.rx_dropped, r.tx_dropped),
+            ] {
+                family.push(&[("interface", &i.name), ("direction", "rx")], rx);
+                family.push(&[("interface", &i.name), ("direction", "tx")], tx);
+            }
+        }
+    }
+
+    let mut sensor_temp = MetricFamily::new(
+        "watchdog_sensor_temperature_celsius",
+        "Temperature of a hwmon sensor.",
+    );
+    let mut sensor_fan =
+        MetricFamily::new("watchdog_sensor_fan_rpm", "Fan speed of a hwmon sensor.");
+    let mut sensor_voltage =
+        MetricFamily::new("watchdog_sensor_volts", "Voltage of a hwmon sensor.");
+    for (family, readings) in [
+        (&mut sensor_temp, &sample.sensors.temperatures),
+        (&mut sensor_fan, &sample.sensors.fans),
+        (&mut sensor_voltage, &sample.sensors.voltages),
+    ] {
+        for r in readings {
+            family.push(&[("chip", &r.chip), ("sensor", &r.label)], r.value);
+        }
+    }
+    let mut power = MetricFamily::new(
+        "watchdog_rapl_power_watts",
+        "Average power of a RAPL zone since the previous sample.",
+    );
+    for zone in &sample.sensors.power {
+        power.push(&[("zone", &zone.id), ("name", &zone.name)], zone.watts);
+    }
+
+    let mut user_cpu = MetricFamily::new(
+        "watchdog_user_cpu_usage_ratio",
+        "CPU used by the processes of a user, 1 is one core.",
+    );
+    let mut user_memory = MetricFamily::new(
+        "watchdog_user_memory_bytes",
+        "Resident memory of the processes of a user.",
+    );
+    let mut user_processes = MetricFamily::new("watchdog_user_processes", "Processes of a user.");
+    if let Some(p) = &sample.processes {
+        for u in &p.users {
+            user_cpu.push(&[("user", &u.user)], u.cpu_percent / 100.0);
+            user_memory.push(&[("user", &u.user)], u.rss_bytes as f64);
+            user_processes.push(&[("user", &u.user)], u.processes as f64);
+        }
+    }
+
+    let mut sessions = MetricFamily::new(
+        "watchdog_sessions",
+        "Logins and tmux or screen servers of a user.",
+    );
+    let mut per_user: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+    for s in &sample.sessions {
+        *per_user.entry((&s.user, &s.kind)).or_insert(0) += 1;
+    }
+    for ((user, kind), count) in per_user {
+        sessions.push(&[("user", user), ("kind", kind)], count as f64);
+    }
+
+    let mut kernel_events = MetricFamily::new(
+        "watchdog_kernel_events",
+        "Xid, MCE, OOM kill and hung task messages in the kernel log since boot.",
+    );
+    for (kind, count) in &sample.kernel.counts {
+        kernel_events.push(&[("kind", kind)], *count as f64);
+    }
+
+    let mut gpu_info = MetricFamily::new("watchdog_gpu_info", "GPU model and driver version.");
+    let mut gpu_temp = MetricFamily::new(
+        "watchdog_gpu_temperature_celsius",
+        "GPU temperature in celsius.",
+    );
+    let mut gpu_util = MetricFamily::new("watchdog_gpu_utilization_ratio", "GPU utilization.");
+    let mut gpu_mem_util = MetricFamily::new(
+        "watchdog_gpu_memory_utilization_ratio",
+        "GPU memory controller utilization.",
+    );
+    let mut gpu_memory = MetricFamily::new("watchdog_gpu_memory_bytes", "GPU memory in bytes.");
+    let mut gpu_power = MetricFamily::new("watchdog_gpu_power_watts", "GPU power draw and limit.");
+    let mut gpu_clock = MetricFamily::new("watchdog_gpu_clock_mhz", "GPU SM and memory clocks.");
+    let mut gpu_fan = MetricFamily::new("watchdog_gpu_fan_ratio", "GPU fan speed.");
+    let mut gpu_pstate = MetricFamily::new("watchdog_gpu_pstate_info", "GPU performance state.");
+    let mut gpu_pcie_link = MetricFamily::new(
+        "watchdog_gpu_pcie_link",
+        "Current PCIe generation and width.",
+    );
+    let mut gpu_pcie_bytes =
+        MetricFamily::new("watchdog_gpu_pcie_bytes_per_second", "GPU PCIe throughput.");
+    let mut gpu_throttle = MetricFamily::new(
+        "watchdog_gpu_throttle_reason",
+        "Active reasons for lowered GPU clocks.",
+    );
+    let mut gpu_ecc = MetricFamily::new("watchdog_gpu_ecc_errors", "GPU ECC error counts.");
+    for (i, gd) in sample.gpu.details.iter().enumerate() {
+        if gd.name.is_empty() {
+            continue;
+        }
+        let index = i.to_string();
+        if let Some(m) = &gd.metrics {
+            for (kind, value) in [("draw", m.power_draw_watts), ("limit", m.power_limit_watts)] {
+                if let Some(v) = value {
+                    gpu_power.push(&[("gpu", &index), ("kind", kind)], v);
+                }
+            }
+            for (clock, value) in [("sm", m.sm_clock_mhz), ("memory", m.memory_clock_mhz)] {
+                if let Some(v) = value {
+                    gpu_clock.push(&[("gpu", &index), ("clock", clock)], v as f64);
+                }
+            }
+            if let Some(f) = m.fan_percent {
+                gpu_fan.push(&[("gpu", &index)], f / 100.0);
+            }
+            if !m.pstate.is_empty() {
+                gpu_pstate.push(&[("gpu", &index), ("pstate", &m.pstate)], 1.0);
+            }
+            for (kind, value) in [("generation", m.pcie_gen), ("width", m.pcie_width)] {
+                if let Some(v) = value {
+                    gpu_pcie_link.push(&[("gpu", &index), ("kind", kind)], v as f64);
+                }
+            }
+            for (direction, value) in [
+                ("rx", m.pcie_rx_bytes_per_sec),
+                ("tx", m.pcie_tx_bytes_per_sec),
+            ] {
+                if let Some(v) = value {
+                    gpu_pcie_bytes.push(&[("gpu", &index), ("direction", direction)], v);
+                }
+            }
+            for reason in &m.throttle_reasons {
+                gpu_throttle.push(&[("gpu", &index), ("reason", reason)], 1.0);
+            }
+            for (kind, scope, value) in [
+                ("corrected", "volatile", m.ecc_corrected_volatile),
+                ("uncorrected", "volatile", m.ecc_uncorrected_volatile),
+                ("corrected", "aggregate", m.ecc_corrected_aggregate),
+                ("uncorrected", "aggregate", m.ecc_uncorrected_aggregate),
+            ] {
+                if let Some(v) = value {
+                    gpu_ecc.push(
+                        &[("gpu", &index), ("kind", kind), ("scope", scope)],
+                        v as f64,
+                    );
+                }
+            }
+        }
+        gpu_info.push(
+            &[
+                ("gpu", &index),
+                ("name", &gd.name),
+                ("driver_version", &gd.driver_version),
+            ],
+            1.0,
+        );
+        if let Ok(t) = gd.temperature_gpu.trim().parse::<f64>() {
+            gpu_temp.push(&[("gpu", &index)], t);
+        }
+        if let Some(u) = parse_percent(&gd.utilization_gpu) {
+            gpu_util.push(&[("gpu", &index)], u);
+        }
+        if let Some(u) = parse_percent(&gd.utilization_memory) {
+            gpu_mem_util.push(&[("gpu", &index)], u);
+        }
+        for (state, value) in [
+            ("used", &gd.memory_used),
+            ("free", &gd.memory_free),
+            ("total", &gd.memory_total),
+        ] {
+            if let Some(b) = parse_mib(value) {
+                gpu_memory.push(&[("gpu", &index), ("state", state)], b);
+            }
+        }
+    }
+
+    let mut gpu_mig_mode = MetricFamily::new("watchdog_gpu_mig_mode_info", "GPU MIG mode.");
+    for (i, gd) in sample.gpu.details.iter().enumerate() {
+        if let Some(m) = gd.metrics.as_ref().filter(|m| !m.mig_mode.is_empty()) {
+            gpu_mig_mode.push(&[("gpu", &i.to_string()), ("mode", &m.mig_mode)], 1.0);
+        }
+    }
+    let mut gpu_mig_memory = MetricFamily::new(
+        "watchdog_gpu_mig_memory_bytes",
+        "Memory of a MIG device in bytes.",
+    );
+    for m in &sample.gpu.mig {
+        let (gpu, gi, ci) = (
+            m.gpu_index.to_string(),
+            m.gpu_instance.to_string(),
+            m.compute_instance.to_string(),
+        );
+        for (state, value) in [("used", m.memory_used_mib), ("total", m.memory_total_mib)] {
+            if let Some(v) = value {
+                gpu_mig_memory.push(
+                    &[
+                        ("gpu", &gpu),
+                        ("gpu_instance", &gi),
+                        ("compute_instance", &ci),
+                        ("profile", &m.profile),
+                        ("state", state),
+                    ],
+                    v as f64 * 1024.0 * 1024.0,
+                );
+            }
+        }
+    }
+    let mut gpu_link = MetricFamily::new(
+        "watchdog_gpu_link_info",
+        "Link between two GPUs as shown by nvidia-smi topo -m.",
+    );
+    if let Some(t) = &sample.gpu.topology {
+        for (i, links) in t.links.iter().enumerate() {
+            for (j, link) in links.iter().enumerate().filter(|(j, _)| *j != i) {
+                gpu_link.push(
+                    &[
+                        ("gpu", &i.to_string()),
+                        ("peer", &j.to_string()),
+                        ("link", link),
+                    ],
+                    1.0,
+                );
+            }
+        }
+    }
+
+    let mut collector_ok = MetricFamily::new(
+        "watchdog_collector_ok",
+        "Whether the collector succeeded in this sample.",
+    );
+    for (name, status) in &sample.collectors {
+        let ok = matches!(
+            status,
+            CollectorStatus::Ok | CollectorStatus::Disabled | CollectorStatus::Unavailable { .. }
+        );
+        collector_ok.push(&[("collector", name)], if ok { 1.0 } else { 0.0 });
+    }
+
+    let mut output = String::new();
+    for family in [
+        &host_info,
+        &boot_time,
+        &host_label,
+        &collector_ok,
+        &cpu_usage,
+        &cpu_temp,
+        &core_usage,
+        &load_average,
+        &processes,
+        &threads,
+        &context_switches,
+        &memory,
+        &swap,
+        &filesystem,
+        &inodes,
+        &disk_bytes,
+        &disk_ops,
+        &disk_util,
+        &net_info,
+        &net_address,
+        &net_speed,
+        &net_bytes,
+        &net_packets,
+        &net_errors,
+        &net_drops,
+        &sensor_temp,
+        &sensor_fan,
+        &sensor_voltage,
+        &power,
+        &user_cpu,
+        &user_memory,
+        &user_processes,
+        &sessions,
+        &kernel_events,
+        &gpu_info,
+        &gpu_temp,
+        &gpu_util,
+        &gpu_mem_util,
+        &gpu_memory,
+        &gpu_power,
+        &gpu_clock,
+        &gpu_fan,
+        &gpu_pstate,
+        &gpu_pcie_link,
+        &gpu_pcie_bytes,
+        &gpu_throttle,
+        &gpu_ecc,
+        &gpu_mig_mode,
+        &gpu_mig_memory,
+        &gpu_link,
+    ] {
+        family.render(&mut output);
+    }
+    output += "# EOF\n";
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse_nvidia_values() {
+        assert_eq!(parse_percent("36 %"), Some(0.36));
+        assert_eq!(parse_percent("Err"), None);
+        assert_eq!(parse_mib("2 MiB"), Some(2097152.0));
+    }
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
@@ -0,0 +1,188 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+pub const POWERCAP_ROOT: &str = "/sys/class/powercap";
+
+/// rapl energy counters of the previous sample, by zone directory
+static PREVIOUS: Mutex<Option<(BTreeMap<String, u64>, Instant)>> = Mutex::new(None);
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SensorReading {
+    /// driver of the chip, e.g. "coretemp", "k10temp", "nvme"
+    pub chip: String,
+    /// e.g. "Package id 0", "Tctl", falls back to the file name
+    pub label: String,
+    /// celsius, rpm or volt
+    pub value: f64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PowerZone {
+    /// e.g. "intel-rapl:0:1"
+    pub id: String,
+    /// e.g. "package-0", "dram"
+    pub name: String,
+    /// zones inside a package are also counted by the package,
+    /// a top level "psys" zone counts the packages as well
+    pub top_level: bool,
+    pub watts: f64,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Sensors {
+    pub temperatures: Vec<SensorReading>,
+    pub fans: Vec<SensorReading>,
+    pub voltages: Vec<SensorReading>,
+    pub power: Vec<PowerZone>,
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|v| v.trim().to_string())
+}
+
+/// Every `<prefix>N_input` of a hwmon directory with its label, scaled by `divisor`.
+fn read_inputs(dir: &Path, chip: &str, prefix: &str, divisor: f64) -> Vec<SensorReading> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+    let mut readings: Vec<SensorReading> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let file = e.file_name().to_string_lossy().to_string();
+            let stem = file.strip_suffix("_input")?;
+            let index = stem.strip_prefix(prefix)?;
+            if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let raw: f64 = read_trimmed(&e.path())?.parse().ok()?;
+            let label = read_trimmed(&dir.join(format!("{}_label", stem)))
+                .unwrap_or_else(|| stem.to_string());
+            Some(SensorReading {
+                chip: chip.to_string(),
+                label,
+                value: raw / divisor,
+            })
+        })
+        .collect();
+    readings.sort_by(|a, b| a.label.cmp(&b.label));
+    readings
+}
+
+/// Temperatures, fans and voltages of every hwmon chip.
+pub fn read_hwmon(root: &Path) -> Result<Sensors, String> {
+    let entries = fs::read_dir(root).map_err(|e| format!("read {}: {}", root.display(), e))?;
+    let mut dirs: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    dirs.sort();
+    let mut sensors = Sensors::default();
+    for dir in dirs {
+        let chip = read_trimmed(&dir.join("name")).unwrap_or_default();
+        // millidegree, rpm and millivolt
+        sensors
+            .temperatures
+            .extend(read_inputs(&dir, &chip, "temp", 1000.0));
+        sensors.fans.extend(read_inputs(&dir, &chip, "fan", 1.0));
+        sensors
+            .voltages
+            .extend(read_inputs(&dir, &chip, "in", 1000.0));
+    }
+    Ok(sensors)
+}
+
+/// Microjoules used between two readings of a counter that wraps at `max_range`.
+fn energy_used(now: u64, before: u64, max_range: u64) -> u64 {
+    if now >= before {
+        now - before
+    } else {
+        max_range.saturating_sub(before) + now
+    }
+}
+
+/// Average power of every rapl zone since the previous call, empty on the first call.
+pub fn read_rapl(root: &Path) -> Result<Vec<PowerZone>, String> {
+    let entries = fs::read_dir(root).map_err(|e| format!("read {}: {}", root.display(), e))?;
+    let mut zones: Vec<(String, String, u64, u64)> = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let zone = entry.file_name().to_string_lossy().to_string();
+        if !zone.starts_with("intel-rapl:") {
+            continue;
+        }
+        let path = entry.path();
+        // energy_uj is only readable by root on recent kernels
+        let energy = match read_trimmed(&path.join("energy_uj")).and_then(|v| v.parse().ok()) {
+            Some(e) => e,
+            None => continue,
+        };
+        let name = read_trimmed(&path.join("name")).unwrap_or_else(|| zone.clone());
+        let max_range = read_trimmed(&path.join("max_energy_range_uj"))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(u64::MAX);
+        zones.push((zone, name, energy, max_range));
+    }
+    if zones.is_empty() {
+        return Err(String::from("no readable rapl zone"));
+    }
+    zones.sort();
+    let read_at = Instant::now();
+    let mut previous = PREVIOUS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut power = Vec::new();
+    if let Some((before, since)) = previous.as_ref() {
+        let seconds = read_at.duration_since(*since).as_secs_f64();
+        for (zone, name, energy, max_range) in &zones {
+            if let Some(b) = before.get(zone).filter(|_| seconds > 0.0) {
+                power.push(PowerZone {
+                    id: zone.clone(),
+                    name: name.clone(),
+                    // "intel-rapl:0" is a package, "intel-rapl:0:1" a part of it
+                    top_level: zone.matches(':').count() == 1,
+                    watts: energy_used(*energy, *b, *max_range) as f64 / 1e6 / seconds,
+                });
+            }
+        }
+    }
+    *previous = Some((
+        zones.into_iter().map(|(zone, _, e, _)| (zone, e)).collect(),
+        read_at,
+    ));
+    Ok(power)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_read_hwmon() {
+        let root = std::env::temp_dir().join(format!("watchdog-hwmon-{}", std::process::id()));
+        let chip = root.join("hwmon0");
+        fs::create_dir_all(&chip).unwrap();
+        for (file, value) in [
+            ("name", "coretemp"),
+            ("temp1_input", "54000"),
+            ("temp1_label", "Package id 0"),
+            ("temp2_input", "51000"),
+            ("temp2_max", "100000"),
+            ("fan1_input", "1200"),
+            ("in0_input", "1800"),
+        ] {
+            fs::write(chip.join(file), value).unwrap();
+        }
+        let sensors = read_hwmon(&root).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(sensors.temperatures.len(), 2);
+        assert_eq!(sensors.temperatures[0].label, "Package id 0");
+        assert_eq!(sensors.temperatures[0].value, 54.0);
+        assert_eq!(sensors.temperatures[1].label, "temp2");
+        assert_eq!(sensors.fans[0].value, 1200.0);
+        assert_eq!(sensors.voltages[0].value, 1.8);
+    }
+    #[test]
+    fn test_energy_used() {
+        assert_eq!(energy_used(150, 100, 1000), 50);
+        assert_eq!(energy_used(50, 900, 1000), 150);
+    }
+}
@@ -0,0 +1,201 @@
+use log::error;
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::sync::Mutex;
+use std::thread;
+
+use chrono::Local;
+use systemstat::Platform;
+use systemstat::System;
+
+const KMSG: &str = "/dev/kmsg";
+/// latest messages sent with every sample
+const MAX_RECENT: usize = 20;
+
+/// None until `follow` ran, then the events or why /dev/kmsg can not be read
+static STATE: Mutex<Option<Result<KernelEvents, String>>> = Mutex::new(None);
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct KernelMessage {
+    /// "xid", "mce", "oom" or "hung_task"
+    pub kind: String,
+    /// sequence number of the record, restarts at boot
+    pub seq: u64,
+    /// unix time
+    pub time: i64,
+    pub message: String,
+    pub xid: Option<u32>,
+    /// pci address of the gpu of an xid
+    pub pci: Option<String>,
+}
+
+/// Faults seen in the kernel log since boot.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct KernelEvents {
+    /// by kind
+    pub counts: BTreeMap<String, u64>,
+    /// newest last
+    pub recent: Vec<KernelMessage>,
+    /// the latest xid of every code and gpu, a burst of other
+    /// messages must not push a dead card out of `recent`
+    pub xids: Vec<KernelMessage>,
+}
+
+fn classify(message: &str) -> Option<&'static str> {
+    if message.contains("NVRM: Xid") {
+        Some("xid")
+    } else if message.contains("Machine check")
+        || message.starts_with("mce:")
+        || message.contains("[Hardware Error]")
+    {
+        Some("mce")
+    } else if message.contains("out of memory: Killed process")
+        || message.contains("Out of memory: Killed process")
+    {
+        Some("oom")
+    } else if message.contains("blocked for more than") {
+        Some("hung_task")
+    } else {
+        None
+    }
+}
+
+/// "NVRM: Xid (PCI:0000:3b:00): 79, pid=1234, GPU has fallen off the bus." -> (79, "0000:3b:00")
+fn parse_xid(message: &str) -> Option<(u32, String)> {
+    let rest = message.split("NVRM: Xid (PCI:").nth(1)?;
+    let (pci, rest) = rest.split_once("):")?;
+    let code = rest.trim_start().split(',').next()?.trim().parse().ok()?;
+    Some((code, pci.to_string()))
+}
+
+/// "4,1234,5678901,-;text\n SUBSYSTEM=..." -> (seq, usec since boot, text)
+fn parse_record(record: &str) -> Option<(u64, u64, &str)> {
+    let line = record.lines().next()?;
+    let (header, text) = line.split_once(';')?;
+    let mut fields = header.split(',');
+    let _priority = fields.next()?;
+    let seq = fields.next()?.parse().ok()?;
+    let usec = fields.next()?.parse().ok()?;
+    Some((seq, usec, text))
+}
+
+fn record(events: &mut KernelEvents, record: &str, boot_time: i64) {
+    let (seq, usec, text) = match parse_record(record) {
+        Some(r) => r,
+        None => return,
+    };
+    let kind = match classify(text) {
+        Some(k) => k,
+        None => return,
+    };
+    *events.counts.entry(kind.to_string()).or_insert(0) += 1;
+    let xid = parse_xid(text);
+    let message = KernelMessage {
+        kind: kind.to_string(),
+        seq,
+        time: boot_time + (usec / 1_000_000) as i64,
+        message: text.to_string(),
+        xid: xid.as_ref().map(|x| x.0),
+        pci: xid.map(|x| x.1),
+    };
+    if message.xid.is_some() {
+        events
+            .xids
+            .retain(|x| x.xid != message.xid || x.pci != message.pci);
+        events.xids.push(message.clone());
+    }
+    events.recent.push(message);
+    if events.recent.len() > MAX_RECENT {
+        events.recent.remove(0);
+    }
+}
+
+/// Follow the kernel log in a background thread, starting with what is still in the ring buffer.
+pub fn follow() {
+    let mut file = match File::open(KMSG) {
+        Ok(f) => f,
+        Err(e) => {
+            // containers and unprivileged users can not read it
+            error!("open {} error: {}", KMSG, e);
+            *STATE.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some(Err(format!("open {}: {}", KMSG, e)));
+            return;
+        }
+    };
+    let uptime = System::new().uptime().map(|u| u.as_secs()).unwrap_or(0);
+    let boot_time = Local::now().timestamp() - uptime as i64;
+    *STATE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Ok(KernelEvents::default()));
+    info!("following {}", KMSG);
+    thread::spawn(move || {
+        // every read returns exactly one record
+        let mut buf = vec![0u8; 8192];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => return,
+                Ok(n) => {
+                    let text = String::from_utf8_lossy(&buf[..n]);
+                    if let Some(Ok(events)) =
+                        STATE.lock().unwrap_or_else(|e| e.into_inner()).as_mut()
+                    {
+                        record(events, &text, boot_time);
+                    }
+                }
+                // records were overwritten before we read them
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => continue,
+                Err(e) => {
+                    error!("read {} error: {}", KMSG, e);
+                    *STATE.lock().unwrap_or_else(|e| e.into_inner()) =
+                        Some(Err(format!("read {}: {}", KMSG, e)));
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// The faults seen so far, None if `follow` was not called.
+pub fn snapshot() -> Option<Result<KernelEvents, String>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_record() {
+        let mut events = KernelEvents::default();
+        for r in [
+            "4,812,5000000,-;NVRM: Xid (PCI:0000:3b:00): 79, pid=1234, name=python, GPU has fallen off the bus.\n SUBSYSTEM=pci",
+            "6,813,6000000,-;usb 1-1: new high-speed USB device",
+            "3,814,7000000,-;Out of memory: Killed process 4242 (python) total-vm:1kB",
+            "3,815,8000000,-;INFO: task jbd2/sda1-8:512 blocked for more than 120 seconds.",
+            "3,816,9000000,-;mce: [Hardware Error]: Machine check events logged",
+        ] {
+            record(&mut events, r, 1_700_000_000);
+        }
+        assert_eq!(events.recent.len(), 4);
+        assert_eq!(events.recent[0].xid, Some(79));
+        assert_eq!(events.recent[0].pci.as_deref(), Some("0000:3b:00"));
+        assert_eq!(events.recent[0].time, 1_700_000_005);
+        assert_eq!(events.counts["oom"], 1);
+        assert_eq!(events.counts["hung_task"], 1);
+        assert_eq!(events.counts["mce"], 1);
+        for seq in 900..930 {
+            record(
+                &mut events,
+                &format!(
+                    "3,{},9500000,-;Out of memory: Killed process 1 (a) total-vm:1kB",
+                    seq
+                ),
+                1_700_000_000,
+            );
+        }
+        assert!(events.recent.iter().all(|m| m.xid.is_none()));
+        assert_eq!(events.xids.len(), 1);
+        assert_eq!(events.xids[0].seq, 812);
+    }
+}
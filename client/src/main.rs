@@ -3,11 +3,13 @@ use chrono::Local;
 use clap::Parser;
 use log::error;
 use log::info;
-use reqwest;
 use serde::Serialize;
-use serde_json::json;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::path::Path;
 use std::process::Command;
+use std::sync::Arc;
+use std::sync::RwLock;
 use std::thread;
 use std::time::Duration;
 use systemstat::saturating_sub_bytes;
@@ -15,6 +17,32 @@ use systemstat::Platform;
 use systemstat::System;
 use thiserror::Error;
 
+use cpu::CpuDetail;
+use disk::DiskIo;
+use disk::MountFilter;
+use disk::MountUsage;
+use hwmon::Sensors;
+use kernel::KernelEvents;
+use net::Interface;
+use nvidia::GpuMetrics;
+use nvidia::MigInstance;
+use nvidia::ProcessRow;
+use nvidia::Topology;
+use procs::ProcessTop;
+use sessions::Session;
+use workload::Workload;
+
+mod cpu;
+mod disk;
+mod exporter;
+mod hwmon;
+mod kernel;
+mod net;
+mod nvidia;
+mod procs;
+mod sessions;
+mod workload;
+
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("can not exec system command: {cmd}")]
@@ -36,6 +64,41 @@ struct Args {
     /// Upload interval (sec)
     #[clap(long, default_value_t = 60)]
     interval: u64,
+
+    /// Serve the latest sample on /metrics and /sample.json (e.g. 0.0.0.0:9101)
+    #[clap(long)]
+    listen: Option<String>,
+
+    /// Do not push samples to the server, only serve them on --listen
+    #[clap(long)]
+    no_push: bool,
+
+    /// Only report mounts of these filesystem types (comma separated)
+    #[clap(long, value_delimiter = ',')]
+    disk_include_fs: Vec<String>,
+
+    /// Do not report mounts of these filesystem types (comma separated)
+    #[clap(long, value_delimiter = ',', default_value = disk::DEFAULT_EXCLUDE_FS)]
+    disk_exclude_fs: Vec<String>,
+
+    /// Number of processes reported by cpu and by memory, 0 to turn it off
+    #[clap(long, default_value_t = 10)]
+    top_processes: usize,
+
+    /// Host label reported with every sample (e.g. rack=A), can be repeated
+    #[clap(long, value_parser = parse_label)]
+    label: Vec<(String, String)>,
+
+    /// Sample the pcie throughput of the gpus, adds about a second to every sample
+    #[clap(long)]
+    pcie_throughput: bool,
+}
+
+fn parse_label(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((k, v)) if !k.trim().is_empty() => Ok((k.trim().to_string(), v.trim().to_string())),
+        _ => Err(format!("label must be key=value, got: {}", input)),
+    }
 }
 
 struct MasterServerInfo {
@@ -56,7 +119,7 @@ impl MasterServerInfo {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 struct SingleCardDetail {
     name: String,
     driver_version: String,
@@ -66,6 +129,9 @@ struct SingleCardDetail {
     memory_total: String,
     memory_free: String,
     memory_used: String,
+    uuid: String,
+    /// power, clocks, pcie, throttling and ecc
+    metrics: Option<GpuMetrics>,
 }
 
 impl SingleCardDetail {
@@ -79,35 +145,108 @@ impl SingleCardDetail {
             memory_total: String::new(),
             memory_free: String::new(),
             memory_used: String::new(),
+            uuid: String::new(),
+            metrics: None,
         }
     }
 }
 
-#[derive(Serialize)]
+/// A compute process running on one GPU.
+#[derive(Serialize, Clone)]
+struct GpuProcess {
+    /// position of the card in `details`
+    gpu_index: usize,
+    pid: u32,
+    user: String,
+    used_memory: String,
+    cwd: String,
+    /// arguments joined by spaces
+    cmdline: String,
+    /// MIG instance the process runs in, None without MIG
+    gpu_instance: Option<u32>,
+    compute_instance: Option<u32>,
+    /// container or slurm job, where the cwd says little
+    workload: Option<Workload>,
+}
+
+#[derive(Serialize, Clone)]
 struct ServerCardsInfo {
     details: Vec<SingleCardDetail>,
     users: Vec<String>,
+    processes: Vec<GpuProcess>,
+    /// MIG devices of the cards in MIG mode
+    mig: Vec<MigInstance>,
+    /// NVLink and PCIe links between the cards
+    topology: Option<Topology>,
 }
 
 impl ServerCardsInfo {
     fn empty() -> ServerCardsInfo {
-        let detail = SingleCardDetail {
-            name: String::new(),
-            driver_version: String::new(),
-            temperature_gpu: String::new(),
-            utilization_gpu: String::new(),
-            utilization_memory: String::new(),
-            memory_total: String::new(),
-            memory_free: String::new(),
-            memory_used: String::new(),
-        };
         ServerCardsInfo {
-            details: vec![detail],
+            details: vec![SingleCardDetail::empty()],
             users: vec![String::from("null")],
+            processes: Vec::new(),
+            mig: Vec::new(),
+            topology: None,
         }
     }
 }
 
+/// Outcome of one collector in a sample.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum CollectorStatus {
+    Ok,
+    /// turned off by the configuration, e.g. gpu on a cpu server
+    Disabled,
+    /// the source does not exist on this host, e.g. no temperature sensor
+    Unavailable {
+        message: String,
+    },
+    /// nvidia-smi runs but can not talk to the driver
+    DriverFailed {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// One round of collected data, pushed to the server and served by the exporter.
+#[derive(Serialize, Clone)]
+struct Sample {
+    gpu: ServerCardsInfo,
+    hostname: String,
+    net: HashMap<String, String>,
+    interfaces: Vec<Interface>,
+    mem: HashMap<String, String>,
+    swap: HashMap<String, String>,
+    cpu: HashMap<String, f32>,
+    cpu_detail: Option<CpuDetail>,
+    disks: Vec<MountUsage>,
+    disk_io: Vec<DiskIo>,
+    sensors: Sensors,
+    /// xid, mce, oom and hung task messages of the kernel log
+    kernel: KernelEvents,
+    /// busiest processes and users
+    processes: Option<ProcessTop>,
+    /// logged in users and their tmux and screen servers
+    sessions: Vec<Session>,
+    other: HashMap<String, String>,
+    labels: HashMap<String, String>,
+    collectors: BTreeMap<String, CollectorStatus>,
+    uptime_seconds: Option<u64>,
+    /// unix time the host booted at
+    boot_time: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct UpdateRequest<'a> {
+    password: &'a str,
+    #[serde(flatten)]
+    sample: &'a Sample,
+}
+
 fn get_now_time() -> String {
     let local: DateTime<Local> = Local::now();
     let local_str = local.format("%Y-%m-%d %H:%M:%S").to_string();
@@ -140,21 +279,17 @@ fn split_gpu_users(nv_command_output: &str) -> Vec<String> {
     nv_vec = nv_split.collect();
     for nc in nv_vec {
         let nct_0 = nc.trim();
-        if nct_0.len() > 0 {
+        if !nct_0.is_empty() {
             // info_list.push(nct.to_string());
             if nct_0.contains("No running processes found") {
                 gpu_users.push(nct_0.to_lowercase());
                 // gpu_users_vec.push("no running processes found".to_string());
             } else {
-                let nct_0_string = nct_0.to_string();
-                let nct_0_split = nct_0_string.split("N/A");
-                let nct_0_vec: Vec<&str> = nct_0_split.collect();
-                let nct_1 = nct_0_vec[nct_0_vec.len() - 1];
-                let nct_1_string = nct_1.to_string();
-                let nct_1_split = nct_1_string.split("C");
-                let nct_1_vec: Vec<&str> = nct_1_split.collect();
-                let nct_2 = nct_1_vec[0];
-                let pid = nct_2.trim().to_string();
+                // GI and CI are numbers instead of N/A on MIG cards
+                let pid = match nvidia::parse_process_row(nct_0) {
+                    Some(row) => row.pid.to_string(),
+                    None => continue,
+                };
                 // path to script file
                 let pwdx = match command_system_pwdx(pid) {
                     Ok(p) => p,
@@ -171,14 +306,111 @@ fn split_gpu_users(nv_command_output: &str) -> Vec<String> {
     gpu_users
 }
 
+fn command_process_user(pid: u32) -> Result<String, ClientError> {
+    let ps_output = match Command::new("ps")
+        .args(["-o", "user=", "-p", &pid.to_string()])
+        .output()
+    {
+        Ok(p) => p,
+        Err(_) => {
+            return Err(ClientError::ExecSystemCommandError {
+                cmd: String::from("ps"),
+            })
+        }
+    };
+    Ok(String::from_utf8_lossy(&ps_output.stdout)
+        .trim()
+        .to_string())
+}
+
+/// "python train.py --lr 0.1", empty if the process is gone or not readable.
+fn process_cmdline(pid: u32) -> String {
+    match std::fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(c) => String::from_utf8_lossy(&c)
+            .split('\0')
+            .filter(|a| !a.is_empty())
+            .collect::<Vec<&str>>()
+            .join(" "),
+        Err(_) => String::new(),
+    }
+}
+
+fn command_gpu_processes(
+    details: &[SingleCardDetail],
+    rows: &[ProcessRow],
+) -> Result<Vec<GpuProcess>, ClientError> {
+    let cmd = vec![
+        "--query-compute-apps=gpu_uuid,pid,used_memory",
+        "--format=csv,noheader",
+    ];
+    // a failed query must not read as a host without processes
+    let nvidia_smi_output = match Command::new("nvidia-smi").args(cmd).output() {
+        Ok(c) if c.status.success() => c,
+        _ => {
+            return Err(ClientError::ExecSystemCommandError {
+                cmd: String::from("nvidia-smi --query-compute-apps"),
+            })
+        }
+    };
+    // GPU-5f8a3c1e-0f3b-7d2a-9c4e-1b2a3c4d5e6f, 31337, 10240 MiB
+    let nv_output = String::from_utf8_lossy(&nvidia_smi_output.stdout).to_string();
+    let mut processes = Vec::new();
+    for line in nv_output.lines() {
+        let split_line: Vec<&str> = line.split(',').map(|l| l.trim()).collect();
+        if split_line.len() < 3 {
+            continue;
+        }
+        let gpu_index = match details.iter().position(|d| d.uuid == split_line[0]) {
+            Some(i) => i,
+            None => continue,
+        };
+        let pid: u32 = match split_line[1].parse() {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        let user = match command_process_user(pid) {
+            Ok(u) => u,
+            Err(e) => {
+                error!("command_process_user error: {}", e);
+                String::new()
+            }
+        };
+        // "31337: /home/test" -> "/home/test"
+        let cwd = match command_system_pwdx(pid.to_string()) {
+            Ok(p) => match p.split_once(": ") {
+                Some((_, path)) => path.to_string(),
+                None => String::new(),
+            },
+            Err(e) => {
+                error!("command_system_pwdx error: {}", e);
+                String::new()
+            }
+        };
+        // query-compute-apps names the parent card of a MIG device
+        let row = rows.iter().find(|r| r.pid == pid && r.gpu == gpu_index);
+        processes.push(GpuProcess {
+            gpu_index,
+            pid,
+            user,
+            used_memory: split_line[2].to_string(),
+            cwd,
+            cmdline: process_cmdline(pid),
+            gpu_instance: row.and_then(|r| r.gpu_instance),
+            compute_instance: row.and_then(|r| r.compute_instance),
+            workload: workload::of_process(pid),
+        });
+    }
+    Ok(processes)
+}
+
 #[derive(Debug, PartialEq)]
 enum CommandStatus {
     Success,
     Failed,
 }
 
-fn command_gpu_users() -> Result<(Vec<String>, CommandStatus), ClientError> {
-    // return ["no running processes found"] - 0
+/// Output of plain `nvidia-smi`.
+fn command_nvidia_smi() -> Result<String, ClientError> {
     let nvidia_smi_output = match Command::new("nvidia-smi").output() {
         Ok(c) => c,
         Err(_) => {
@@ -187,25 +419,31 @@ fn command_gpu_users() -> Result<(Vec<String>, CommandStatus), ClientError> {
             })
         }
     };
-    let nv_output = String::from_utf8_lossy(&nvidia_smi_output.stdout).to_string();
+    Ok(String::from_utf8_lossy(&nvidia_smi_output.stdout).to_string())
+}
+
+fn gpu_users_of(nv_output: &str) -> (Vec<String>, CommandStatus) {
+    // return ["no running processes found"] - 0
     let (gpu_users, status) =
         if nv_output.contains("Driver Version:") & nv_output.contains("CUDA Version:") {
-            let gpu_users = split_gpu_users(&nv_output);
+            let gpu_users = split_gpu_users(nv_output);
             let status = CommandStatus::Success;
             (gpu_users, status)
         } else {
-            let gpu_users = vec!["driver failed".to_string()];
+            // reported as a collector status, not as a user
+            let gpu_users = Vec::new();
             let status = CommandStatus::Failed;
             (gpu_users, status)
         };
-    Ok((gpu_users, status))
+    (gpu_users, status)
 }
 
-fn gpu_info() -> Result<ServerCardsInfo, ClientError> {
+fn gpu_info(pcie_throughput: bool) -> Result<(ServerCardsInfo, CollectorStatus), ClientError> {
     // get users from nvidia-smi
-    let (users, status) = command_gpu_users()?;
+    let nv_output = command_nvidia_smi()?;
+    let (users, status) = gpu_users_of(&nv_output);
     let card_details = if status == CommandStatus::Success {
-        let cmd = vec!["--query-gpu=name,driver_version,temperature.gpu,utilization.gpu,utilization.memory,memory.total,memory.free,memory.used", "--format=csv,noheader"];
+        let cmd = vec!["--query-gpu=name,driver_version,temperature.gpu,utilization.gpu,utilization.memory,memory.total,memory.free,memory.used,uuid", "--format=csv,noheader"];
         let nvidia_smi_query_output = match Command::new("nvidia-smi").args(cmd).output() {
             Ok(c) => c,
             Err(_) => {
@@ -256,6 +494,10 @@ fn gpu_info() -> Result<ServerCardsInfo, ClientError> {
                 } else {
                     String::from("Err")
                 };
+                let uuid = match split_line.get(8) {
+                    Some(u) => u.trim().to_string(),
+                    None => String::new(),
+                };
                 SingleCardDetail {
                     name,
                     driver_version,
@@ -265,18 +507,82 @@ fn gpu_info() -> Result<ServerCardsInfo, ClientError> {
                     memory_total,
                     memory_free,
                     memory_used,
+                    uuid,
+                    metrics: None,
                 }
             };
             cards_detail.push(gpu_info);
         }
+        match nvidia::gpu_metrics(pcie_throughput) {
+            Ok(metrics) => {
+                for (card, m) in cards_detail.iter_mut().zip(metrics) {
+                    card.metrics = Some(m);
+                }
+            }
+            Err(e) => error!("get gpu metrics error: {}", e),
+        }
         cards_detail
     } else {
         vec![SingleCardDetail::empty()]
     };
-    Ok(ServerCardsInfo {
-        details: card_details,
-        users,
-    })
+    let mut processes_error = None;
+    let processes = if status == CommandStatus::Success {
+        match command_gpu_processes(&card_details, &nvidia::parse_processes(&nv_output)) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("command_gpu_processes error: {}", e);
+                processes_error = Some(e.to_string());
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    let mig_enabled = card_details
+        .iter()
+        .any(|d| matches!(&d.metrics, Some(m) if m.mig_mode == "Enabled"));
+    let mig = if mig_enabled {
+        match nvidia::mig_instances(&nv_output) {
+            Ok(m) => m,
+            Err(e) => {
+                error!("get mig instances error: {}", e);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    // a single card has no links worth asking for
+    let topology = if status == CommandStatus::Success && card_details.len() > 1 {
+        match nvidia::topology(card_details.len()) {
+            Ok(t) => Some(t),
+            Err(e) => {
+                error!("get gpu topology error: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let collector_status = match status {
+        CommandStatus::Success => match processes_error {
+            Some(message) => CollectorStatus::Error { message },
+            None => CollectorStatus::Ok,
+        },
+        CommandStatus::Failed => CollectorStatus::DriverFailed {
+            message: String::from("nvidia-smi can not communicate with the driver"),
+        },
+    };
+    Ok((
+        ServerCardsInfo {
+            details: card_details,
+            users,
+            processes,
+            mig,
+            topology,
+        },
+        collector_status,
+    ))
 }
 
 fn hostname() -> Result<String, ClientError> {
@@ -294,97 +600,120 @@ fn hostname() -> Result<String, ClientError> {
     Ok(info.to_string())
 }
 
-fn net_info() -> HashMap<String, String> {
-    let sys = System::new();
+/// Interfaces and, for older servers, one address per interface.
+fn net_info() -> (HashMap<String, String>, Vec<Interface>, CollectorStatus) {
     let mut net_info_hm: HashMap<String, String> = HashMap::new();
-    match sys.networks() {
-        Ok(netifs) => {
-            for netif in netifs.values() {
-                if netif.addrs.len() > 0 {
-                    let addrs = format!("{:?}", netif.addrs[0].addr);
-                    // println!("{:?}", addrs);
-                    if !addrs.contains("Empty") {
-                        let addrs_strip_1 = match addrs.strip_prefix("V4(") {
-                            Some(a) => a,
-                            _ => match addrs.strip_prefix("V6(") {
-                                Some(b) => b,
-                                _ => "null",
-                            },
-                        };
-                        let addrs_strip_2 = match addrs_strip_1.strip_suffix(")") {
-                            Some(a) => a,
-                            None => "",
-                        };
-                        net_info_hm.insert(netif.name.to_string(), addrs_strip_2.to_string());
-                    }
-                } else {
-                    net_info_hm.insert(netif.name.to_string(), "null".to_string());
-                }
-                // println!("{} {:?}", netif.name, netif.addrs);
-            }
+    let interfaces = match net::interfaces() {
+        Ok(i) => i,
+        Err(x) => {
+            println!("net_info error: {}", x);
+            return (
+                net_info_hm,
+                Vec::new(),
+                CollectorStatus::Error { message: x },
+            );
         }
-        Err(x) => println!("net_info error: {}", x),
+    };
+    for interface in &interfaces {
+        // prefer ipv4, which is what people look for in /info
+        let address = interface
+            .addresses
+            .iter()
+            .find(|a| !a.contains(':'))
+            .or(interface.addresses.first());
+        let address = match address {
+            Some(a) => a.split('/').next().unwrap_or_default().to_string(),
+            None => "null".to_string(),
+        };
+        net_info_hm.insert(interface.name.clone(), address);
     }
-    net_info_hm
+    (net_info_hm, interfaces, CollectorStatus::Ok)
 }
 
-fn mem_info() -> HashMap<String, String> {
+fn mem_info() -> (HashMap<String, String>, CollectorStatus) {
     let sys = System::new();
     let mut mem_info_hm: HashMap<String, String> = HashMap::new();
     match sys.memory() {
         Ok(mem) => {
-            let used_str = format!("{}", saturating_sub_bytes(mem.total, mem.free));
-            let total_str = format!("{}", mem.total);
-            mem_info_hm.insert("used".to_string(), used_str);
-            mem_info_hm.insert("total".to_string(), total_str);
+            let used = saturating_sub_bytes(mem.total, mem.free);
+            mem_info_hm.insert("used".to_string(), format!("{}", used));
+            mem_info_hm.insert("total".to_string(), format!("{}", mem.total));
+            mem_info_hm.insert("used_bytes".to_string(), used.as_u64().to_string());
+            mem_info_hm.insert("total_bytes".to_string(), mem.total.as_u64().to_string());
+        }
+        Err(x) => {
+            println!("mem_info error: {}", x);
+            return (
+                mem_info_hm,
+                CollectorStatus::Error {
+                    message: x.to_string(),
+                },
+            );
         }
-        Err(x) => println!("mem_info error: {}", x),
     }
-    mem_info_hm
+    (mem_info_hm, CollectorStatus::Ok)
 }
 
-fn swap_info() -> HashMap<String, String> {
+fn swap_info() -> (HashMap<String, String>, CollectorStatus) {
     let sys = System::new();
     let mut swap_info_hm: HashMap<String, String> = HashMap::new();
     match sys.swap() {
         Ok(swap) => {
-            let used_str = format!("{}", saturating_sub_bytes(swap.total, swap.free));
-            let total_str = format!("{}", swap.total);
-            swap_info_hm.insert("used".to_string(), used_str);
-            swap_info_hm.insert("total".to_string(), total_str);
+            let used = saturating_sub_bytes(swap.total, swap.free);
+            swap_info_hm.insert("used".to_string(), format!("{}", used));
+            swap_info_hm.insert("total".to_string(), format!("{}", swap.total));
+            swap_info_hm.insert("used_bytes".to_string(), used.as_u64().to_string());
+            swap_info_hm.insert("total_bytes".to_string(), swap.total.as_u64().to_string());
+        }
+        Err(x) => {
+            println!("\nSwap: error: {}", x);
+            return (
+                swap_info_hm,
+                CollectorStatus::Error {
+                    message: x.to_string(),
+                },
+            );
         }
-        Err(x) => println!("\nSwap: error: {}", x),
     }
-    swap_info_hm
+    (swap_info_hm, CollectorStatus::Ok)
 }
 
-fn cpu_info() -> HashMap<String, f32> {
+/// Load, details and temperature, load and temperature each with their own status.
+fn cpu_info() -> (
+    HashMap<String, f32>,
+    Option<CpuDetail>,
+    CollectorStatus,
+    CollectorStatus,
+) {
     let sys = System::new();
     let mut cpu_info_hm: HashMap<String, f32> = HashMap::new();
-    match sys.cpu_load_aggregate() {
-        Ok(cpu) => {
-            thread::sleep(Duration::from_secs(1));
-            match cpu.done() {
-                Ok(cpu) => {
-                    cpu_info_hm.insert("user".to_string(), cpu.user);
-                    cpu_info_hm.insert("nice".to_string(), cpu.nice);
-                    cpu_info_hm.insert("system".to_string(), cpu.system);
-                    cpu_info_hm.insert("interrupt".to_string(), cpu.interrupt);
-                    cpu_info_hm.insert("idle".to_string(), cpu.idle);
-                }
-                Err(e) => error!("get cpu error: {}", e),
-            };
+    let mut cpu_detail = None;
+    let mut load_status = CollectorStatus::Ok;
+    let mut temp_status = CollectorStatus::Ok;
+    match cpu::collect() {
+        Ok((load, detail)) => {
+            cpu_info_hm = load.to_map();
+            cpu_detail = Some(detail);
+        }
+        Err(e) => {
+            error!("get cpu error: {}", e);
+            load_status = CollectorStatus::Error { message: e };
         }
-        Err(x) => println!("cpu_info error: {}", x),
     }
 
     match sys.cpu_temp() {
         Ok(cpu_temp) => {
             cpu_info_hm.insert("temp".to_string(), cpu_temp);
         }
-        Err(x) => println!("cpu_info error: {}", x),
+        Err(x) => {
+            println!("cpu_info error: {}", x);
+            // virtual machines and some boards have no sensor at all
+            temp_status = CollectorStatus::Unavailable {
+                message: x.to_string(),
+            };
+        }
     }
-    cpu_info_hm
+    (cpu_info_hm, cpu_detail, load_status, temp_status)
 }
 
 fn _convert_sec_to_str(input: u64) -> String {
@@ -404,9 +733,10 @@ fn _convert_sec_to_str(input: u64) -> String {
     uptime_str
 }
 
-fn others_info() -> HashMap<String, String> {
+fn others_info() -> (HashMap<String, String>, CollectorStatus) {
     let sys = System::new();
     let mut others_info_hm: HashMap<String, String> = HashMap::new();
+    let mut status = CollectorStatus::Ok;
     match sys.uptime() {
         Ok(uptime) => {
             let uptime_sec = uptime.as_secs();
@@ -414,7 +744,12 @@ fn others_info() -> HashMap<String, String> {
             //println!("{}", uptime_info);
             others_info_hm.insert("uptime".to_string(), uptime_info);
         }
-        Err(x) => println!("uptime error: {}", x),
+        Err(x) => {
+            println!("uptime error: {}", x);
+            status = CollectorStatus::Error {
+                message: x.to_string(),
+            };
+        }
     }
 
     others_info_hm.insert("nowtime".to_string(), get_now_time());
@@ -427,7 +762,172 @@ fn others_info() -> HashMap<String, String> {
         Err(x) => println!("boottime error: {}", x),
     }
     */
-    others_info_hm
+    (others_info_hm, status)
+}
+
+fn disk_info(
+    filter: &MountFilter,
+) -> (
+    Vec<MountUsage>,
+    Vec<DiskIo>,
+    CollectorStatus,
+    CollectorStatus,
+) {
+    let (mounts, mounts_status) = match disk::mounts(filter) {
+        Ok(m) => (m, CollectorStatus::Ok),
+        Err(e) => {
+            error!("get mounts error: {}", e);
+            (Vec::new(), CollectorStatus::Error { message: e })
+        }
+    };
+    let (io, io_status) = match disk::io() {
+        Ok(i) => (i, CollectorStatus::Ok),
+        Err(e) => {
+            error!("get disk io error: {}", e);
+            (Vec::new(), CollectorStatus::Error { message: e })
+        }
+    };
+    (mounts, io, mounts_status, io_status)
+}
+
+/// hwmon sensors and rapl power, each with its own status.
+fn sensors_info() -> (Sensors, CollectorStatus, CollectorStatus) {
+    let (mut sensors, hwmon_status) = match hwmon::read_hwmon(Path::new(hwmon::HWMON_ROOT)) {
+        Ok(s) => (s, CollectorStatus::Ok),
+        // virtual machines have no sensors
+        Err(e) => (
+            Sensors::default(),
+            CollectorStatus::Unavailable { message: e },
+        ),
+    };
+    let rapl_status = match hwmon::read_rapl(Path::new(hwmon::POWERCAP_ROOT)) {
+        Ok(p) => {
+            sensors.power = p;
+            CollectorStatus::Ok
+        }
+        // amd and arm hosts have no rapl
+        Err(e) => CollectorStatus::Unavailable { message: e },
+    };
+    (sensors, hwmon_status, rapl_status)
+}
+
+/// Top processes by cpu and memory and the usage of every user.
+fn processes_info(top_processes: usize) -> (Option<ProcessTop>, CollectorStatus) {
+    if top_processes == 0 {
+        return (None, CollectorStatus::Disabled);
+    }
+    match procs::collect(top_processes) {
+        Ok(t) => (Some(t), CollectorStatus::Ok),
+        Err(e) => {
+            error!("get processes error: {}", e);
+            (None, CollectorStatus::Error { message: e })
+        }
+    }
+}
+
+/// Logins from utmp and the terminal multiplexers that outlive them.
+fn sessions_info() -> (Vec<Session>, CollectorStatus) {
+    let mut sessions = sessions::multiplexers();
+    let status = match sessions::read_utmp(Path::new(sessions::UTMP)) {
+        Ok(s) => {
+            sessions.splice(0..0, s);
+            CollectorStatus::Ok
+        }
+        // containers have no utmp
+        Err(e) => CollectorStatus::Unavailable { message: e },
+    };
+    (sessions, status)
+}
+
+/// Faults the kernel log follower has seen so far.
+fn kernel_info() -> (KernelEvents, CollectorStatus) {
+    match kernel::snapshot() {
+        Some(Ok(k)) => (k, CollectorStatus::Ok),
+        // needs root or CAP_SYSLOG
+        Some(Err(e)) => (
+            KernelEvents::default(),
+            CollectorStatus::Unavailable { message: e },
+        ),
+        None => (KernelEvents::default(), CollectorStatus::Disabled),
+    }
+}
+
+fn collect_sample(
+    gpu_flag: bool,
+    labels: &HashMap<String, String>,
+    mount_filter: &MountFilter,
+    top_processes: usize,
+    pcie_throughput: bool,
+) -> Sample {
+    let hostname = match hostname() {
+        Ok(h) => h,
+        Err(e) => {
+            error!("get hostname error: {}", e);
+            String::new()
+        }
+    };
+    let (gpu_info_result, gpu_status) = match gpu_flag {
+        true => match gpu_info(pcie_throughput) {
+            Ok(g) => g,
+            // jump over error
+            Err(e) => (
+                ServerCardsInfo::empty(),
+                CollectorStatus::Error {
+                    message: e.to_string(),
+                },
+            ),
+        },
+        _ => (ServerCardsInfo::empty(), CollectorStatus::Disabled),
+    };
+    let (net, interfaces, net_status) = net_info();
+    let (mem, mem_status) = mem_info();
+    let (swap, swap_status) = swap_info();
+    let (cpu, cpu_detail, cpu_status, cpu_temp_status) = cpu_info();
+    let (disks, disk_io, disk_status, disk_io_status) = disk_info(mount_filter);
+    let (sensors, hwmon_status, rapl_status) = sensors_info();
+    let (kernel, kernel_status) = kernel_info();
+    let (processes, processes_status) = processes_info(top_processes);
+    let (sessions, sessions_status) = sessions_info();
+    let (other, other_status) = others_info();
+    let uptime_seconds = System::new().uptime().ok().map(|u| u.as_secs());
+    let boot_time = uptime_seconds.map(|u| Local::now().timestamp() - u as i64);
+    let collectors = BTreeMap::from([
+        (String::from("gpu"), gpu_status),
+        (String::from("net"), net_status),
+        (String::from("mem"), mem_status),
+        (String::from("swap"), swap_status),
+        (String::from("cpu"), cpu_status),
+        (String::from("cpu_temp"), cpu_temp_status),
+        (String::from("disk"), disk_status),
+        (String::from("disk_io"), disk_io_status),
+        (String::from("hwmon"), hwmon_status),
+        (String::from("rapl"), rapl_status),
+        (String::from("kernel"), kernel_status),
+        (String::from("processes"), processes_status),
+        (String::from("sessions"), sessions_status),
+        (String::from("uptime"), other_status),
+    ]);
+    Sample {
+        gpu: gpu_info_result,
+        hostname,
+        net,
+        interfaces,
+        mem,
+        swap,
+        cpu,
+        cpu_detail,
+        disks,
+        disk_io,
+        sensors,
+        kernel,
+        processes,
+        sessions,
+        other,
+        labels: labels.clone(),
+        collectors,
+        uptime_seconds,
+        boot_time,
+    }
 }
 
 fn main() {
@@ -438,50 +938,60 @@ fn main() {
         let server_info = MasterServerInfo::new("123456", &args.server_addr);
         let interval = args.interval;
         let sleep_duration = Duration::from_secs(interval);
-        let gpu_flag = match args.server_type.as_str() {
-            "gpu" => true,
-            _ => false,
+        let gpu_flag = args.server_type.as_str() == "gpu";
+        let labels: HashMap<String, String> = args.label.iter().cloned().collect();
+        let mount_filter = MountFilter {
+            include_fs: args.disk_include_fs.clone(),
+            exclude_fs: args.disk_exclude_fs.clone(),
         };
-        loop {
-            let hostname = match hostname() {
-                Ok(h) => h,
-                Err(e) => {
-                    error!("get hostname error: {}", e);
-                    String::new()
+        kernel::follow();
+        let latest: Arc<RwLock<Option<Sample>>> = Arc::new(RwLock::new(None));
+        match &args.listen {
+            Some(listen) => {
+                if let Err(e) = exporter::serve(listen, latest.clone()) {
+                    error!("start exporter on {} error: {}", listen, e);
+                    // without the exporter a --no-push client has nothing left to do
+                    if args.no_push {
+                        std::process::exit(1);
+                    }
                 }
-            };
-            let net_info_result = net_info();
-            let mem_info_result = mem_info();
-            let swap_info_result = swap_info();
-            let cpu_info_result = cpu_info();
-            let other_info_result = others_info();
-
-            let gpu_info_result = match gpu_flag {
-                true => match gpu_info() {
-                    Ok(g) => g,
-                    Err(_) => ServerCardsInfo::empty(), // jump over error
-                },
-                _ => ServerCardsInfo::empty(),
-            };
-            let json_data = json!({
-                "password": server_info.password,
-                "gpu": gpu_info_result,
-                "hostname": hostname,
-                "net": net_info_result,
-                "mem": mem_info_result,
-                "swap": swap_info_result,
-                "cpu": cpu_info_result,
-                "other": other_info_result,
-            });
-            let client = reqwest::blocking::Client::new();
-            let res = client.post(&server_info.serveraddr).json(&json_data).send();
-            match res {
-                Ok(response) => {
-                    if response.status() != 200 {
-                        println!("Send update data error: {}", response.status());
+            }
+            None if args.no_push => {
+                error!("--no-push needs --listen, the samples would go nowhere");
+                std::process::exit(1);
+            }
+            None => {}
+        }
+        loop {
+            let sample = collect_sample(
+                gpu_flag,
+                &labels,
+                &mount_filter,
+                args.top_processes,
+                args.pcie_throughput,
+            );
+            if !args.no_push {
+                let update_request = UpdateRequest {
+                    password: &server_info.password,
+                    sample: &sample,
+                };
+                let client = reqwest::blocking::Client::new();
+                let res = client
+                    .post(&server_info.serveraddr)
+                    .json(&update_request)
+                    .send();
+                match res {
+                    Ok(response) => {
+                        if response.status() != 200 {
+                            println!("Send update data error: {}", response.status());
+                        }
                     }
+                    Err(e) => println!("{}", e),
                 }
-                Err(e) => println!("{}", e),
+            }
+            match latest.write() {
+                Ok(mut l) => *l = Some(sample),
+                Err(e) => error!("update latest sample error: {}", e),
             }
             thread::sleep(sleep_duration);
         }
@@ -498,7 +1008,7 @@ mod tests {
     use super::*;
     #[test]
     fn test_command_gpu_users() {
-        let (ret, status) = command_gpu_users().unwrap();
+        let (ret, status) = gpu_users_of(&command_nvidia_smi().unwrap());
         println!("{:?} - {:?}", ret, status);
     }
 }
@@ -0,0 +1,169 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+use systemstat::IpAddr;
+use systemstat::Platform;
+use systemstat::System;
+
+/// counters under /sys/class/net/<interface>/statistics
+const COUNTERS: [&str; 8] = [
+    "rx_bytes",
+    "tx_bytes",
+    "rx_packets",
+    "tx_packets",
+    "rx_errors",
+    "tx_errors",
+    "rx_dropped",
+    "tx_dropped",
+];
+
+type Counters = [u64; COUNTERS.len()];
+
+/// interface counters of the previous sample
+static PREVIOUS: Mutex<Option<(BTreeMap<String, Counters>, Instant)>> = Mutex::new(None);
+
+/// Traffic per second since the previous sample.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct NetRates {
+    pub rx_bytes: f64,
+    pub tx_bytes: f64,
+    pub rx_packets: f64,
+    pub tx_packets: f64,
+    pub rx_errors: f64,
+    pub tx_errors: f64,
+    pub rx_dropped: f64,
+    pub tx_dropped: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Interface {
+    pub name: String,
+    /// "10.0.0.1/24", "fe80::1/64"
+    pub addresses: Vec<String>,
+    pub mac: String,
+    /// operstate, e.g. "up", "down", "unknown"
+    pub state: String,
+    /// None if the driver does not tell, e.g. virtual interfaces
+    pub speed_mbps: Option<u64>,
+    pub mtu: Option<u64>,
+    /// None on the first sample
+    pub rates: Option<NetRates>,
+}
+
+fn prefix_len(netmask: &IpAddr) -> u32 {
+    match netmask {
+        IpAddr::V4(m) => u32::from(*m).count_ones(),
+        IpAddr::V6(m) => u128::from(*m).count_ones(),
+        _ => 0,
+    }
+}
+
+fn format_address(addr: &IpAddr, netmask: &IpAddr) -> Option<String> {
+    match addr {
+        IpAddr::V4(a) => Some(format!("{}/{}", a, prefix_len(netmask))),
+        IpAddr::V6(a) => Some(format!("{}/{}", a, prefix_len(netmask))),
+        _ => None,
+    }
+}
+
+fn read_sys(interface: &str, file: &str) -> Option<String> {
+    let path = Path::new("/sys/class/net").join(interface).join(file);
+    fs::read_to_string(path).ok().map(|v| v.trim().to_string())
+}
+
+fn read_counters(interface: &str) -> Counters {
+    let mut counters = [0; COUNTERS.len()];
+    for (value, name) in counters.iter_mut().zip(COUNTERS) {
+        *value = read_sys(interface, &format!("statistics/{}", name))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+    }
+    counters
+}
+
+fn rates_since(now: &Counters, before: &Counters, seconds: f64) -> NetRates {
+    let rate = |i: usize| now[i].saturating_sub(before[i]) as f64 / seconds;
+    NetRates {
+        rx_bytes: rate(0),
+        tx_bytes: rate(1),
+        rx_packets: rate(2),
+        tx_packets: rate(3),
+        rx_errors: rate(4),
+        tx_errors: rate(5),
+        rx_dropped: rate(6),
+        tx_dropped: rate(7),
+    }
+}
+
+/// All interfaces with their addresses and the traffic since the previous call.
+pub fn interfaces() -> Result<Vec<Interface>, String> {
+    let sys = System::new();
+    let networks = sys.networks().map_err(|e| e.to_string())?;
+    let counters: BTreeMap<String, Counters> = networks
+        .keys()
+        .map(|name| (name.clone(), read_counters(name)))
+        .collect();
+    let read_at = Instant::now();
+    let mut previous = PREVIOUS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut interfaces = Vec::new();
+    for network in networks.values() {
+        let name = &network.name;
+        let rates = match (previous.as_ref(), counters.get(name)) {
+            (Some((before, since)), Some(now)) if read_at > *since => before
+                .get(name)
+                .map(|b| rates_since(now, b, read_at.duration_since(*since).as_secs_f64())),
+            _ => None,
+        };
+        interfaces.push(Interface {
+            name: name.clone(),
+            addresses: network
+                .addrs
+                .iter()
+                .filter_map(|a| format_address(&a.addr, &a.netmask))
+                .collect(),
+            mac: read_sys(name, "address").unwrap_or_default(),
+            state: read_sys(name, "operstate").unwrap_or_default(),
+            // virtual interfaces report -1
+            speed_mbps: read_sys(name, "speed").and_then(|v| v.parse().ok()),
+            mtu: read_sys(name, "mtu").and_then(|v| v.parse().ok()),
+            rates,
+        });
+    }
+    *previous = Some((counters, read_at));
+    Ok(interfaces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::net::Ipv6Addr;
+    #[test]
+    fn test_format_address() {
+        assert_eq!(
+            format_address(
+                &IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                &IpAddr::V4(Ipv4Addr::new(255, 255, 255, 0))
+            ),
+            Some(String::from("10.0.0.1/24"))
+        );
+        let mask = IpAddr::V6(Ipv6Addr::new(0xffff, 0xffff, 0xffff, 0xffff, 0, 0, 0, 0));
+        assert_eq!(
+            format_address(&IpAddr::V6(Ipv6Addr::LOCALHOST), &mask),
+            Some(String::from("::1/64"))
+        );
+        assert_eq!(format_address(&IpAddr::Empty, &IpAddr::Empty), None);
+    }
+    #[test]
+    fn test_rates_since() {
+        let before = [100, 0, 10, 0, 0, 0, 0, 0];
+        let now = [300, 50, 30, 5, 0, 0, 2, 0];
+        let rates = rates_since(&now, &before, 2.0);
+        assert_eq!(rates.rx_bytes, 100.0);
+        assert_eq!(rates.tx_packets, 2.5);
+        assert_eq!(rates.rx_dropped, 1.0);
+    }
+}
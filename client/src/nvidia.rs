@@ -0,0 +1,470 @@
+use serde::Serialize;
+use std::process::Command;
+use std::sync::Mutex;
+
+use crate::ClientError;
+
+/// asked in one query, a field the driver does not know fails all of it
+const QUERY_FIELDS: &str = "power.draw,power.limit,clocks.sm,clocks.mem,fan.speed,pstate,pcie.link.gen.current,pcie.link.width.current,clocks_throttle_reasons.active,ecc.errors.corrected.volatile.total,ecc.errors.uncorrected.volatile.total,ecc.errors.corrected.aggregate.total,ecc.errors.uncorrected.aggregate.total";
+
+/// process types in the process table of `nvidia-smi`
+const PROCESS_TYPES: [&str; 4] = ["C", "G", "C+G", "M"];
+
+/// the topology only changes with the hardware, kept until the card count changes
+static TOPOLOGY: Mutex<Option<Topology>> = Mutex::new(None);
+
+/// bits of clocks_throttle_reasons.active
+const THROTTLE_REASONS: [(u64, &str); 9] = [
+    (0x1, "gpu_idle"),
+    (0x2, "applications_clocks_setting"),
+    (0x4, "sw_power_cap"),
+    (0x8, "hw_slowdown"),
+    (0x10, "sync_boost"),
+    (0x20, "sw_thermal_slowdown"),
+    (0x40, "hw_thermal_slowdown"),
+    (0x80, "hw_power_brake_slowdown"),
+    (0x100, "display_clock_setting"),
+];
+
+/// Metrics of one card beyond the basic query, None where the card does not support it.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct GpuMetrics {
+    pub power_draw_watts: Option<f64>,
+    pub power_limit_watts: Option<f64>,
+    pub sm_clock_mhz: Option<u64>,
+    pub memory_clock_mhz: Option<u64>,
+    pub fan_percent: Option<f64>,
+    /// "P0" is full speed, "P8" idle
+    pub pstate: String,
+    pub pcie_gen: Option<u64>,
+    pub pcie_width: Option<u64>,
+    pub pcie_rx_bytes_per_sec: Option<f64>,
+    pub pcie_tx_bytes_per_sec: Option<f64>,
+    /// why the clocks are lowered right now, e.g. "hw_thermal_slowdown"
+    pub throttle_reasons: Vec<String>,
+    pub ecc_corrected_volatile: Option<u64>,
+    pub ecc_uncorrected_volatile: Option<u64>,
+    pub ecc_corrected_aggregate: Option<u64>,
+    pub ecc_uncorrected_aggregate: Option<u64>,
+    /// "Enabled" or "Disabled", empty on cards without MIG
+    pub mig_mode: String,
+}
+
+/// A MIG device, a compute instance inside a gpu instance.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct MigInstance {
+    /// position of the parent card in `details`
+    pub gpu_index: usize,
+    pub gpu_instance: u32,
+    pub compute_instance: u32,
+    /// index of the MIG device on its card
+    pub device: u32,
+    /// e.g. "3g.20gb"
+    pub profile: String,
+    /// "MIG-...", usable in CUDA_VISIBLE_DEVICES
+    pub uuid: String,
+    pub memory_used_mib: Option<u64>,
+    pub memory_total_mib: Option<u64>,
+}
+
+/// How the cards are connected, from `nvidia-smi topo -m`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Topology {
+    /// links[i][j] between card i and j: "X", "NV<n>", "PIX", "PXB", "PHB", "NODE" or "SYS"
+    pub links: Vec<Vec<String>>,
+    /// cpus close to each card, e.g. "0-31"
+    pub cpu_affinity: Vec<String>,
+    pub numa_affinity: Vec<String>,
+}
+
+/// A row of the process table of `nvidia-smi`.
+#[derive(Debug, PartialEq)]
+pub struct ProcessRow {
+    pub gpu: usize,
+    /// None unless the card is in MIG mode
+    pub gpu_instance: Option<u32>,
+    pub compute_instance: Option<u32>,
+    pub pid: u32,
+}
+
+/// "250.31 W" -> 250.31, "[N/A]" and "[Not Supported]" -> None
+fn number<T: std::str::FromStr>(field: &str) -> Option<T> {
+    field.split_whitespace().next()?.parse().ok()
+}
+
+fn throttle_reasons(field: &str) -> Vec<String> {
+    let mask = match u64::from_str_radix(field.trim().trim_start_matches("0x"), 16) {
+        Ok(m) => m,
+        Err(_) => return Vec::new(),
+    };
+    THROTTLE_REASONS
+        .iter()
+        .filter(|(bit, _)| mask & bit != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+fn parse_line(line: &str) -> GpuMetrics {
+    let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+    let field = |i: usize| -> &str { fields.get(i).copied().unwrap_or_default() };
+    GpuMetrics {
+        power_draw_watts: number(field(0)),
+        power_limit_watts: number(field(1)),
+        sm_clock_mhz: number(field(2)),
+        memory_clock_mhz: number(field(3)),
+        fan_percent: number(field(4)),
+        pstate: field(5).to_string(),
+        pcie_gen: number(field(6)),
+        pcie_width: number(field(7)),
+        pcie_rx_bytes_per_sec: None,
+        pcie_tx_bytes_per_sec: None,
+        throttle_reasons: throttle_reasons(field(8)),
+        ecc_corrected_volatile: number(field(9)),
+        ecc_uncorrected_volatile: number(field(10)),
+        ecc_corrected_aggregate: number(field(11)),
+        ecc_uncorrected_aggregate: number(field(12)),
+        mig_mode: String::new(),
+    }
+}
+
+/// "|    0    1    0      12345      C   python      1000MiB |", GI and CI are "N/A" without MIG
+/// and missing on drivers before MIG.
+pub fn parse_process_row(row: &str) -> Option<ProcessRow> {
+    let tokens: Vec<&str> = row
+        .trim_matches(|c: char| c == '|' || c.is_whitespace())
+        .split_whitespace()
+        .collect();
+    let type_index = tokens.iter().position(|t| PROCESS_TYPES.contains(t))?;
+    let pid = tokens.get(type_index.checked_sub(1)?)?.parse().ok()?;
+    let gpu = tokens.first()?.parse().ok()?;
+    let (gpu_instance, compute_instance) = match type_index {
+        4 => (tokens[1].parse().ok(), tokens[2].parse().ok()),
+        _ => (None, None),
+    };
+    Some(ProcessRow {
+        gpu,
+        gpu_instance,
+        compute_instance,
+        pid,
+    })
+}
+
+/// Rows of the process table in the output of `nvidia-smi`.
+pub fn parse_processes(smi_output: &str) -> Vec<ProcessRow> {
+    match smi_output.split_once("Processes:") {
+        Some((_, table)) => table.lines().filter_map(parse_process_row).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// "|  0    1   0   0  |     13MiB / 20096MiB | ..." rows of the MIG devices table of `nvidia-smi`.
+fn parse_mig_devices(smi_output: &str) -> Vec<MigInstance> {
+    let table = match smi_output.split_once("MIG devices:") {
+        Some((_, rest)) => rest.split("Processes:").next().unwrap_or_default(),
+        None => return Vec::new(),
+    };
+    table
+        .lines()
+        .filter_map(|line| {
+            let cells: Vec<&str> = line.split('|').map(|c| c.trim()).collect();
+            let ids: Vec<u32> = cells
+                .get(1)?
+                .split_whitespace()
+                .map(|t| t.parse().ok())
+                .collect::<Option<Vec<u32>>>()?;
+            if ids.len() != 4 {
+                return None;
+            }
+            let memory = cells.get(2).and_then(|c| c.split_once('/'));
+            let mib = |m: &str| number(m.trim().trim_end_matches("MiB"));
+            Some(MigInstance {
+                gpu_index: ids[0] as usize,
+                gpu_instance: ids[1],
+                compute_instance: ids[2],
+                device: ids[3],
+                memory_used_mib: memory.and_then(|(u, _)| mib(u)),
+                memory_total_mib: memory.and_then(|(_, t)| mib(t)),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// `nvidia-smi -L` -> (gpu, device, profile, uuid) of every MIG device
+fn parse_mig_list(output: &str) -> Vec<(usize, u32, String, String)> {
+    let mut gpu = 0;
+    let mut devices = Vec::new();
+    for line in output.lines().map(|l| l.trim()) {
+        if let Some(rest) = line.strip_prefix("GPU ") {
+            gpu = number(rest.split(':').next().unwrap_or_default()).unwrap_or(gpu);
+        } else if let Some(rest) = line.strip_prefix("MIG ") {
+            // "MIG 3g.20gb     Device  0: (UUID: MIG-...)"
+            let profile = rest.split_whitespace().next().unwrap_or_default();
+            let device = rest
+                .split("Device")
+                .nth(1)
+                .and_then(|d| number(d.split(':').next().unwrap_or_default()));
+            let uuid = rest
+                .split("UUID: ")
+                .nth(1)
+                .map(|u| u.trim_end_matches(')').to_string());
+            if let (Some(device), Some(uuid)) = (device, uuid) {
+                devices.push((gpu, device, profile.to_string(), uuid));
+            }
+        }
+    }
+    devices
+}
+
+/// MIG devices of every card in MIG mode, `smi_output` is the output of plain `nvidia-smi`.
+pub fn mig_instances(smi_output: &str) -> Result<Vec<MigInstance>, ClientError> {
+    let output = Command::new("nvidia-smi").arg("-L").output().map_err(|_| {
+        ClientError::ExecSystemCommandError {
+            cmd: String::from("nvidia-smi -L"),
+        }
+    })?;
+    let list = parse_mig_list(&String::from_utf8_lossy(&output.stdout));
+    let mut instances = parse_mig_devices(smi_output);
+    for instance in instances.iter_mut() {
+        if let Some((_, _, profile, uuid)) = list
+            .iter()
+            .find(|(g, d, _, _)| *g == instance.gpu_index && *d == instance.device)
+        {
+            instance.profile = profile.clone();
+            instance.uuid = uuid.clone();
+        }
+    }
+    Ok(instances)
+}
+
+/// Drop the color codes newer drivers put into `nvidia-smi topo -m`.
+fn strip_escapes(input: &str) -> String {
+    let mut output = String::new();
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // "\x1b[4m"
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            output.push(c);
+        }
+    }
+    output
+}
+
+fn is_gpu_name(name: &str) -> bool {
+    name.strip_prefix("GPU")
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn parse_topology(output: &str) -> Topology {
+    let output = strip_escapes(output);
+    let mut lines = output.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<&str> = match lines.next() {
+        Some(h) => h.split('\t').map(|c| c.trim()).collect(),
+        None => return Topology::default(),
+    };
+    let gpu_columns: Vec<usize> = (0..header.len())
+        .filter(|i| is_gpu_name(header[*i]))
+        .collect();
+    let column = |name: &str| header.iter().position(|c| *c == name);
+    let (cpu_column, numa_column) = (column("CPU Affinity"), column("NUMA Affinity"));
+    let mut topology = Topology::default();
+    for line in lines {
+        let cells: Vec<&str> = line.split('\t').map(|c| c.trim()).collect();
+        if !cells.first().is_some_and(|c| is_gpu_name(c)) {
+            continue;
+        }
+        let cell = |i: Option<usize>| -> String {
+            i.and_then(|i| cells.get(i))
+                .map(|c| c.to_string())
+                .unwrap_or_default()
+        };
+        topology
+            .links
+            .push(gpu_columns.iter().map(|i| cell(Some(*i))).collect());
+        topology.cpu_affinity.push(cell(cpu_column));
+        topology.numa_affinity.push(cell(numa_column));
+    }
+    topology
+}
+
+/// Links between the cards, asked again only when the card count changed.
+pub fn topology(card_count: usize) -> Result<Topology, ClientError> {
+    let mut cached = TOPOLOGY.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(t) = cached.as_ref().filter(|t| t.links.len() == card_count) {
+        return Ok(t.clone());
+    }
+    let output = Command::new("nvidia-smi")
+        .args(["topo", "-m"])
+        .output()
+        .map_err(|_| ClientError::ExecSystemCommandError {
+            cmd: String::from("nvidia-smi topo -m"),
+        })?;
+    let topology = parse_topology(&String::from_utf8_lossy(&output.stdout));
+    *cached = Some(topology.clone());
+    Ok(topology)
+}
+
+/// `nvidia-smi dmon -s t` lines, "    0     12     34" -> (gpu, rx, tx) in MB/s
+fn parse_dmon(output: &str) -> Vec<(usize, f64, f64)> {
+    output
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .filter_map(|l| {
+            let fields: Vec<&str> = l.split_whitespace().collect();
+            Some((
+                fields.first()?.parse().ok()?,
+                fields.get(1)?.parse().ok()?,
+                fields.get(2)?.parse().ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// Extended metrics of every card in the order of the basic query,
+/// `pcie_throughput` samples the pcie traffic as well, which takes about a second.
+pub fn gpu_metrics(pcie_throughput: bool) -> Result<Vec<GpuMetrics>, ClientError> {
+    let query = format!("--query-gpu={}", QUERY_FIELDS);
+    let output = Command::new("nvidia-smi")
+        .args([query.as_str(), "--format=csv,noheader"])
+        .output()
+        .ok()
+        // the error message of a rejected field would be parsed as the first card
+        .filter(|o| o.status.success())
+        .ok_or_else(|| ClientError::ExecSystemCommandError {
+            cmd: String::from("nvidia-smi --query-gpu"),
+        })?;
+    let mut metrics: Vec<GpuMetrics> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(parse_line)
+        .collect();
+    // pcie throughput is only sampled by dmon, which takes about a second
+    let dmon = match pcie_throughput {
+        true => Command::new("nvidia-smi")
+            .args(["dmon", "-c", "1", "-s", "t"])
+            .output()
+            .ok()
+            .filter(|o| o.status.success()),
+        false => None,
+    };
+    if let Some(dmon) = dmon {
+        for (gpu, rx, tx) in parse_dmon(&String::from_utf8_lossy(&dmon.stdout)) {
+            if let Some(m) = metrics.get_mut(gpu) {
+                m.pcie_rx_bytes_per_sec = Some(rx * 1e6);
+                m.pcie_tx_bytes_per_sec = Some(tx * 1e6);
+            }
+        }
+    }
+    // asked on its own, drivers before MIG reject the field and would fail the query above
+    if let Some(mig) = Command::new("nvidia-smi")
+        .args(["--query-gpu=mig.mode.current", "--format=csv,noheader"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+    {
+        for (m, mode) in metrics
+            .iter_mut()
+            .zip(String::from_utf8_lossy(&mig.stdout).lines())
+        {
+            if mode.trim() == "Enabled" || mode.trim() == "Disabled" {
+                m.mig_mode = mode.trim().to_string();
+            }
+        }
+    }
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse_line() {
+        let m = parse_line("250.31 W, 300.00 W, 1410 MHz, 1215 MHz, 45 %, P0, 4, 16, 0x0000000000000044, 0, 2, 10, 2");
+        assert_eq!(m.power_draw_watts, Some(250.31));
+        assert_eq!(m.sm_clock_mhz, Some(1410));
+        assert_eq!(m.pstate, "P0");
+        assert_eq!(
+            m.throttle_reasons,
+            vec!["sw_power_cap", "hw_thermal_slowdown"]
+        );
+        assert_eq!(m.ecc_uncorrected_volatile, Some(2));
+        let consumer = parse_line("30.12 W, 350.00 W, 210 MHz, 405 MHz, [N/A], P8, 1, 16, 0x0000000000000001, [N/A], [N/A], [N/A], [N/A]");
+        assert_eq!(consumer.fan_percent, None);
+        assert_eq!(consumer.ecc_corrected_volatile, None);
+        assert_eq!(consumer.throttle_reasons, vec!["gpu_idle"]);
+    }
+    #[test]
+    fn test_parse_dmon() {
+        let output = "# gpu  rxpci  txpci \n# Idx   MB/s   MB/s \n    0     12     34 \n    1      -      - \n";
+        assert_eq!(parse_dmon(output), vec![(0, 12.0, 34.0)]);
+    }
+    #[test]
+    fn test_parse_mig() {
+        let smi = "\
+| MIG devices:                                                                          |
++------------------+----------------------------------+-----------+-----------------------+
+| GPU  GI  CI  MIG |                     Memory-Usage |        Vol|      Shared           |
+|      ID  ID  Dev |                       BAR1-Usage | SM     Unc| CE ENC DEC OFA JPG    |
+|==================+==================================+===========+=======================|
+|  0    1   0   0  |            1500MiB / 19968MiB    | 42      0 |  3   0    2    0    0 |
+|                  |                 0MiB / 32767MiB  |           |                       |
++------------------+----------------------------------+-----------+-----------------------+
+|  0    5   0   1  |              13MiB /  4864MiB    | 14      0 |  1   0    0    0    0 |
++------------------+----------------------------------+-----------+-----------------------+
+| Processes:                                                                            |
+|  GPU   GI   CI        PID   Type   Process name                            GPU Memory |
+|        ID   ID                                                             Usage      |
+|=======================================================================================|
+|    0    1    0      31337      C   python                                     1486MiB |
++---------------------------------------------------------------------------------------+
+";
+        let list = "\
+GPU 0: NVIDIA A100-SXM4-40GB (UUID: GPU-5d5ba0d6-d33d-2b2c-524d-9e3d8d2b8a8a)
+  MIG 3g.20gb     Device  0: (UUID: MIG-2aa5bd6c-a1b4-5bc0-8d0f-5e0b6a3b1c2d)
+  MIG 1g.5gb      Device  1: (UUID: MIG-0b6b1f9e-3c4d-5e6f-7a8b-9c0d1e2f3a4b)
+GPU 1: NVIDIA A100-SXM4-40GB (UUID: GPU-7e8f9a0b-1c2d-3e4f-5a6b-7c8d9e0f1a2b)
+";
+        let devices = parse_mig_devices(smi);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[1].gpu_instance, 5);
+        assert_eq!(devices[0].memory_used_mib, Some(1500));
+        assert_eq!(devices[1].memory_total_mib, Some(4864));
+        let list = parse_mig_list(list);
+        assert_eq!(list[1].0, 0);
+        assert_eq!(list[1].2, "1g.5gb");
+        assert_eq!(list[0].3, "MIG-2aa5bd6c-a1b4-5bc0-8d0f-5e0b6a3b1c2d");
+        assert_eq!(
+            parse_processes(smi),
+            vec![ProcessRow {
+                gpu: 0,
+                gpu_instance: Some(1),
+                compute_instance: Some(0),
+                pid: 31337
+            }]
+        );
+        let plain =
+            parse_process_row("|    1   N/A  N/A      4242      C   python    1000MiB |").unwrap();
+        assert_eq!((plain.gpu, plain.gpu_instance, plain.pid), (1, None, 4242));
+    }
+    #[test]
+    fn test_parse_topology() {
+        let output =
+            "\t\x1b[4mGPU0\tGPU1\tGPU2\tNIC0\tCPU Affinity\tNUMA Affinity\tGPU NUMA ID\x1b[0m\n\
+GPU0\t X \tNV12\tSYS\tPXB\t0-31\t0\t\tN/A\n\
+GPU1\tNV12\t X \tSYS\tSYS\t0-31\t0\t\tN/A\n\
+GPU2\tSYS\tSYS\t X \tSYS\t32-63\t1\t\tN/A\n\
+NIC0\tPXB\tSYS\tSYS\t X \n\n\
+Legend:\n\n  X    = Self\n";
+        let topology = parse_topology(output);
+        assert_eq!(topology.links.len(), 3);
+        assert_eq!(topology.links[0], vec!["X", "NV12", "SYS"]);
+        assert_eq!(topology.cpu_affinity, vec!["0-31", "0-31", "32-63"]);
+        assert_eq!(topology.numa_affinity[2], "1");
+    }
+}
@@ -0,0 +1,299 @@
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::workload;
+use crate::workload::Workload;
+
+/// clock ticks of /proc/<pid>/stat, 100 on every architecture linux runs on
+const USER_HZ: f64 = 100.0;
+/// the first sample has nothing to compare with, measure over this instead
+const FIRST_SAMPLE_WINDOW: Duration = Duration::from_millis(250);
+/// longest command line sent
+const MAX_COMMAND: usize = 200;
+
+/// cpu ticks by pid and start time, a reused pid has another start time
+type Ticks = HashMap<(u32, u64), u64>;
+
+/// cpu ticks of every process in the previous sample
+static PREVIOUS: Mutex<Option<(Ticks, Instant)>> = Mutex::new(None);
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    pub user: String,
+    /// command line, the name of the process if it has none
+    pub command: String,
+    /// of one core, 400 is four cores busy
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+    pub runtime_seconds: u64,
+    pub workload: Option<Workload>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct UserUsage {
+    pub user: String,
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+    pub processes: u64,
+}
+
+/// The busiest processes and users of the host.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ProcessTop {
+    pub top_cpu: Vec<ProcessUsage>,
+    pub top_memory: Vec<ProcessUsage>,
+    /// every user, most cpu first
+    pub users: Vec<UserUsage>,
+}
+
+/// One process as read from /proc.
+struct ProcessStat {
+    pid: u32,
+    name: String,
+    /// user and system ticks
+    ticks: u64,
+    /// ticks after boot
+    start_time: u64,
+    uid: Option<u32>,
+    rss_bytes: u64,
+}
+
+/// "1234 (my proc) S 1 ..." -> (name, utime + stime, starttime), the name may contain anything
+fn parse_stat(text: &str) -> Option<(String, u64, u64)> {
+    let (head, rest) = text.rsplit_once(')')?;
+    let name = head.split_once('(')?.1.to_string();
+    // rest starts at field 3, utime and stime are fields 14 and 15, starttime 22
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let field = |i: usize| -> Option<u64> { fields.get(i - 3)?.parse().ok() };
+    Some((name, field(14)? + field(15)?, field(22)?))
+}
+
+/// "Uid:\t1000\t1000\t1000\t1000" and "VmRSS:\t  2048 kB" of /proc/<pid>/status
+fn parse_status(text: &str) -> (Option<u32>, u64) {
+    let mut uid = None;
+    let mut rss_bytes = 0;
+    for line in text.lines() {
+        if let Some(v) = line.strip_prefix("Uid:") {
+            uid = v.split_whitespace().next().and_then(|u| u.parse().ok());
+        } else if let Some(v) = line.strip_prefix("VmRSS:") {
+            let kib: u64 = v
+                .split_whitespace()
+                .next()
+                .and_then(|k| k.parse().ok())
+                .unwrap_or(0);
+            rss_bytes = kib * 1024;
+        }
+    }
+    (uid, rss_bytes)
+}
+
+/// uid -> name from /etc/passwd
+fn parse_passwd(text: &str) -> HashMap<u32, String> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            Some((fields.get(2)?.parse().ok()?, fields.first()?.to_string()))
+        })
+        .collect()
+}
+
+/// uid -> name of the local users
+pub fn user_names() -> HashMap<u32, String> {
+    fs::read_to_string("/etc/passwd")
+        .map(|p| parse_passwd(&p))
+        .unwrap_or_default()
+}
+
+fn read_processes() -> Result<Vec<ProcessStat>, String> {
+    let entries = fs::read_dir("/proc").map_err(|e| format!("read /proc: {}", e))?;
+    let mut processes = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let pid: u32 = match entry.file_name().to_string_lossy().parse() {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        // the process may end between the two reads
+        let stat = match fs::read_to_string(entry.path().join("stat"))
+            .ok()
+            .and_then(|s| parse_stat(&s))
+        {
+            Some(s) => s,
+            None => continue,
+        };
+        let (uid, rss_bytes) = match fs::read_to_string(entry.path().join("status")) {
+            Ok(s) => parse_status(&s),
+            Err(_) => continue,
+        };
+        processes.push(ProcessStat {
+            pid,
+            name: stat.0,
+            ticks: stat.1,
+            start_time: stat.2,
+            uid,
+            rss_bytes,
+        });
+    }
+    Ok(processes)
+}
+
+fn command_line(pid: u32, name: &str) -> String {
+    let command = match fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(c) => String::from_utf8_lossy(&c)
+            .split('\0')
+            .filter(|a| !a.is_empty())
+            .collect::<Vec<&str>>()
+            .join(" "),
+        Err(_) => String::new(),
+    };
+    // kernel threads have no command line
+    if command.is_empty() {
+        return format!("[{}]", name);
+    }
+    command.chars().take(MAX_COMMAND).collect()
+}
+
+/// The `count` busiest processes by cpu and by memory, and the usage of every user.
+fn summarize(processes: Vec<ProcessUsage>, count: usize) -> ProcessTop {
+    let mut users: BTreeMap<&str, UserUsage> = BTreeMap::new();
+    for p in &processes {
+        let user = users.entry(&p.user).or_insert_with(|| UserUsage {
+            user: p.user.clone(),
+            ..Default::default()
+        });
+        user.cpu_percent += p.cpu_percent;
+        user.rss_bytes += p.rss_bytes;
+        user.processes += 1;
+    }
+    let mut users: Vec<UserUsage> = users.into_values().collect();
+    users.sort_by(|a, b| {
+        b.cpu_percent
+            .total_cmp(&a.cpu_percent)
+            .then(b.rss_bytes.cmp(&a.rss_bytes))
+    });
+    let mut by_cpu = processes.clone();
+    by_cpu.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent));
+    by_cpu.truncate(count);
+    let mut by_memory = processes;
+    by_memory.sort_by_key(|p| Reverse(p.rss_bytes));
+    by_memory.truncate(count);
+    ProcessTop {
+        top_cpu: by_cpu,
+        top_memory: by_memory,
+        users,
+    }
+}
+
+/// Top `count` processes and per user usage since the previous call.
+pub fn collect(count: usize) -> Result<ProcessTop, String> {
+    let mut previous = PREVIOUS.lock().unwrap_or_else(|e| e.into_inner());
+    let (before, since) = match previous.take() {
+        Some(p) => p,
+        None => {
+            let first = read_processes()?
+                .into_iter()
+                .map(|p| ((p.pid, p.start_time), p.ticks))
+                .collect();
+            let first = (first, Instant::now());
+            thread::sleep(FIRST_SAMPLE_WINDOW);
+            first
+        }
+    };
+    let now = read_processes()?;
+    let read_at = Instant::now();
+    let elapsed = read_at.duration_since(since).as_secs_f64();
+    let uptime: f64 = fs::read_to_string("/proc/uptime")
+        .ok()
+        .and_then(|u| u.split_whitespace().next()?.parse().ok())
+        .unwrap_or(0.0);
+    let names = user_names();
+    let usage: Vec<ProcessUsage> = now
+        .iter()
+        .map(|p| {
+            // a process started after the previous sample used all its ticks since
+            let before = before.get(&(p.pid, p.start_time)).copied().unwrap_or(0);
+            let ticks = p.ticks.saturating_sub(before);
+            ProcessUsage {
+                pid: p.pid,
+                user: match p.uid {
+                    // users of a directory service are not in /etc/passwd
+                    Some(uid) => names.get(&uid).cloned().unwrap_or(uid.to_string()),
+                    None => String::new(),
+                },
+                command: p.name.clone(),
+                cpu_percent: if elapsed > 0.0 {
+                    ticks as f64 / USER_HZ / elapsed * 100.0
+                } else {
+                    0.0
+                },
+                rss_bytes: p.rss_bytes,
+                runtime_seconds: (uptime - p.start_time as f64 / USER_HZ).max(0.0) as u64,
+                workload: None,
+            }
+        })
+        .collect();
+    *previous = Some((
+        now.iter()
+            .map(|p| ((p.pid, p.start_time), p.ticks))
+            .collect(),
+        read_at,
+    ));
+    drop(previous);
+    let mut top = summarize(usage, count);
+    // only the listed processes are worth the extra reads
+    for p in top.top_cpu.iter_mut().chain(top.top_memory.iter_mut()) {
+        p.command = command_line(p.pid, &p.command);
+        p.workload = workload::of_process(p.pid);
+    }
+    Ok(top)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse() {
+        let stat = "4242 (python (worker)) R 1 4242 4242 0 -1 4194304 100 0 0 0 1500 250 0 0 20 0 8 0 123456 1000 500";
+        assert_eq!(
+            parse_stat(stat),
+            Some((String::from("python (worker)"), 1750, 123456))
+        );
+        let status = "Name:\tpython\nUid:\t1000\t1000\t1000\t1000\nVmRSS:\t    2048 kB\n";
+        assert_eq!(parse_status(status), (Some(1000), 2048 * 1024));
+        let passwd = "root:x:0:0:root:/root:/bin/bash\nalice:x:1000:1000::/home/alice:/bin/bash\n";
+        assert_eq!(parse_passwd(passwd)[&1000], "alice");
+    }
+    #[test]
+    fn test_summarize() {
+        let process = |pid: u32, user: &str, cpu_percent: f64, rss_bytes: u64| ProcessUsage {
+            pid,
+            user: user.to_string(),
+            cpu_percent,
+            rss_bytes,
+            ..Default::default()
+        };
+        let top = summarize(
+            vec![
+                process(1, "root", 0.5, 10),
+                process(2, "alice", 390.0, 100),
+                process(3, "alice", 200.0, 300),
+                process(4, "bob", 10.0, 5000),
+            ],
+            2,
+        );
+        let pids = |p: &[ProcessUsage]| p.iter().map(|p| p.pid).collect::<Vec<u32>>();
+        assert_eq!(pids(&top.top_cpu), vec![2, 3]);
+        assert_eq!(pids(&top.top_memory), vec![4, 3]);
+        assert_eq!(top.users[0].user, "alice");
+        assert_eq!(top.users[0].cpu_percent, 590.0);
+        assert_eq!(top.users[0].processes, 2);
+        assert_eq!(top.users.len(), 3);
+    }
+}
@@ -0,0 +1,190 @@
+use serde::Serialize;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::path::Path;
+
+use crate::procs;
+
+pub const UTMP: &str = "/var/run/utmp";
+
+/// size of `struct utmp` on linux, the same on 64 and 32 bit
+const RECORD_SIZE: usize = 384;
+/// ut_type of a logged in user
+const USER_PROCESS: i16 = 7;
+/// clock ticks of /proc/<pid>/stat
+const USER_HZ: i64 = 100;
+/// process names of terminal multiplexer servers, which keep sessions alive after logout
+const MULTIPLEXERS: [(&str, &str); 2] = [("tmux: server", "tmux"), ("SCREEN", "screen")];
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub user: String,
+    /// "login" from utmp, "tmux" or "screen" for a multiplexer server
+    pub kind: String,
+    /// e.g. "pts/0", empty for a multiplexer
+    pub tty: String,
+    /// host or address the user came from, empty for a local login
+    pub from: String,
+    /// unix time
+    pub login_time: i64,
+    pub pid: u32,
+}
+
+/// NUL padded string field of a record.
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).trim().to_string()
+}
+
+fn int(field: &[u8]) -> i32 {
+    i32::from_ne_bytes([field[0], field[1], field[2], field[3]])
+}
+
+/// ut_addr_v6, only the first word is set for ipv4
+fn address(field: &[u8]) -> String {
+    if field.iter().all(|b| *b == 0) {
+        return String::new();
+    }
+    if field[4..].iter().all(|b| *b == 0) {
+        return Ipv4Addr::new(field[0], field[1], field[2], field[3]).to_string();
+    }
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(&field[..16]);
+    Ipv6Addr::from(octets).to_string()
+}
+
+/// Logged in users of a utmp file, including entries of processes that are gone.
+fn parse_utmp(data: &[u8]) -> Vec<Session> {
+    data.chunks_exact(RECORD_SIZE)
+        .filter(|r| i16::from_ne_bytes([r[0], r[1]]) == USER_PROCESS)
+        .map(|r| {
+            // ut_host, falling back to ut_addr_v6
+            let from = match text(&r[76..332]) {
+                h if h.is_empty() => address(&r[348..364]),
+                h => h,
+            };
+            Session {
+                user: text(&r[44..76]),
+                kind: String::from("login"),
+                tty: text(&r[8..40]),
+                from,
+                login_time: int(&r[340..344]) as i64,
+                pid: int(&r[4..8]) as u32,
+            }
+        })
+        .collect()
+}
+
+fn is_running(pid: u32) -> bool {
+    Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+/// Current logins, an entry whose process is gone was not cleaned up after a crash.
+pub fn read_utmp(path: &Path) -> Result<Vec<Session>, String> {
+    let data = fs::read(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+    Ok(parse_utmp(&data)
+        .into_iter()
+        .filter(|s| is_running(s.pid))
+        .collect())
+}
+
+/// tmux and screen servers, they hold sessions of users that logged out.
+pub fn multiplexers() -> Vec<Session> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+    let boot_time = fs::read_to_string("/proc/stat")
+        .ok()
+        .and_then(|s| {
+            s.lines()
+                .find_map(|l| l.strip_prefix("btime "))
+                .and_then(|b| b.trim().parse::<i64>().ok())
+        })
+        .unwrap_or(0);
+    let names = procs::user_names();
+    let mut sessions = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let pid: u32 = match entry.file_name().to_string_lossy().parse() {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let (head, rest) = match stat.rsplit_once(')') {
+            Some(s) => s,
+            None => continue,
+        };
+        // the name is cut to 15 characters, the command line has the full title
+        let cmdline = fs::read(entry.path().join("cmdline"))
+            .map(|c| text(&c))
+            .unwrap_or_default();
+        let name = head.split_once('(').map(|h| h.1).unwrap_or_default();
+        let kind = match MULTIPLEXERS
+            .iter()
+            .find(|(title, _)| cmdline.starts_with(title) || name == *title)
+        {
+            Some((_, kind)) => kind,
+            None => continue,
+        };
+        let uid: Option<u32> = fs::read_to_string(entry.path().join("status"))
+            .ok()
+            .and_then(|s| {
+                s.lines()
+                    .find_map(|l| l.strip_prefix("Uid:"))
+                    .and_then(|u| u.split_whitespace().next()?.parse().ok())
+            });
+        // starttime is field 22, rest starts at field 3
+        let start_ticks: i64 = rest
+            .split_whitespace()
+            .nth(19)
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        sessions.push(Session {
+            user: match uid {
+                Some(uid) => names.get(&uid).cloned().unwrap_or(uid.to_string()),
+                None => String::new(),
+            },
+            kind: kind.to_string(),
+            tty: String::new(),
+            from: String::new(),
+            login_time: boot_time + start_ticks / USER_HZ,
+            pid,
+        });
+    }
+    sessions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn record(kind: i16, pid: i32, tty: &str, user: &str, host: &str, addr: [u8; 4]) -> Vec<u8> {
+        let mut r = vec![0u8; RECORD_SIZE];
+        r[0..2].copy_from_slice(&kind.to_ne_bytes());
+        r[4..8].copy_from_slice(&pid.to_ne_bytes());
+        r[8..8 + tty.len()].copy_from_slice(tty.as_bytes());
+        r[44..44 + user.len()].copy_from_slice(user.as_bytes());
+        r[76..76 + host.len()].copy_from_slice(host.as_bytes());
+        r[340..344].copy_from_slice(&1_700_000_000i32.to_ne_bytes());
+        r[348..352].copy_from_slice(&addr);
+        r
+    }
+    #[test]
+    fn test_parse_utmp() {
+        let mut data = record(2, 0, "~", "reboot", "6.8.0", [0; 4]);
+        data.extend(record(7, 4242, "pts/0", "alice", "10.0.0.7", [10, 0, 0, 7]));
+        data.extend(record(7, 4343, "pts/1", "bob", "", [192, 168, 1, 20]));
+        data.extend(record(8, 4444, "pts/2", "", "", [0; 4]));
+        let sessions = parse_utmp(&data);
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].user, "alice");
+        assert_eq!(sessions[0].tty, "pts/0");
+        assert_eq!(sessions[0].from, "10.0.0.7");
+        assert_eq!(sessions[0].login_time, 1_700_000_000);
+        assert_eq!(sessions[1].pid, 4343);
+        assert_eq!(sessions[1].from, "192.168.1.20");
+    }
+}
@@ -0,0 +1,304 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::procs;
+
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+/// container names and job owners are asked once, forgotten when there are this many
+const MAX_NAMES: usize = 1000;
+
+/// container id -> name, empty if docker does not know the container
+static NAMES: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+/// a job scontrol told nothing about is asked again after this
+const JOB_USER_RETRY: Duration = Duration::from_secs(300);
+/// longest wait for slurmctld, scontrol runs in the sampling loop
+const SCONTROL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// slurm job id -> user as told by scontrol, empty if it failed, and when it was asked
+static JOB_USERS: Mutex<Option<HashMap<String, (String, Instant)>>> = Mutex::new(None);
+
+/// The container or batch job a process belongs to.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Workload {
+    /// "docker", "podman", "containerd", "crio", "kubernetes" or "slurm"
+    pub kind: String,
+    /// container id or slurm job id
+    pub id: String,
+    /// container name, empty if unknown
+    pub name: String,
+    /// owner of a slurm job, the process may run as somebody else, empty if unknown
+    pub user: String,
+}
+
+fn is_container_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// "docker-<id>.scope" -> ("docker", id)
+fn scope_container(segment: &str) -> Option<(&'static str, &str)> {
+    let segment = segment.strip_suffix(".scope").unwrap_or(segment);
+    for (prefix, kind) in [
+        ("docker-", "docker"),
+        ("libpod-", "podman"),
+        ("cri-containerd-", "containerd"),
+        ("crio-", "crio"),
+    ] {
+        if let Some(id) = segment
+            .strip_prefix(prefix)
+            .filter(|id| is_container_id(id))
+        {
+            return Some((kind, id));
+        }
+    }
+    None
+}
+
+/// The workload of a cgroup path, e.g. "/slurm/uid_1000/job_12345/step_0" or
+/// "/system.slice/docker-<id>.scope".
+fn parse_path(path: &str) -> Option<Workload> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if let Some(job) = segments.iter().find_map(|s| s.strip_prefix("job_")) {
+        if !job.is_empty() && job.bytes().all(|b| b.is_ascii_digit()) {
+            return Some(Workload {
+                kind: String::from("slurm"),
+                id: job.to_string(),
+                ..Default::default()
+            });
+        }
+    }
+    let in_kubernetes = segments.iter().any(|s| s.starts_with("kubepods"));
+    for (i, segment) in segments.iter().enumerate().rev() {
+        // cgroupfs driver: "/docker/<id>", "/kubepods/besteffort/pod<uid>/<id>"
+        let found = match scope_container(segment) {
+            Some(f) => Some(f),
+            None if is_container_id(segment) && i > 0 && segments[i - 1] == "docker" => {
+                Some(("docker", *segment))
+            }
+            None if is_container_id(segment) && in_kubernetes => Some(("kubernetes", *segment)),
+            None => None,
+        };
+        if let Some((kind, id)) = found {
+            return Some(Workload {
+                kind: String::from(if in_kubernetes { "kubernetes" } else { kind }),
+                id: id.to_string(),
+                ..Default::default()
+            });
+        }
+    }
+    None
+}
+
+/// Content of /proc/<pid>/cgroup, "hierarchy:controllers:path" per line.
+fn parse_cgroup(content: &str) -> Option<Workload> {
+    content
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .find_map(parse_path)
+}
+
+/// "4:memory:/slurm/uid_1000/job_678/step_batch" -> 1000, cgroup v2 has no uid in the path.
+fn slurm_uid(content: &str) -> Option<u32> {
+    content
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .flat_map(|path| path.split('/'))
+        .find_map(|s| s.strip_prefix("uid_")?.parse().ok())
+}
+
+/// "JobId=12345 JobName=train UserId=alice(1000) GroupId=..." -> "alice"
+fn parse_scontrol_user(output: &str) -> Option<String> {
+    let user_id = output
+        .split_whitespace()
+        .find_map(|f| f.strip_prefix("UserId="))?;
+    let name = user_id.split('(').next()?;
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Owner of a slurm job, from the cgroup path or else from scontrol.
+fn job_user(cgroup: &str, job: &str) -> String {
+    if let Some(uid) = slurm_uid(cgroup) {
+        return procs::user_names()
+            .remove(&uid)
+            .unwrap_or_else(|| uid.to_string());
+    }
+    let mut users = JOB_USERS.lock().unwrap_or_else(|e| e.into_inner());
+    let users = users.get_or_insert_with(HashMap::new);
+    match users.get(job) {
+        Some((user, _)) if !user.is_empty() => return user.clone(),
+        // no scontrol on this host or slurmctld did not answer
+        Some((_, asked)) if asked.elapsed() < JOB_USER_RETRY => return String::new(),
+        _ => {}
+    }
+    let user = scontrol_job(job)
+        .and_then(|o| parse_scontrol_user(&o))
+        .unwrap_or_default();
+    if users.len() >= MAX_NAMES {
+        users.clear();
+    }
+    users.insert(job.to_string(), (user.clone(), Instant::now()));
+    user
+}
+
+/// Output of `scontrol show job`, None if it failed or took longer than `SCONTROL_TIMEOUT`.
+fn scontrol_job(job: &str) -> Option<String> {
+    let mut child = Command::new("scontrol")
+        .args(["show", "job", job, "--oneliner"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if started.elapsed() < SCONTROL_TIMEOUT => {
+                thread::sleep(Duration::from_millis(20))
+            }
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+    let output = child.wait_with_output().ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        None
+    }
+}
+
+/// Name of a container from the docker API, None if docker does not answer.
+fn docker_name(id: &str) -> Option<String> {
+    let mut stream = UnixStream::connect(DOCKER_SOCKET).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+    // HTTP/1.0 so that docker closes the connection after the response
+    write!(
+        stream,
+        "GET /containers/{}/json HTTP/1.0\r\nHost: docker\r\n\r\n",
+        id
+    )
+    .ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    let (_, body) = response.split_once("\r\n\r\n")?;
+    let container: serde_json::Value = serde_json::from_str(body).ok()?;
+    // "No such container" has no name
+    Some(
+        container
+            .get("Name")
+            .and_then(|n| n.as_str())
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_string(),
+    )
+}
+
+fn container_name(id: &str) -> String {
+    let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    let names = names.get_or_insert_with(HashMap::new);
+    if let Some(name) = names.get(id) {
+        return name.clone();
+    }
+    // docker not running or not answering in time, asked again next time
+    let name = match docker_name(id) {
+        Some(n) => n,
+        None => return String::new(),
+    };
+    if names.len() >= MAX_NAMES {
+        names.clear();
+    }
+    names.insert(id.to_string(), name.clone());
+    name
+}
+
+/// The container or slurm job of a process, None for a plain process or one that is gone.
+pub fn of_process(pid: u32) -> Option<Workload> {
+    let content = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+    let mut workload = parse_cgroup(&content)?;
+    if workload.kind == "slurm" {
+        workload.user = job_user(&content, &workload.id);
+    } else {
+        workload.name = container_name(&workload.id);
+    }
+    Some(workload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse_cgroup() {
+        let id = "4f3c2a1b0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b";
+        let workload = |kind: &str, id: &str| Workload {
+            kind: kind.to_string(),
+            id: id.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_cgroup(&format!("0::/system.slice/docker-{}.scope\n", id)),
+            Some(workload("docker", id))
+        );
+        assert_eq!(
+            parse_cgroup(&format!(
+                "12:pids:/docker/{}\n11:memory:/docker/{}\n",
+                id, id
+            )),
+            Some(workload("docker", id))
+        );
+        assert_eq!(
+            parse_cgroup(&format!("0::/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1234.slice/cri-containerd-{}.scope\n", id)),
+            Some(workload("kubernetes", id))
+        );
+        assert_eq!(
+            parse_cgroup("0::/system.slice/slurmstepd.scope/job_12345/step_0/user/task_0\n"),
+            Some(workload("slurm", "12345"))
+        );
+        assert_eq!(
+            parse_cgroup("4:memory:/slurm/uid_1000/job_678/step_batch\n"),
+            Some(workload("slurm", "678"))
+        );
+        assert_eq!(
+            parse_cgroup("0::/user.slice/user-1000.slice/session-3.scope\n"),
+            None
+        );
+    }
+    #[test]
+    fn test_job_user() {
+        assert_eq!(
+            slurm_uid(
+                "5:pids:/slurm/uid_1000/job_678/step_0\n4:memory:/slurm/uid_1000/job_678/step_0\n"
+            ),
+            Some(1000)
+        );
+        assert_eq!(
+            slurm_uid("0::/system.slice/slurmstepd.scope/job_12345/step_0/user/task_0\n"),
+            None
+        );
+        assert_eq!(
+            parse_scontrol_user(
+                "JobId=12345 JobName=train UserId=alice(1000) GroupId=lab(100) MCS_label=N/A"
+            ),
+            Some(String::from("alice"))
+        );
+        assert_eq!(
+            parse_scontrol_user("slurm_load_jobs error: Invalid job id specified"),
+            None
+        );
+    }
+}
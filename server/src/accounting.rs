@@ -0,0 +1,410 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::Datelike;
+use chrono::Local;
+use chrono::NaiveDate;
+use log::error;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::free::mib_to_bytes;
+use crate::free::percent;
+use crate::query;
+use crate::query::Record;
+use crate::query::Value;
+use crate::redis_load;
+use crate::redis_save;
+use crate::render::Column;
+use crate::render::Format;
+use crate::render::InfoTable;
+use crate::update_interval;
+use crate::ServerError;
+use crate::ServerInfo;
+
+/// a gap of more update intervals means the host was away, it is not counted
+const MAX_GAP_INTERVALS: u32 = 3;
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// time of the previous update of every host
+static LAST_UPDATE: OnceCell<Mutex<HashMap<String, Instant>>> = OnceCell::new();
+/// every host updates on its own request, keeps the read-modify-write of a day atomic
+static ACCOUNTING_LOCK: Mutex<()> = Mutex::new(());
+
+/// Usage of one user on one host and card model during one day.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Usage {
+    pub user: String,
+    pub host: String,
+    pub model: String,
+    pub gpu_seconds: f64,
+    /// GiB of gpu memory held, times seconds
+    pub memory_seconds: f64,
+    /// utilization in percent times gpu seconds, divide by `gpu_seconds` for the average
+    pub utilization_seconds: f64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.gpu_seconds += other.gpu_seconds;
+        self.memory_seconds += other.memory_seconds;
+        self.utilization_seconds += other.utilization_seconds;
+    }
+}
+
+impl Record for Usage {
+    const FIELDS: &'static [&'static str] = &["user", "host", "model"];
+
+    fn values(&self, field: &str) -> Vec<Value> {
+        match field {
+            "user" => vec![Value::Text(self.user.clone())],
+            "host" => vec![Value::Text(self.host.clone())],
+            "model" => vec![Value::Text(self.model.clone())],
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn last_update() -> &'static Mutex<HashMap<String, Instant>> {
+    LAST_UPDATE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn day_key(day: NaiveDate) -> String {
+    format!("accounting:{}", day.format("%Y-%m-%d"))
+}
+
+/// Share `seconds` of every busy card among the users running on it.
+pub fn usage_of(server_info: &ServerInfo, seconds: f64) -> Vec<Usage> {
+    let mut usage: BTreeMap<(String, String), Usage> = BTreeMap::new();
+    for (gpu, detail) in server_info.gpu.details.iter().enumerate() {
+        let processes: Vec<_> = server_info
+            .gpu
+            .processes
+            .iter()
+            .filter(|p| p.gpu_index == gpu && !p.user.is_empty())
+            .collect();
+        let mut users: Vec<&str> = processes.iter().map(|p| p.user.as_str()).collect();
+        users.sort();
+        users.dedup();
+        if users.is_empty() {
+            continue;
+        }
+        let share = seconds / users.len() as f64;
+        let utilization = percent(&detail.utilization_gpu).unwrap_or(0.0);
+        for user in users {
+            let memory: u64 = processes
+                .iter()
+                .filter(|p| p.user == user)
+                .filter_map(|p| mib_to_bytes(&p.used_memory))
+                .sum();
+            let entry = usage
+                .entry((user.to_string(), detail.name.clone()))
+                .or_insert_with(|| Usage {
+                    user: user.to_string(),
+                    host: server_info.hostname.clone(),
+                    model: detail.name.clone(),
+                    ..Usage::default()
+                });
+            entry.gpu_seconds += share;
+            entry.memory_seconds += memory as f64 / GIB * seconds;
+            entry.utilization_seconds += utilization * share;
+        }
+    }
+    usage.into_values().collect()
+}
+
+/// Seconds to count since the previous update, None for the first one or after an outage.
+fn counted_seconds(previous: Option<Instant>, now: Instant, max_gap: Duration) -> Option<f64> {
+    let gap = now.saturating_duration_since(previous?);
+    (gap <= max_gap).then_some(gap.as_secs_f64())
+}
+
+/// Called on every update, adds the time since the previous update of the host.
+pub fn record(server_info: &ServerInfo) {
+    let now = Instant::now();
+    let seconds = match last_update().lock() {
+        Ok(mut last) => {
+            let previous = last.insert(server_info.hostname.clone(), now);
+            match counted_seconds(previous, now, update_interval() * MAX_GAP_INTERVALS) {
+                Some(s) => s,
+                None => return,
+            }
+        }
+        Err(_) => return,
+    };
+    let usage = usage_of(server_info, seconds);
+    if usage.is_empty() {
+        return;
+    }
+    if let Err(e) = add_to_day(Local::now().date_naive(), &usage) {
+        error!("save accounting error: {}", e);
+    }
+}
+
+fn add_to_day(day: NaiveDate, usage: &[Usage]) -> Result<(), ServerError> {
+    let _guard = ACCOUNTING_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut records: Vec<Usage> = redis_load(&day_key(day))?;
+    for u in usage {
+        match records
+            .iter_mut()
+            .find(|r| r.user == u.user && r.host == u.host && r.model == u.model)
+        {
+            Some(r) => r.add(u),
+            None => records.push(u.clone()),
+        }
+    }
+    redis_save(&day_key(day), &records)
+}
+
+#[derive(Clone, Copy)]
+enum GroupBy {
+    User,
+    Host,
+    Model,
+}
+
+impl GroupBy {
+    fn parse(input: &str) -> Option<GroupBy> {
+        match input.trim().to_lowercase().as_str() {
+            "user" => Some(GroupBy::User),
+            "host" => Some(GroupBy::Host),
+            "model" => Some(GroupBy::Model),
+            _ => None,
+        }
+    }
+    fn key(&self, usage: &Usage) -> String {
+        match self {
+            GroupBy::User => usage.user.clone(),
+            GroupBy::Host => usage.host.clone(),
+            GroupBy::Model => usage.model.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    group: String,
+    gpu_hours: f64,
+    gpu_memory_gib_hours: f64,
+    /// percent
+    average_utilization: f64,
+}
+
+impl Record for Report {
+    const FIELDS: &'static [&'static str] = &["group", "gpu_hours", "gpu_memory", "util"];
+
+    fn values(&self, field: &str) -> Vec<Value> {
+        match field {
+            "group" => vec![Value::Text(self.group.clone())],
+            "gpu_hours" => vec![Value::Number(self.gpu_hours)],
+            "gpu_memory" => vec![Value::Number(self.gpu_memory_gib_hours)],
+            "util" => vec![Value::Number(self.average_utilization)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Sum the records per group, largest consumer first.
+fn report(records: &[Usage], group_by: GroupBy) -> Vec<Report> {
+    let mut groups: BTreeMap<String, Usage> = BTreeMap::new();
+    for r in records {
+        groups.entry(group_by.key(r)).or_default().add(r);
+    }
+    let mut report: Vec<Report> = groups
+        .into_iter()
+        .map(|(group, u)| Report {
+            group,
+            gpu_hours: u.gpu_seconds / 3600.0,
+            gpu_memory_gib_hours: u.memory_seconds / 3600.0,
+            average_utilization: if u.gpu_seconds > 0.0 {
+                u.utilization_seconds / u.gpu_seconds
+            } else {
+                0.0
+            },
+        })
+        .collect();
+    report.sort_by(|a, b| b.gpu_hours.total_cmp(&a.gpu_hours));
+    report
+}
+
+#[derive(Deserialize)]
+pub struct AccountingQuery {
+    /// first day, YYYY-MM-DD, defaults to the first of this month
+    from: Option<String>,
+    /// last day (inclusive), defaults to today
+    to: Option<String>,
+    group_by: Option<String>,
+    /// filter on the records before grouping, e.g. `model ~ "*A100*" and host != node3`
+    q: Option<String>,
+    /// on the report rows, defaults to the most gpu hours first
+    sort: Option<String>,
+    limit: Option<usize>,
+    format: Option<String>,
+}
+
+#[get("/api/v1/accounting")]
+async fn accounting(req: HttpRequest, query: web::Query<AccountingQuery>) -> impl Responder {
+    let format = match Format::negotiate(&req, query.format.as_deref()) {
+        Ok(f) => f,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let today = Local::now().date_naive();
+    let parse_day = |input: &Option<String>, default: NaiveDate| match input {
+        Some(d) => NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d")
+            .map_err(|_| format!("invalid date: {}, expected YYYY-MM-DD", d)),
+        None => Ok(default),
+    };
+    let from = match parse_day(&query.from, today.with_day(1).unwrap_or(today)) {
+        Ok(d) => d,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let to = match parse_day(&query.to, today) {
+        Ok(d) => d,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    if to < from || (to - from).num_days() > 366 {
+        return HttpResponse::BadRequest().body("from must be before to, at most one year apart");
+    }
+    let group_by = match query.group_by.as_deref().map(GroupBy::parse) {
+        Some(Some(g)) => g,
+        None => GroupBy::User,
+        Some(None) => {
+            return HttpResponse::BadRequest().body("group_by must be one of user, host, model")
+        }
+    };
+    let filter = match &query.q {
+        Some(q) if !q.trim().is_empty() => match query::parse_for::<Usage>(q) {
+            Ok(f) => Some(f),
+            Err(e) => return HttpResponse::BadRequest().body(e.explain(q)),
+        },
+        _ => None,
+    };
+    let sort_key = match &query.sort {
+        Some(s) if !s.trim().is_empty() => match query::SortKey::parse_for::<Report>(s) {
+            Ok(k) => Some(k),
+            Err(e) => return HttpResponse::BadRequest().body(format!("sort: {}", e.message)),
+        },
+        _ => None,
+    };
+
+    let mut records = Vec::new();
+    for day in from.iter_days().take_while(|d| *d <= to) {
+        match redis_load::<Vec<Usage>>(&day_key(day)) {
+            Ok(r) => records.extend(r),
+            Err(e) => {
+                error!("load accounting error: {}", e);
+                return HttpResponse::InternalServerError().body("load accounting failed");
+            }
+        }
+    }
+    if let Some(filter) = filter {
+        records.retain(|r| filter.matches_record(r));
+    }
+    let mut report = report(&records, group_by);
+    if let Some(sort_key) = sort_key {
+        sort_key.sort_records(&mut report);
+    }
+    if let Some(limit) = query.limit {
+        report.truncate(limit);
+    }
+    if format == Format::Json {
+        return HttpResponse::Ok().json(report);
+    }
+
+    let column = |key, title, note| Column {
+        key,
+        title,
+        note,
+        list: false,
+    };
+    let group_title = match group_by {
+        GroupBy::User => "user",
+        GroupBy::Host => "host",
+        GroupBy::Model => "model",
+    };
+    let mut table = InfoTable::new(vec![
+        column("group", group_title, None),
+        column("gpu_hours", "gpu hours", None),
+        column(
+            "gpu_memory_gib_hours",
+            "gpu memory",
+            Some("GiB of gpu memory held, times hours"),
+        ),
+        column(
+            "average_utilization",
+            "avg gpu@u",
+            Some("average utilization of the cards while in use"),
+        ),
+    ]);
+    for r in &report {
+        table.add_row(vec![
+            r.group.clone(),
+            format!("{:.2}", r.gpu_hours),
+            format!("{:.2}", r.gpu_memory_gib_hours),
+            format!("{:.1}", r.average_utilization),
+        ]);
+    }
+    let body = match format {
+        Format::Table => format!(
+            ">> gpu usage from {} to {} by {}\n{}{}",
+            from,
+            to,
+            group_title,
+            table.render(format),
+            table.notes()
+        ),
+        _ => table.render(format),
+    };
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn usage(user: &str, host: &str, gpu_seconds: f64, utilization: f64) -> Usage {
+        Usage {
+            user: user.to_string(),
+            host: host.to_string(),
+            model: String::from("NVIDIA A100"),
+            gpu_seconds,
+            memory_seconds: 0.0,
+            utilization_seconds: utilization * gpu_seconds,
+        }
+    }
+    #[test]
+    fn test_report() {
+        let records = vec![
+            usage("alice", "node1", 3600.0, 50.0),
+            usage("alice", "node2", 3600.0, 100.0),
+            usage("bob", "node1", 1800.0, 10.0),
+        ];
+        let by_user = report(&records, GroupBy::User);
+        assert_eq!(by_user[0].group, "alice");
+        assert_eq!(by_user[0].gpu_hours, 2.0);
+        assert_eq!(by_user[0].average_utilization, 75.0);
+        let by_host = report(&records, GroupBy::Host);
+        assert_eq!(by_host[0].group, "node1");
+        assert_eq!(by_host[0].gpu_hours, 1.5);
+    }
+    #[test]
+    fn test_counted_seconds() {
+        // the client sleeps --interval after collecting, so updates come a bit over 60 s apart
+        let first = Instant::now();
+        let second = first + Duration::from_secs(61);
+        let max_gap = Duration::from_secs(60) * MAX_GAP_INTERVALS;
+        assert_eq!(counted_seconds(None, first, max_gap), None);
+        assert_eq!(counted_seconds(Some(first), second, max_gap), Some(61.0));
+        let after_outage = second + Duration::from_secs(600);
+        assert_eq!(counted_seconds(Some(second), after_outage, max_gap), None);
+    }
+}
@@ -0,0 +1,107 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::Local;
+use log::warn;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::stream;
+
+/// recent alerts kept for the API
+const MAX_ALERTS: usize = 500;
+/// the same alert key is not raised again within this time
+const REPEAT_AFTER: Duration = Duration::from_secs(24 * 3600);
+
+static ALERTS: OnceCell<Mutex<VecDeque<Alert>>> = OnceCell::new();
+static RAISED: OnceCell<Mutex<HashMap<String, Instant>>> = OnceCell::new();
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Alert {
+    pub time: String,
+    pub kind: String,
+    pub host: String,
+    /// user the alert is about, empty if nobody in particular
+    pub user: String,
+    pub message: String,
+}
+
+impl Alert {
+    pub fn new(kind: &str, host: &str, user: &str, message: &str) -> Alert {
+        Alert {
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            kind: kind.to_string(),
+            host: host.to_string(),
+            user: user.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+fn alerts() -> &'static Mutex<VecDeque<Alert>> {
+    ALERTS.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+fn raised() -> &'static Mutex<HashMap<String, Instant>> {
+    RAISED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Raise an alert once per `key`, returns false if it was raised recently.
+pub fn raise(key: &str, alert: Alert) -> bool {
+    match raised().lock() {
+        Ok(mut r) => {
+            r.retain(|_, t| t.elapsed() < REPEAT_AFTER);
+            if r.contains_key(key) {
+                return false;
+            }
+            r.insert(key.to_string(), Instant::now());
+        }
+        Err(_) => return false,
+    }
+    warn!("[{}] {}: {}", alert.kind, alert.host, alert.message);
+    stream::publish_alert(&alert.host, &alert.message);
+    if let Ok(mut a) = alerts().lock() {
+        a.push_back(alert);
+        while a.len() > MAX_ALERTS {
+            a.pop_front();
+        }
+    }
+    true
+}
+
+/// Allow the alert `key` to be raised again, e.g. once the problem went away.
+pub fn resolve(key: &str) {
+    if let Ok(mut r) = raised().lock() {
+        r.remove(key);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AlertQuery {
+    kind: Option<String>,
+    host: Option<String>,
+    user: Option<String>,
+}
+
+#[get("/api/v1/alerts")]
+async fn alerts_api(query: web::Query<AlertQuery>) -> impl Responder {
+    let list: Vec<Alert> = match alerts().lock() {
+        Ok(a) => a
+            .iter()
+            .rev()
+            .filter(|a| query.kind.as_ref().is_none_or(|k| &a.kind == k))
+            .filter(|a| query.host.as_ref().is_none_or(|h| &a.host == h))
+            .filter(|a| query.user.as_ref().is_none_or(|u| &a.user == u))
+            .cloned()
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    HttpResponse::Ok().json(list)
+}
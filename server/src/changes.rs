@@ -0,0 +1,185 @@
+use chrono::DateTime;
+use chrono::Local;
+use log::error;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::events;
+use crate::redis_load;
+use crate::redis_save;
+use crate::ServerInfo;
+
+const SNAPSHOTS_KEY: &str = "snapshots";
+/// boot times computed from uptime jitter by a few seconds
+pub const BOOT_TIME_TOLERANCE: i64 = 120;
+
+/// last known state of every host ever seen, kept across server restarts
+static SNAPSHOTS: OnceCell<Mutex<BTreeMap<String, Snapshot>>> = OnceCell::new();
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
+pub struct Snapshot {
+    pub first_seen: Option<DateTime<Local>>,
+    pub boot_time: Option<i64>,
+    pub uptime_seconds: Option<u64>,
+    pub driver_versions: Vec<String>,
+    pub gpu_count: usize,
+}
+
+impl Snapshot {
+    fn of(server_info: &ServerInfo) -> Snapshot {
+        let cards: Vec<_> = server_info
+            .gpu
+            .details
+            .iter()
+            .filter(|d| !d.name.is_empty())
+            .collect();
+        let mut driver_versions: Vec<String> =
+            cards.iter().map(|d| d.driver_version.clone()).collect();
+        driver_versions.sort();
+        driver_versions.dedup();
+        Snapshot {
+            first_seen: None,
+            boot_time: server_info.boot_time,
+            uptime_seconds: server_info.uptime_seconds,
+            driver_versions,
+            gpu_count: cards.len(),
+        }
+    }
+}
+
+fn snapshots() -> &'static Mutex<BTreeMap<String, Snapshot>> {
+    SNAPSHOTS.get_or_init(|| {
+        let snapshots = match redis_load(SNAPSHOTS_KEY) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("load snapshots error: {}", e);
+                BTreeMap::new()
+            }
+        };
+        Mutex::new(snapshots)
+    })
+}
+
+fn format_duration(seconds: u64) -> String {
+    format!(
+        "{}d {}h {}m",
+        seconds / 86400,
+        seconds % 86400 / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+/// Events between the previous and the current snapshot of a host, as (kind, message).
+pub fn compare(previous: &Snapshot, current: &Snapshot) -> Vec<(&'static str, String)> {
+    let mut changes = Vec::new();
+    // uptime going backwards is a reboot even if the clock jumped
+    let rebooted = match (previous.boot_time, current.boot_time) {
+        (Some(p), Some(c)) => c - p > BOOT_TIME_TOLERANCE,
+        _ => false,
+    } || matches!(
+        (previous.uptime_seconds, current.uptime_seconds),
+        (Some(p), Some(c)) if c + (BOOT_TIME_TOLERANCE as u64) < p
+    );
+    if rebooted {
+        let mut message = String::from("rebooted");
+        if let Some(b) = current
+            .boot_time
+            .and_then(|b| DateTime::from_timestamp(b, 0))
+        {
+            message += &format!(
+                " at {}",
+                b.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+            );
+        }
+        if let Some(u) = previous.uptime_seconds {
+            message += &format!(", was up for at least {}", format_duration(u));
+        }
+        changes.push(("reboot", message));
+    }
+    // a failed driver reports no cards, the health state covers that
+    if previous.gpu_count > 0 && current.gpu_count > 0 {
+        if previous.driver_versions != current.driver_versions {
+            changes.push((
+                "driver",
+                format!(
+                    "gpu driver {} -> {}",
+                    previous.driver_versions.join(","),
+                    current.driver_versions.join(",")
+                ),
+            ));
+        }
+        if previous.gpu_count != current.gpu_count {
+            changes.push((
+                "gpu_count",
+                format!("gpu count {} -> {}", previous.gpu_count, current.gpu_count),
+            ));
+        }
+    }
+    changes
+}
+
+/// Called on every update, records first sight, reboots and hardware changes of the host.
+pub fn check(server_info: &ServerInfo) {
+    let mut current = Snapshot::of(server_info);
+    let host = &server_info.hostname;
+    let mut snapshots = match snapshots().lock() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    let changes = match snapshots.get(host) {
+        Some(previous) => {
+            current.first_seen = previous.first_seen;
+            // keep the old value if this update lacks it, e.g. a driver failure
+            if current.gpu_count == 0 {
+                current.driver_versions = previous.driver_versions.clone();
+                current.gpu_count = previous.gpu_count;
+            }
+            compare(previous, &current)
+        }
+        None => {
+            current.first_seen = Some(Local::now());
+            let mut message = format!("first seen with {} gpu(s)", current.gpu_count);
+            if !current.driver_versions.is_empty() {
+                message += &format!(", driver {}", current.driver_versions.join(","));
+            }
+            vec![("first_seen", message)]
+        }
+    };
+    snapshots.insert(host.clone(), current);
+    // uptime alone moves on with every update, not worth a write
+    if !changes.is_empty() {
+        if let Err(e) = redis_save(SNAPSHOTS_KEY, &*snapshots) {
+            error!("save snapshots error: {}", e);
+        }
+    }
+    drop(snapshots);
+    for (kind, message) in changes {
+        events::record(host, kind, &message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn snapshot(boot_time: i64, uptime: u64, driver: &str, gpu_count: usize) -> Snapshot {
+        Snapshot {
+            first_seen: None,
+            boot_time: Some(boot_time),
+            uptime_seconds: Some(uptime),
+            driver_versions: vec![driver.to_string()],
+            gpu_count,
+        }
+    }
+    #[test]
+    fn test_compare() {
+        let before = snapshot(1_700_000_000, 86400, "535.1", 8);
+        let jitter = snapshot(1_700_000_003, 86460, "535.1", 8);
+        assert!(compare(&before, &jitter).is_empty());
+        let reboot = snapshot(1_700_090_000, 60, "550.2", 7);
+        let kinds: Vec<&str> = compare(&before, &reboot).iter().map(|c| c.0).collect();
+        assert_eq!(kinds, vec!["reboot", "driver", "gpu_count"]);
+    }
+}
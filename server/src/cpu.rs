@@ -0,0 +1,84 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// one character per core, from idle to fully busy
+const HEAT: [char; 9] = ['·', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// cores per line of the heat strip
+const STRIP_WIDTH: usize = 32;
+
+/// Share of time by mode of one core, as sent by the client.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+pub struct CpuLoad {
+    #[serde(default)]
+    pub user: f32,
+    #[serde(default)]
+    pub nice: f32,
+    #[serde(default)]
+    pub system: f32,
+    #[serde(default)]
+    pub interrupt: f32,
+    #[serde(default)]
+    pub idle: f32,
+    #[serde(default)]
+    pub iowait: f32,
+    #[serde(default)]
+    pub steal: f32,
+}
+
+impl CpuLoad {
+    /// Everything but idle and iowait.
+    pub fn busy(&self) -> f32 {
+        (1.0 - self.idle - self.iowait).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CpuDetail {
+    #[serde(default)]
+    pub cores: Vec<CpuLoad>,
+    /// 1, 5 and 15 minutes
+    #[serde(default)]
+    pub load_average: [f32; 3],
+    #[serde(default)]
+    pub processes_running: u64,
+    #[serde(default)]
+    pub processes_total: u64,
+    #[serde(default)]
+    pub threads: u64,
+    #[serde(default)]
+    pub context_switches_per_sec: f64,
+}
+
+/// "▁▁█▃" with a line break every `STRIP_WIDTH` cores.
+pub fn heat_strip(cores: &[CpuLoad]) -> String {
+    cores
+        .chunks(STRIP_WIDTH)
+        .map(|line| {
+            line.iter()
+                .map(|c| HEAT[(c.busy() * (HEAT.len() - 1) as f32).round() as usize])
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// "0.52 0.58 0.59"
+pub fn describe_load(detail: &CpuDetail) -> String {
+    let [one, five, fifteen] = detail.load_average;
+    format!("{:.2} {:.2} {:.2}", one, five, fifteen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_heat_strip() {
+        let core = |idle: f32| CpuLoad {
+            idle,
+            ..Default::default()
+        };
+        assert_eq!(heat_strip(&[core(1.0), core(0.5), core(0.0)]), "·▄█");
+        let many = vec![core(1.0); STRIP_WIDTH + 1];
+        assert_eq!(heat_strip(&many).lines().count(), 2);
+    }
+}
@@ -0,0 +1,29 @@
+use actix_web::get;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+
+// embedded at build time so the dashboard works without internet access
+const DASHBOARD_HTML: &str = include_str!("../static/dashboard.html");
+const DASHBOARD_CSS: &str = include_str!("../static/dashboard.css");
+const DASHBOARD_JS: &str = include_str!("../static/dashboard.js");
+
+#[get("/dashboard")]
+async fn dashboard() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(DASHBOARD_HTML)
+}
+
+#[get("/dashboard/dashboard.css")]
+async fn dashboard_css() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/css; charset=utf-8")
+        .body(DASHBOARD_CSS)
+}
+
+#[get("/dashboard/dashboard.js")]
+async fn dashboard_js() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("application/javascript; charset=utf-8")
+        .body(DASHBOARD_JS)
+}
@@ -0,0 +1,109 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct MountUsage {
+    pub mount: String,
+    #[serde(default)]
+    pub device: String,
+    #[serde(default)]
+    pub fs_type: String,
+    pub size_bytes: u64,
+    pub used_bytes: u64,
+    /// free for unprivileged users
+    pub avail_bytes: u64,
+    #[serde(default)]
+    pub inodes_total: u64,
+    #[serde(default)]
+    pub inodes_used: u64,
+}
+
+impl MountUsage {
+    /// Share of the space unprivileged users can fill, as `df` shows it.
+    pub fn used_ratio(&self) -> f64 {
+        let usable = self.used_bytes + self.avail_bytes;
+        if usable == 0 {
+            0.0
+        } else {
+            self.used_bytes as f64 / usable as f64
+        }
+    }
+
+    pub fn inodes_ratio(&self) -> f64 {
+        if self.inodes_total == 0 {
+            0.0
+        } else {
+            self.inodes_used as f64 / self.inodes_total as f64
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct DiskIo {
+    pub device: String,
+    #[serde(default)]
+    pub read_bytes_per_sec: f64,
+    #[serde(default)]
+    pub write_bytes_per_sec: f64,
+    #[serde(default)]
+    pub reads_per_sec: f64,
+    #[serde(default)]
+    pub writes_per_sec: f64,
+    #[serde(default)]
+    pub utilization: f64,
+}
+
+/// The mount closest to running out of space or inodes.
+pub fn fullest(mounts: &[MountUsage]) -> Option<&MountUsage> {
+    mounts.iter().max_by(|a, b| {
+        let full = |m: &MountUsage| m.used_ratio().max(m.inodes_ratio());
+        full(a).total_cmp(&full(b))
+    })
+}
+
+/// "/home 93 % of 1.8 TiB", inodes are named when they are the tighter limit.
+pub fn describe_fullest(mounts: &[MountUsage]) -> String {
+    let m = match fullest(mounts) {
+        Some(m) => m,
+        None => return String::from("-"),
+    };
+    let size = m.size_bytes as f64 / GIB;
+    let size = if size >= 1024.0 {
+        format!("{:.1} TiB", size / 1024.0)
+    } else if size >= 10.0 {
+        format!("{:.0} GiB", size)
+    } else {
+        format!("{:.1} GiB", size)
+    };
+    if m.inodes_ratio() > m.used_ratio() {
+        format!("{} {:.0} % inodes", m.mount, m.inodes_ratio() * 100.0)
+    } else {
+        format!("{} {:.0} % of {}", m.mount, m.used_ratio() * 100.0, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn mount(name: &str, used: u64, avail: u64, inodes_used: u64) -> MountUsage {
+        MountUsage {
+            mount: name.to_string(),
+            size_bytes: (used + avail) << 30,
+            used_bytes: used << 30,
+            avail_bytes: avail << 30,
+            inodes_total: 100,
+            inodes_used,
+            ..Default::default()
+        }
+    }
+    #[test]
+    fn test_fullest() {
+        let mounts = vec![mount("/", 50, 50, 10), mount("/home", 93, 7, 10)];
+        assert_eq!(describe_fullest(&mounts), "/home 93 % of 100 GiB");
+        let mounts = vec![mount("/", 50, 50, 10), mount("/data", 10, 90, 99)];
+        assert_eq!(describe_fullest(&mounts), "/data 99 % inodes");
+        assert_eq!(describe_fullest(&[]), "-");
+    }
+}
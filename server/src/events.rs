@@ -0,0 +1,90 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Local;
+use log::error;
+use log::info;
+use redis::Commands;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::redis_connection;
+use crate::stream;
+use crate::ServerError;
+use crate::STATE_PREFIX;
+
+/// a redis list, newest first, the json blob of older versions was "events"
+const EVENTS_KEY: &str = "events:list";
+/// oldest events are dropped beyond this
+const MAX_EVENTS: usize = 5000;
+const DEFAULT_LIMIT: usize = 100;
+
+/// Something that happened to a host, e.g. a health change.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct HostEvent {
+    pub time: DateTime<Local>,
+    pub host: String,
+    pub kind: String,
+    pub message: String,
+}
+
+/// Store the event in the timeline and forward it to the stream listeners.
+pub fn record(host: &str, kind: &str, message: &str) {
+    info!("[{}] {}: {}", kind, host, message);
+    stream::publish_message(kind, host, message);
+    let event = HostEvent {
+        time: Local::now(),
+        host: host.to_string(),
+        kind: kind.to_string(),
+        message: message.to_string(),
+    };
+    if let Err(e) = push(&event) {
+        error!("save event error: {}", e);
+    }
+}
+
+fn push(event: &HostEvent) -> Result<(), ServerError> {
+    let mut con = redis_connection()?;
+    let key = format!("{}{}", STATE_PREFIX, EVENTS_KEY);
+    let _: () = con.lpush(&key, serde_json::to_string(event)?)?;
+    let _: () = con.ltrim(&key, 0, MAX_EVENTS as isize - 1)?;
+    Ok(())
+}
+
+fn load() -> Result<Vec<HostEvent>, ServerError> {
+    let mut con = redis_connection()?;
+    let key = format!("{}{}", STATE_PREFIX, EVENTS_KEY);
+    let events: Vec<String> = con.lrange(&key, 0, MAX_EVENTS as isize - 1)?;
+    events
+        .iter()
+        .map(|e| Ok(serde_json::from_str(e)?))
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct EventQuery {
+    host: Option<String>,
+    kind: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Timeline of host events, newest first.
+#[get("/api/v1/events")]
+async fn list_events(query: web::Query<EventQuery>) -> impl Responder {
+    let events = match load() {
+        Ok(e) => e,
+        Err(e) => {
+            error!("load events error: {}", e);
+            return HttpResponse::InternalServerError().body("load events failed");
+        }
+    };
+    let list: Vec<HostEvent> = events
+        .into_iter()
+        .filter(|e| query.host.as_ref().is_none_or(|h| &e.host == h))
+        .filter(|e| query.kind.as_ref().is_none_or(|k| &e.kind == k))
+        .take(query.limit.unwrap_or(DEFAULT_LIMIT))
+        .collect();
+    HttpResponse::Ok().json(list)
+}
@@ -0,0 +1,433 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use log::error;
+use prettytable::row;
+use prettytable::Table;
+use serde::Deserialize;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::query::parse_number;
+use crate::redis_database;
+use crate::render::Column;
+use crate::render::Format;
+use crate::render::InfoTable;
+use crate::ServerInfo;
+
+const MIB: f64 = 1024.0 * 1024.0;
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+/// candidates listed below the suggestion
+const MAX_CANDIDATES: usize = 20;
+
+#[derive(Deserialize)]
+pub struct FreeQuery {
+    count: Option<usize>,
+    /// "20GiB", "20000MiB" or plain bytes
+    min_mem: Option<String>,
+    /// case insensitive substring of the card name, e.g. "3090"
+    model: Option<String>,
+    same_host: Option<bool>,
+    /// highest utilization in percent a card may have
+    max_util: Option<f64>,
+    format: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Candidate {
+    pub host: String,
+    pub gpu: usize,
+    name: String,
+    memory_free: u64,
+    memory_total: u64,
+    utilization: f64,
+    pub processes: usize,
+    /// cards of the same host connected to this one by NVLink
+    nvlink_peers: Vec<usize>,
+}
+
+impl Candidate {
+    pub fn slot(&self) -> String {
+        format!("{}:{}", self.host, self.gpu)
+    }
+}
+
+/// Idle cards first, then the lowest utilization (in steps of 10 %), then the most free memory.
+fn rank(a: &Candidate, b: &Candidate) -> Ordering {
+    let util_step = |c: &Candidate| (c.utilization / 10.0).floor() as u64;
+    a.processes
+        .cmp(&b.processes)
+        .then(util_step(a).cmp(&util_step(b)))
+        .then(b.memory_free.cmp(&a.memory_free))
+        .then(a.host.cmp(&b.host))
+        .then(a.gpu.cmp(&b.gpu))
+}
+
+pub fn mib_to_bytes(input: &str) -> Option<u64> {
+    let value: f64 = input.trim().trim_end_matches("MiB").trim().parse().ok()?;
+    Some((value * MIB) as u64)
+}
+
+pub fn percent(input: &str) -> Option<f64> {
+    input.trim().trim_end_matches('%').trim().parse().ok()
+}
+
+/// Every card that satisfies the request, best first.
+pub fn candidates(
+    database: &BTreeMap<String, ServerInfo>,
+    min_mem: u64,
+    model: Option<&str>,
+    max_util: f64,
+) -> Vec<Candidate> {
+    let model = model.map(|m| m.to_lowercase());
+    let mut candidates = Vec::new();
+    for (hostname, server_info) in database {
+        for (gpu, detail) in server_info.gpu.details.iter().enumerate() {
+            if detail.name.is_empty() {
+                continue;
+            }
+            // a card split into MIG devices can not be handed out as a whole
+            if matches!(&detail.metrics, Some(m) if m.mig_mode == "Enabled") {
+                continue;
+            }
+            // "Err" in any value means the card can not be trusted
+            let (memory_free, memory_total, utilization) = match (
+                mib_to_bytes(&detail.memory_free),
+                mib_to_bytes(&detail.memory_total),
+                percent(&detail.utilization_gpu),
+            ) {
+                (Some(f), Some(t), Some(u)) => (f, t, u),
+                _ => continue,
+            };
+            if memory_free < min_mem || utilization > max_util {
+                continue;
+            }
+            if let Some(m) = &model {
+                if !detail.name.to_lowercase().contains(m.as_str()) {
+                    continue;
+                }
+            }
+            let processes = server_info
+                .gpu
+                .processes
+                .iter()
+                .filter(|p| p.gpu_index == gpu)
+                .count();
+            candidates.push(Candidate {
+                host: hostname.clone(),
+                gpu,
+                name: detail.name.clone(),
+                memory_free,
+                memory_total,
+                utilization,
+                processes,
+                nvlink_peers: match &server_info.gpu.topology {
+                    Some(t) => t.nvlink_peers(gpu),
+                    None => Vec::new(),
+                },
+            });
+        }
+    }
+    candidates.sort_by(rank);
+    candidates
+}
+
+fn all_linked(cards: &[&Candidate]) -> bool {
+    cards.len() > 1
+        && cards.iter().enumerate().all(|(i, a)| {
+            cards[i + 1..]
+                .iter()
+                .all(|b| a.host == b.host && a.nvlink_peers.contains(&b.gpu))
+        })
+}
+
+/// The best `count` cards of a host that are all connected by NVLink.
+fn linked_group<'a>(cards: &[&'a Candidate], count: usize) -> Option<Vec<&'a Candidate>> {
+    for (i, first) in cards.iter().enumerate() {
+        let mut group = vec![*first];
+        for c in &cards[i + 1..] {
+            if group.len() == count {
+                break;
+            }
+            if group.iter().all(|g| g.nvlink_peers.contains(&c.gpu)) {
+                group.push(c);
+            }
+        }
+        if group.len() == count {
+            return Some(group);
+        }
+    }
+    None
+}
+
+/// Pick `count` cards, all on one host if asked to.
+pub fn choose(candidates: &[Candidate], count: usize, same_host: bool) -> Vec<Candidate> {
+    if !same_host {
+        if candidates.len() < count {
+            return Vec::new();
+        }
+        let best = candidates[..count].to_vec();
+        // a host with an NVLink group beats the best cards spread over PCIe or the network
+        let grouped = match count > 1 {
+            true => choose(candidates, count, true),
+            false => Vec::new(),
+        };
+        let processes = |cards: &[Candidate]| cards.iter().map(|c| c.processes).sum::<usize>();
+        if all_linked(&grouped.iter().collect::<Vec<&Candidate>>())
+            && processes(&grouped) <= processes(&best)
+        {
+            return grouped;
+        }
+        return best;
+    }
+    let mut per_host: BTreeMap<&str, Vec<&Candidate>> = BTreeMap::new();
+    for c in candidates {
+        per_host.entry(&c.host).or_default().push(c);
+    }
+    let busy = |cards: &Vec<&Candidate>| cards.iter().map(|c| c.processes).sum::<usize>();
+    // candidates are sorted, so the first `count` of a host are its best cards
+    per_host
+        .into_values()
+        .filter(|cards| cards.len() >= count)
+        .map(|cards| {
+            let best = cards[..count].to_vec();
+            // cards talking over NVLink beat slightly better cards talking over PCIe
+            match linked_group(&cards, count) {
+                Some(linked) if count > 1 && busy(&linked) <= busy(&best) => linked,
+                _ => best,
+            }
+        })
+        // fewest busy cards, then NVLink, then the better of the weakest cards
+        .min_by(|a, b| {
+            busy(a)
+                .cmp(&busy(b))
+                .then(all_linked(b).cmp(&all_linked(a)))
+                .then(rank(a[count - 1], b[count - 1]))
+                .then(rank(a[0], b[0]))
+        })
+        .map(|cards| cards.into_iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct FreeResponse {
+    found: bool,
+    /// all chosen cards are connected by NVLink
+    nvlink: bool,
+    suggestion: Vec<String>,
+    chosen: Vec<Candidate>,
+    candidates: Vec<Candidate>,
+}
+
+fn render_text(description: &str, response: &FreeResponse) -> String {
+    let mut output = format!(">> {}\n", description);
+    if response.found {
+        output += &format!("{}\n", response.suggestion.join(" "));
+        let mut per_host: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for c in &response.chosen {
+            per_host.entry(&c.host).or_default().push(c.gpu.to_string());
+        }
+        // nvidia-smi counts in PCI bus order, CUDA puts the fastest card first by default
+        for (host, gpus) in per_host {
+            output += &format!(
+                ">> {}: CUDA_DEVICE_ORDER=PCI_BUS_ID CUDA_VISIBLE_DEVICES={}\n",
+                host,
+                gpus.join(",")
+            );
+        }
+        if response.nvlink {
+            output += ">> connected by NVLink\n";
+        }
+    } else {
+        output += ">> nothing free right now\n";
+    }
+    if !response.candidates.is_empty() {
+        let mut table = Table::new();
+        table.add_row(row![
+            c -> "slot",
+            c -> "gpu device",
+            c -> "free",
+            c -> "gpu@u",
+            c -> "procs"
+        ]);
+        for c in &response.candidates {
+            table.add_row(row![
+                c.slot(),
+                c.name,
+                r -> format!("{:.1}/{:.1} GiB", c.memory_free as f64 / GIB, c.memory_total as f64 / GIB),
+                r -> format!("{:.0} %", c.utilization),
+                r -> c.processes
+            ]);
+        }
+        output += &table.to_string();
+    }
+    output
+}
+
+/// The candidates for csv, markdown and html, chosen cards are marked.
+fn render_candidates(response: &FreeResponse, format: Format) -> String {
+    let column = |key, title| Column {
+        key,
+        title,
+        note: None,
+        list: false,
+    };
+    let mut table = InfoTable::new(vec![
+        column("slot", "slot"),
+        column("name", "gpu device"),
+        column("memory_free", "free"),
+        column("utilization", "gpu@u"),
+        column("processes", "procs"),
+        column("chosen", "chosen"),
+    ]);
+    for c in &response.candidates {
+        let chosen = response.suggestion.contains(&c.slot());
+        table.add_row(vec![
+            c.slot(),
+            c.name.clone(),
+            format!(
+                "{:.1}/{:.1} GiB",
+                c.memory_free as f64 / GIB,
+                c.memory_total as f64 / GIB
+            ),
+            format!("{:.0} %", c.utilization),
+            c.processes.to_string(),
+            String::from(if chosen { "yes" } else { "" }),
+        ]);
+    }
+    table.render(format)
+}
+
+#[get("/api/v1/gpus/free")]
+async fn free_gpus(req: HttpRequest, query: web::Query<FreeQuery>) -> impl Responder {
+    let format = match Format::negotiate(&req, query.format.as_deref()) {
+        Ok(f) => f,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let min_mem = match &query.min_mem {
+        Some(m) => match parse_number(m.trim()) {
+            Some(n) => n as u64,
+            None => {
+                return HttpResponse::BadRequest().body(format!("invalid min_mem: {}", m));
+            }
+        },
+        None => 0,
+    };
+    let count = query.count.unwrap_or(1).max(1);
+    let same_host = query.same_host.unwrap_or(false);
+    let max_util = query.max_util.unwrap_or(100.0);
+    let database = match redis_database() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("get redis database failed: {}", e);
+            return HttpResponse::Ok().body("get redis database failed");
+        }
+    };
+    let candidates = candidates(&database, min_mem, query.model.as_deref(), max_util);
+    let chosen = choose(&candidates, count, same_host);
+    let response = FreeResponse {
+        found: !chosen.is_empty(),
+        nvlink: all_linked(&chosen.iter().collect::<Vec<&Candidate>>()),
+        suggestion: chosen.iter().map(|c| c.slot()).collect(),
+        chosen,
+        candidates: candidates.into_iter().take(MAX_CANDIDATES).collect(),
+    };
+    match format {
+        Format::Json => HttpResponse::Ok().json(response),
+        Format::Csv | Format::Markdown | Format::Html => HttpResponse::Ok()
+            .content_type(format.content_type())
+            .body(render_candidates(&response, format)),
+        Format::Table => {
+            let mut description = format!(
+                "{} GPU(s) with >= {:.1} GiB free",
+                count,
+                min_mem as f64 / GIB
+            );
+            if let Some(m) = &query.model {
+                description += &format!(", model ~ {}", m);
+            }
+            if same_host {
+                description += ", on the same host";
+            }
+            HttpResponse::Ok()
+                .content_type("text/plain; charset=utf-8")
+                .body(render_text(&description, &response))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn candidate(host: &str, gpu: usize, free_gib: u64, processes: usize) -> Candidate {
+        Candidate {
+            host: host.to_string(),
+            gpu,
+            name: String::from("NVIDIA GeForce RTX 3090"),
+            memory_free: free_gib * GIB as u64,
+            memory_total: 24 * GIB as u64,
+            utilization: 0.0,
+            processes,
+            nvlink_peers: Vec::new(),
+        }
+    }
+    #[test]
+    fn test_choose() {
+        let mut candidates = vec![
+            candidate("node1", 0, 20, 0),
+            candidate("node2", 0, 24, 0),
+            candidate("node2", 1, 10, 1),
+            candidate("node1", 1, 18, 0),
+        ];
+        candidates.sort_by(rank);
+        let slots = |c: Vec<Candidate>| c.iter().map(|c| c.slot()).collect::<Vec<String>>();
+        assert_eq!(
+            slots(choose(&candidates, 2, false)),
+            vec!["node2:0", "node1:0"]
+        );
+        assert_eq!(
+            slots(choose(&candidates, 2, true)),
+            vec!["node1:0", "node1:1"]
+        );
+        assert!(choose(&candidates, 3, true).is_empty());
+    }
+    #[test]
+    fn test_choose_nvlink() {
+        // 0-1 and 2-3 are NVLink pairs, 0 and 2 are the best cards
+        let peers = [1, 0, 3, 2];
+        let mut candidates: Vec<Candidate> = [24, 20, 23, 22]
+            .iter()
+            .enumerate()
+            .map(|(gpu, free)| Candidate {
+                nvlink_peers: vec![peers[gpu]],
+                ..candidate("node1", gpu, *free, 0)
+            })
+            .collect();
+        candidates.sort_by(rank);
+        let slots = |c: Vec<Candidate>| c.iter().map(|c| c.slot()).collect::<Vec<String>>();
+        assert_eq!(
+            slots(choose(&candidates, 2, true)),
+            vec!["node1:0", "node1:1"]
+        );
+        assert_eq!(
+            slots(choose(&candidates, 2, false)),
+            vec!["node1:0", "node1:1"]
+        );
+        // an idle pair over PCIe beats a busy NVLink pair
+        if let Some(c) = candidates.iter_mut().find(|c| c.gpu == 1) {
+            c.processes = 1;
+        }
+        candidates.sort_by(rank);
+        assert_eq!(
+            slots(choose(&candidates, 2, true)),
+            vec!["node1:0", "node1:2"]
+        );
+        assert_eq!(
+            slots(choose(&candidates, 2, false)),
+            vec!["node1:0", "node1:2"]
+        );
+    }
+}
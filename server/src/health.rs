@@ -0,0 +1,329 @@
+use actix_web::get;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Local;
+use log::error;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::events;
+use crate::host_ttl;
+use crate::kernel;
+use crate::nvidia;
+use crate::ServerInfo;
+
+/// collectors a host is of no use without
+const CORE_COLLECTORS: [&str; 3] = ["cpu", "mem", "gpu"];
+
+static HEALTH: OnceCell<Mutex<BTreeMap<String, HostHealth>>> = OnceCell::new();
+
+/// Outcome of one client collector, as reported with every update.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum CollectorStatus {
+    Ok,
+    Disabled,
+    Unavailable {
+        #[serde(default)]
+        message: String,
+    },
+    DriverFailed {
+        #[serde(default)]
+        message: String,
+    },
+    Error {
+        #[serde(default)]
+        message: String,
+    },
+    /// sent by a newer client
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Health {
+    Healthy,
+    /// some values are missing, the host still works
+    Degraded,
+    GpuDriverFailed,
+    /// a core collector (cpu, mem, gpu) failed
+    CollectorError,
+    /// no update for longer than `--stale-after`
+    Stale,
+    /// no update for three intervals, the redis expiry, the host left `/info`
+    Offline,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Health::Healthy => "healthy",
+            Health::Degraded => "degraded",
+            Health::GpuDriverFailed => "gpu-driver-failed",
+            Health::CollectorError => "collector-error",
+            Health::Stale => "stale",
+            Health::Offline => "offline",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HostHealth {
+    pub host: String,
+    pub state: Health,
+    /// why the host is not healthy
+    pub reason: String,
+    pub since: DateTime<Local>,
+    #[serde(skip)]
+    last_update: Instant,
+}
+
+fn health() -> &'static Mutex<BTreeMap<String, HostHealth>> {
+    HEALTH.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Health of a host judged from one update alone.
+pub fn assess(server_info: &ServerInfo) -> (Health, String) {
+    let collectors = &server_info.collectors;
+    if let Some(CollectorStatus::DriverFailed { message }) = collectors.get("gpu") {
+        return (Health::GpuDriverFailed, message.clone());
+    }
+    // clients before the collector statuses sent this as a gpu user
+    if server_info
+        .gpu
+        .users
+        .iter()
+        .any(|u| u.contains("driver failed"))
+    {
+        return (Health::GpuDriverFailed, String::from("driver failed"));
+    }
+    let failed: Vec<(&String, &String)> = collectors
+        .iter()
+        .filter_map(|(name, status)| match status {
+            CollectorStatus::Error { message } => Some((name, message)),
+            _ => None,
+        })
+        .collect();
+    let describe = |failed: &[&(&String, &String)]| -> String {
+        failed
+            .iter()
+            .map(|(name, message)| format!("{}: {}", name, message))
+            .collect::<Vec<String>>()
+            .join("; ")
+    };
+    let core: Vec<&(&String, &String)> = failed
+        .iter()
+        .filter(|(name, _)| CORE_COLLECTORS.contains(&name.as_str()))
+        .collect();
+    if !core.is_empty() {
+        return (Health::CollectorError, describe(&core));
+    }
+    if !failed.is_empty() {
+        return (
+            Health::Degraded,
+            describe(&failed.iter().collect::<Vec<_>>()),
+        );
+    }
+    let broken_cards: Vec<String> = server_info
+        .gpu
+        .details
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| {
+            [
+                &d.utilization_gpu,
+                &d.utilization_memory,
+                &d.memory_total,
+                &d.memory_free,
+                &d.memory_used,
+            ]
+            .iter()
+            .any(|v| v.as_str() == "Err")
+        })
+        .map(|(i, _)| i.to_string())
+        .collect();
+    if !broken_cards.is_empty() {
+        return (
+            Health::Degraded,
+            format!("unreadable values on gpu {}", broken_cards.join(",")),
+        );
+    }
+    let card_problems: Vec<String> = server_info
+        .gpu
+        .details
+        .iter()
+        .enumerate()
+        .filter_map(|(i, d)| {
+            let problems = nvidia::problems(d.metrics.as_ref()?);
+            if problems.is_empty() {
+                None
+            } else {
+                Some(format!("gpu {}: {}", i, problems.join(", ")))
+            }
+        })
+        .collect();
+    if !card_problems.is_empty() {
+        return (Health::Degraded, card_problems.join("; "));
+    }
+    let kernel_problems = kernel::problems(&server_info.kernel);
+    if !kernel_problems.is_empty() {
+        return (Health::Degraded, kernel_problems.join("; "));
+    }
+    (Health::Healthy, String::new())
+}
+
+/// A change of state to record once the health lock is released.
+struct Transition {
+    host: String,
+    kind: &'static str,
+    message: String,
+}
+
+impl Transition {
+    fn record(&self) {
+        events::record(&self.host, self.kind, &self.message);
+    }
+}
+
+fn transition(host_health: &mut HostHealth, state: Health, reason: String) -> Option<Transition> {
+    if host_health.state == state {
+        host_health.reason = reason;
+        return None;
+    }
+    let mut message = format!("{} -> {}", host_health.state, state);
+    if !reason.is_empty() {
+        message += &format!(" ({})", reason);
+    }
+    let kind = if state == Health::Offline {
+        "offline"
+    } else {
+        "health"
+    };
+    host_health.state = state;
+    host_health.reason = reason;
+    host_health.since = Local::now();
+    Some(Transition {
+        host: host_health.host.clone(),
+        kind,
+        message,
+    })
+}
+
+/// Called on every update.
+pub fn observe(server_info: &ServerInfo) {
+    let (state, reason) = assess(server_info);
+    let mut health = match health().lock() {
+        Ok(h) => h,
+        Err(_) => return,
+    };
+    match health.get_mut(&server_info.hostname) {
+        Some(h) => {
+            h.last_update = Instant::now();
+            let change = transition(h, state, reason);
+            // recording talks to redis, the watch must not wait for that
+            drop(health);
+            if let Some(change) = change {
+                change.record();
+            }
+        }
+        None => {
+            health.insert(
+                server_info.hostname.clone(),
+                HostHealth {
+                    host: server_info.hostname.clone(),
+                    state,
+                    reason,
+                    since: Local::now(),
+                    last_update: Instant::now(),
+                },
+            );
+        }
+    }
+}
+
+/// Move hosts without updates to stale and later to offline.
+pub fn start_watch(stale_after: u64) {
+    let stale_after = Duration::from_secs(stale_after.max(1));
+    // offline when the host drops out of redis
+    let offline_after = host_ttl();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            ticker.tick().await;
+            let mut changes = Vec::new();
+            if let Ok(mut health) = health().lock() {
+                for h in health.values_mut() {
+                    let silent = h.last_update.elapsed();
+                    let reason = format!("no update for {} s", silent.as_secs());
+                    if silent > offline_after && h.state != Health::Offline {
+                        changes.extend(transition(h, Health::Offline, reason));
+                    } else if silent > stale_after
+                        && silent <= offline_after
+                        && h.state != Health::Stale
+                    {
+                        changes.extend(transition(h, Health::Stale, reason));
+                    }
+                }
+            }
+            if changes.is_empty() {
+                continue;
+            }
+            let recorded = tokio::task::spawn_blocking(move || {
+                for change in changes {
+                    change.record();
+                }
+            });
+            if let Err(e) = recorded.await {
+                error!("record health events error: {}", e);
+            }
+        }
+    });
+}
+
+/// Current state of the host for `/info`.
+pub fn state(host: &str) -> Option<Health> {
+    health().lock().ok()?.get(host).map(|h| h.state)
+}
+
+#[get("/api/v1/health")]
+async fn health_api() -> impl Responder {
+    let list: Vec<HostHealth> = match health().lock() {
+        Ok(h) => h.values().cloned().collect(),
+        Err(_) => Vec::new(),
+    };
+    HttpResponse::Ok().json(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn server_info(collectors: &str) -> ServerInfo {
+        let json = format!(
+            r#"{{"gpu": {{"details": [], "users": []}}, "hostname": "node41", "net": {{}},
+            "mem": {{}}, "swap": {{}}, "cpu": {{}}, "other": {{}}, "collectors": {}}}"#,
+            collectors
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+    #[test]
+    fn test_assess() {
+        let ok = server_info(r#"{"cpu": {"status": "ok"}, "cpu_temp": {"status": "unavailable"}}"#);
+        assert_eq!(assess(&ok).0, Health::Healthy);
+        let driver = server_info(r#"{"gpu": {"status": "driver_failed", "message": "x"}}"#);
+        assert_eq!(assess(&driver).0, Health::GpuDriverFailed);
+        let mem = server_info(r#"{"mem": {"status": "error", "message": "x"}}"#);
+        assert_eq!(assess(&mem).0, Health::CollectorError);
+        let net =
+            server_info(r#"{"net": {"status": "error", "message": "x"}, "x": {"status": "new"}}"#);
+        assert_eq!(assess(&net).0, Health::Degraded);
+    }
+}
@@ -0,0 +1,221 @@
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Local;
+use chrono::NaiveDate;
+use log::error;
+use once_cell::sync::OnceCell;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use crate::redis_load;
+use crate::redis_save;
+use crate::reservation::parse_time;
+use crate::update_interval;
+use crate::ServerError;
+
+/// an entry not seen for this many update intervals has ended
+const GONE_AFTER_INTERVALS: u32 = 3;
+/// longest search range (days)
+const MAX_SEARCH_DAYS: i64 = 366;
+
+/// Something a host reports from one update to a later one, a gpu job or a login.
+pub trait Tracked: Serialize + DeserializeOwned + Clone {
+    type Key: Hash + Eq + Clone;
+    fn key(&self) -> Self::Key;
+    fn host(&self) -> &str;
+    fn started(&self) -> DateTime<Local>;
+    fn last_seen(&self) -> DateTime<Local>;
+    fn set_running(&mut self, running: bool);
+
+    fn overlaps(&self, from: DateTime<Local>, to: DateTime<Local>) -> bool {
+        self.started() <= to && from <= self.last_seen()
+    }
+}
+
+/// The running entries, kept across server restarts, and the ended ones by day.
+pub struct History<T: Tracked> {
+    /// prefix of the redis keys, e.g. "jobs"
+    name: &'static str,
+    running: OnceCell<Mutex<HashMap<T::Key, T>>>,
+    /// serializes the read-modify-write of a day of ended entries
+    day_lock: Mutex<()>,
+}
+
+impl<T: Tracked> History<T> {
+    pub const fn new(name: &'static str) -> Self {
+        History {
+            name,
+            running: OnceCell::new(),
+            day_lock: Mutex::new(()),
+        }
+    }
+
+    fn running_key(&self) -> String {
+        format!("{}:running", self.name)
+    }
+
+    fn day_key(&self, day: NaiveDate) -> String {
+        format!("{}:{}", self.name, day.format("%Y-%m-%d"))
+    }
+
+    pub fn running(&self) -> &Mutex<HashMap<T::Key, T>> {
+        self.running.get_or_init(|| {
+            // a list, json has no tuple keys
+            let entries: Vec<T> = match redis_load(&self.running_key()) {
+                Ok(e) => e,
+                Err(e) => {
+                    error!("load running {} error: {}", self.name, e);
+                    Vec::new()
+                }
+            };
+            Mutex::new(entries.into_iter().map(|e| (e.key(), e)).collect())
+        })
+    }
+
+    /// Removes the entries gone from `host`, unless it could not list them,
+    /// and those of hosts that stopped sending updates.
+    pub fn end_gone(
+        &self,
+        running: &mut HashMap<T::Key, T>,
+        host: &str,
+        now: DateTime<Local>,
+        listed: bool,
+    ) -> Vec<T> {
+        let gone_after = Duration::from_std(update_interval() * GONE_AFTER_INTERVALS)
+            .unwrap_or(Duration::minutes(3));
+        let gone: Vec<T::Key> = running
+            .iter()
+            .filter(|(_, e)| {
+                (e.host() == host && listed && e.last_seen() != now)
+                    || now - e.last_seen() > gone_after
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        gone.iter().filter_map(|key| running.remove(key)).collect()
+    }
+
+    /// Archives the ended entries, then saves the running ones. A crash in
+    /// between duplicates an entry rather than losing it.
+    pub fn save(&self, running: &HashMap<T::Key, T>, ended: Vec<T>) {
+        if !ended.is_empty() {
+            if let Err(e) = self.archive(ended) {
+                error!("save {} history error: {}", self.name, e);
+            }
+        }
+        if let Err(e) = redis_save(&self.running_key(), &running.values().collect::<Vec<&T>>()) {
+            error!("save running {} error: {}", self.name, e);
+        }
+    }
+
+    /// Ended entries are stored under the day they were last seen.
+    fn archive(&self, ended: Vec<T>) -> Result<(), ServerError> {
+        let _guard = self.day_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut per_day: HashMap<NaiveDate, Vec<T>> = HashMap::new();
+        for mut entry in ended {
+            entry.set_running(false);
+            per_day
+                .entry(entry.last_seen().date_naive())
+                .or_default()
+                .push(entry);
+        }
+        for (day, entries) in per_day {
+            let mut history: Vec<T> = redis_load(&self.day_key(day))?;
+            history.extend(entries);
+            redis_save(&self.day_key(day), &history)?;
+        }
+        Ok(())
+    }
+
+    /// Takes the entries `continues` matches out of the recent history, they run again.
+    pub fn resume(
+        &self,
+        now: DateTime<Local>,
+        continues: impl Fn(&T) -> bool,
+    ) -> Result<Vec<T>, ServerError> {
+        let _guard = self.day_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let today = now.date_naive();
+        let mut resumed = Vec::new();
+        // archived under the day last seen, which is at most a few intervals ago
+        for day in [today.pred_opt(), Some(today)].into_iter().flatten() {
+            let mut history: Vec<T> = redis_load(&self.day_key(day))?;
+            let before = history.len();
+            history.retain(|entry| {
+                if continues(entry) {
+                    resumed.push(entry.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+            if history.len() != before {
+                redis_save(&self.day_key(day), &history)?;
+            }
+        }
+        for entry in &mut resumed {
+            entry.set_running(true);
+        }
+        Ok(resumed)
+    }
+
+    /// Running and archived entries between `from` (7 days ago by default) and `to` (now).
+    pub fn search(&self, from: Option<&str>, to: Option<&str>) -> Result<Vec<T>, String> {
+        let now = Local::now();
+        let (from, to) = range(from, to, now)?;
+        let mut entries: Vec<T> = match self.running().lock() {
+            Ok(r) => r.values().cloned().collect(),
+            Err(_) => Vec::new(),
+        };
+        // an entry is archived under its last day, which may be after `to`
+        let last_day = now.date_naive().max(to.date_naive());
+        for day in from.date_naive().iter_days().take_while(|d| *d <= last_day) {
+            match redis_load::<Vec<T>>(&self.day_key(day)) {
+                Ok(history) => entries.extend(history),
+                Err(e) => {
+                    error!("load {} history error: {}", self.name, e);
+                    return Err(format!("load {} history failed", self.name));
+                }
+            }
+        }
+        entries.retain(|e| e.overlaps(from, to));
+        Ok(entries)
+    }
+}
+
+fn range(
+    from: Option<&str>,
+    to: Option<&str>,
+    now: DateTime<Local>,
+) -> Result<(DateTime<Local>, DateTime<Local>), String> {
+    let from = match from {
+        Some(f) => parse_time(f).ok_or(format!("invalid from: {}", f))?,
+        None => now - Duration::days(7),
+    };
+    let to = match to {
+        Some(t) => parse_time(t).ok_or(format!("invalid to: {}", t))?,
+        None => now,
+    };
+    if to < from || (to - from).num_days() > MAX_SEARCH_DAYS {
+        return Err(String::from(
+            "from must be before to, at most one year apart",
+        ));
+    }
+    Ok((from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_range() {
+        let now = parse_time("2024-05-08 12:00").unwrap();
+        let (from, to) = range(None, None, now).unwrap();
+        assert_eq!(to - from, Duration::days(7));
+        assert!(range(Some("2024-05-01"), Some("2024-05-07"), now).is_ok());
+        assert!(range(Some("2024-05-07"), Some("2024-05-01"), now).is_err());
+        assert!(range(Some("2022-05-01"), None, now).is_err());
+        assert!(range(Some("yesterday"), None, now).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Local;
+use log::info;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::alert;
+use crate::alert::Alert;
+use crate::free::mib_to_bytes;
+use crate::free::percent;
+use crate::notify;
+use crate::notify::Notification;
+use crate::update_interval;
+use crate::ServerInfo;
+use crate::HOST_TTL_INTERVALS;
+
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+static CONFIG: OnceCell<IdleConfig> = OnceCell::new();
+/// gpus that hold memory at low utilization, by host and gpu
+static IDLE: OnceCell<Mutex<BTreeMap<(String, usize), IdleGpu>>> = OnceCell::new();
+
+struct IdleConfig {
+    /// percent, a card below it counts as idle
+    max_util: f64,
+    /// a card idle for this long gets flagged
+    period: Duration,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct IdleGpu {
+    pub host: String,
+    pub gpu: usize,
+    pub gpu_name: String,
+    pub users: Vec<String>,
+    pub pids: Vec<u32>,
+    /// bytes held by the processes
+    pub memory: u64,
+    pub utilization: f64,
+    pub idle_since: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+    /// idle for longer than the configured period
+    pub flagged: bool,
+}
+
+impl IdleGpu {
+    fn alert_key(&self) -> String {
+        format!(
+            "idle:{}:{}:{}",
+            self.host,
+            self.gpu,
+            self.idle_since.timestamp()
+        )
+    }
+
+    /// "3h12m"
+    pub fn idle_for(&self) -> String {
+        let minutes = (self.last_seen - self.idle_since).num_minutes();
+        format!("{}h{:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// Threshold in percent and period in minutes.
+pub fn configure(max_util: f64, period: i64) {
+    info!(
+        "gpus below {} % utilization for {} min while holding memory are idle",
+        max_util, period
+    );
+    let _ = CONFIG.set(IdleConfig {
+        max_util,
+        period: Duration::minutes(period.max(1)),
+    });
+}
+
+fn config() -> &'static IdleConfig {
+    CONFIG.get_or_init(|| IdleConfig {
+        max_util: 5.0,
+        period: Duration::minutes(60),
+    })
+}
+
+fn idle() -> &'static Mutex<BTreeMap<(String, usize), IdleGpu>> {
+    IDLE.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Drops the cards of hosts that stopped sending updates, once they left redis.
+fn forget(
+    idle: &mut BTreeMap<(String, usize), IdleGpu>,
+    now: DateTime<Local>,
+    interval: std::time::Duration,
+) {
+    let forget_after =
+        Duration::from_std(interval * HOST_TTL_INTERVALS).unwrap_or(Duration::minutes(3));
+    idle.retain(|_, i| now - i.last_seen < forget_after);
+}
+
+/// Called on every update, flags the cards of the host that stayed idle for too long.
+pub fn check(server_info: &ServerInfo) {
+    let config = config();
+    let now = Local::now();
+    let host = &server_info.hostname;
+    let mut flagged = Vec::new();
+    {
+        let mut idle = match idle().lock() {
+            Ok(i) => i,
+            Err(_) => return,
+        };
+        forget(&mut idle, now, update_interval());
+        for (gpu, detail) in server_info.gpu.details.iter().enumerate() {
+            let key = (host.clone(), gpu);
+            let processes: Vec<_> = server_info
+                .gpu
+                .processes
+                .iter()
+                .filter(|p| p.gpu_index == gpu)
+                .collect();
+            let memory: u64 = processes
+                .iter()
+                .filter_map(|p| mib_to_bytes(&p.used_memory))
+                .sum();
+            let utilization = percent(&detail.utilization_gpu);
+            let is_idle = memory > 0 && utilization.is_some_and(|u| u < config.max_util);
+            if !is_idle {
+                if let Some(i) = idle.remove(&key) {
+                    alert::resolve(&i.alert_key());
+                }
+                continue;
+            }
+            let mut users: Vec<String> = processes.iter().map(|p| p.user.clone()).collect();
+            users.sort();
+            users.dedup();
+            let entry = idle.entry(key).or_insert_with(|| IdleGpu {
+                host: host.clone(),
+                gpu,
+                gpu_name: detail.name.clone(),
+                users: Vec::new(),
+                pids: Vec::new(),
+                memory: 0,
+                utilization: 0.0,
+                idle_since: now,
+                last_seen: now,
+                flagged: false,
+            });
+            entry.users = users;
+            entry.pids = processes.iter().map(|p| p.pid).collect();
+            entry.memory = memory;
+            entry.utilization = utilization.unwrap_or(0.0);
+            entry.last_seen = now;
+            if !entry.flagged && now - entry.idle_since >= config.period {
+                entry.flagged = true;
+                flagged.push(entry.clone());
+            }
+        }
+    }
+    for i in flagged {
+        let message = format!(
+            "gpu {} on {} holds {:.1} GiB at {:.0} % utilization for {} (pid {}), please free it if you are done",
+            i.gpu,
+            i.host,
+            i.memory as f64 / GIB,
+            i.utilization,
+            i.idle_for(),
+            i.pids
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        );
+        let users = i.users.join(",");
+        if alert::raise(
+            &i.alert_key(),
+            Alert::new("idle", &i.host, &users, &message),
+        ) {
+            for user in &i.users {
+                notify::notify_user(
+                    user,
+                    Notification {
+                        kind: String::from("idle_gpu"),
+                        user: user.clone(),
+                        subject: format!("idle gpu {}:{}", i.host, i.gpu),
+                        message: message.clone(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Flagged cards of the host by gpu index, for `/info`.
+pub fn flagged(host: &str) -> BTreeMap<usize, IdleGpu> {
+    match idle().lock() {
+        Ok(idle) => idle
+            .values()
+            .filter(|i| i.flagged && i.host == host)
+            .map(|i| (i.gpu, i.clone()))
+            .collect(),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IdleQuery {
+    /// also list cards that are idle but not yet for the whole period
+    all: Option<bool>,
+    user: Option<String>,
+}
+
+#[get("/api/v1/gpus/idle")]
+async fn idle_gpus(query: web::Query<IdleQuery>) -> impl Responder {
+    let all = query.all.unwrap_or(false);
+    let list: Vec<IdleGpu> = match idle().lock() {
+        Ok(idle) => idle
+            .values()
+            .filter(|i| all || i.flagged)
+            .filter(|i| query.user.as_ref().is_none_or(|u| i.users.contains(u)))
+            .cloned()
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    HttpResponse::Ok().json(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_forget() {
+        let now = Local::now();
+        let gpu = |host: &str, last_seen: DateTime<Local>| IdleGpu {
+            host: host.to_string(),
+            gpu: 0,
+            gpu_name: String::new(),
+            users: Vec::new(),
+            pids: Vec::new(),
+            memory: 0,
+            utilization: 0.0,
+            idle_since: now - Duration::hours(2),
+            last_seen,
+            flagged: false,
+        };
+        let mut idle = BTreeMap::new();
+        idle.insert(
+            (String::from("node1"), 0),
+            gpu("node1", now - Duration::seconds(200)),
+        );
+        idle.insert(
+            (String::from("node2"), 0),
+            gpu("node2", now - Duration::seconds(700)),
+        );
+        // clients pushing every 180 s are a single update behind after 200 s
+        forget(&mut idle, now, std::time::Duration::from_secs(180));
+        assert_eq!(idle.len(), 1);
+        assert!(idle.contains_key(&(String::from("node1"), 0)));
+        forget(&mut idle, now, std::time::Duration::from_secs(60));
+        assert!(idle.is_empty());
+    }
+}
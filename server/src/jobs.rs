@@ -0,0 +1,336 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::free::mib_to_bytes;
+use crate::free::percent;
+use crate::health::CollectorStatus;
+use crate::history::History;
+use crate::history::Tracked;
+use crate::query;
+use crate::query::Record;
+use crate::query::Value;
+use crate::render::Column;
+use crate::render::Format;
+use crate::render::InfoTable;
+use crate::workload;
+use crate::ServerInfo;
+
+const DEFAULT_LIMIT: usize = 200;
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// host, gpu and pid
+type JobKey = (String, usize, u32);
+
+static JOBS: History<Job> = History::new("jobs");
+
+/// One process on one gpu, from the first to the last update it showed up in.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Job {
+    pub host: String,
+    pub gpu: usize,
+    pub gpu_name: String,
+    pub pid: u32,
+    pub user: String,
+    pub cmdline: String,
+    pub cwd: String,
+    /// "job 12345 (alice)" or "docker trainer (alice)", empty for a plain process
+    #[serde(default)]
+    pub workload: String,
+    pub first_seen: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+    /// bytes
+    pub peak_memory: u64,
+    /// utilization of the card in percent, averaged over the updates
+    pub average_utilization: f64,
+    pub samples: u64,
+    #[serde(default)]
+    pub running: bool,
+}
+
+impl Tracked for Job {
+    type Key = JobKey;
+
+    fn key(&self) -> JobKey {
+        (self.host.clone(), self.gpu, self.pid)
+    }
+
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn started(&self) -> DateTime<Local> {
+        self.first_seen
+    }
+
+    fn last_seen(&self) -> DateTime<Local> {
+        self.last_seen
+    }
+
+    fn set_running(&mut self, running: bool) {
+        self.running = running;
+    }
+}
+
+impl Record for Job {
+    const FIELDS: &'static [&'static str] = &[
+        "host", "user", "gpu", "gpu.name", "pid", "cmd", "cwd", "workload", "mem", "util",
+        "duration", "running",
+    ];
+
+    fn values(&self, field: &str) -> Vec<Value> {
+        let value = match field {
+            "host" => Value::Text(self.host.clone()),
+            "user" => Value::Text(self.user.clone()),
+            "gpu" => Value::Number(self.gpu as f64),
+            "gpu.name" => Value::Text(self.gpu_name.clone()),
+            "pid" => Value::Number(self.pid as f64),
+            "cmd" => Value::Text(self.cmdline.clone()),
+            "cwd" => Value::Text(self.cwd.clone()),
+            "workload" => Value::Text(self.workload.clone()),
+            "mem" => Value::Number(self.peak_memory as f64),
+            "util" => Value::Number(self.average_utilization),
+            "duration" => Value::Number((self.last_seen - self.first_seen).num_seconds() as f64),
+            "running" => Value::Text(self.running.to_string()),
+            _ => return Vec::new(),
+        };
+        vec![value]
+    }
+}
+
+/// Called on every update, follows the processes of the host and archives the ended ones.
+pub fn record(server_info: &ServerInfo) {
+    let now = Local::now();
+    let host = &server_info.hostname;
+    let mut jobs = match JOBS.running().lock() {
+        Ok(j) => j,
+        Err(_) => return,
+    };
+    for process in &server_info.gpu.processes {
+        let detail = server_info.gpu.details.get(process.gpu_index);
+        let memory = mib_to_bytes(&process.used_memory).unwrap_or(0);
+        let utilization = detail
+            .and_then(|d| percent(&d.utilization_gpu))
+            .unwrap_or(0.0);
+        let key = (host.clone(), process.gpu_index, process.pid);
+        let job = jobs.entry(key).or_insert_with(|| Job {
+            host: host.clone(),
+            gpu: process.gpu_index,
+            gpu_name: detail.map(|d| d.name.clone()).unwrap_or_default(),
+            pid: process.pid,
+            user: process.user.clone(),
+            cmdline: process.cmdline.clone(),
+            cwd: process.cwd.clone(),
+            workload: match &process.workload {
+                Some(w) => workload::describe(w, &process.user),
+                None => String::new(),
+            },
+            first_seen: now,
+            last_seen: now,
+            peak_memory: 0,
+            average_utilization: 0.0,
+            samples: 0,
+            running: true,
+        });
+        job.last_seen = now;
+        job.peak_memory = job.peak_memory.max(memory);
+        job.samples += 1;
+        job.average_utilization += (utilization - job.average_utilization) / job.samples as f64;
+        if job.cmdline.is_empty() {
+            job.cmdline = process.cmdline.clone();
+        }
+    }
+    // a failed nvidia-smi sends no processes, the jobs of the host have not ended
+    let listed = server_info
+        .collectors
+        .get("gpu")
+        .is_none_or(|s| *s == CollectorStatus::Ok);
+    let finished = JOBS.end_gone(&mut jobs, host, now, listed);
+    if server_info.gpu.processes.is_empty() && finished.is_empty() {
+        return;
+    }
+    JOBS.save(&jobs, finished);
+}
+
+#[derive(Deserialize)]
+pub struct JobQuery {
+    host: Option<String>,
+    user: Option<String>,
+    pid: Option<u32>,
+    gpu: Option<usize>,
+    /// defaults to 7 days ago
+    from: Option<String>,
+    /// defaults to now
+    to: Option<String>,
+    /// filter, see query.rs, e.g. `mem > 40GiB and duration > 3600`
+    q: Option<String>,
+    /// defaults to newest first
+    sort: Option<String>,
+    limit: Option<usize>,
+    format: Option<String>,
+}
+
+/// Running and archived jobs that match, newest first unless sorted otherwise.
+fn search(query: &JobQuery) -> Result<Vec<Job>, String> {
+    let filter = match &query.q {
+        Some(q) if !q.trim().is_empty() => {
+            Some(query::parse_for::<Job>(q).map_err(|e| e.explain(q))?)
+        }
+        _ => None,
+    };
+    let sort_key = match &query.sort {
+        Some(s) if !s.trim().is_empty() => {
+            Some(query::SortKey::parse_for::<Job>(s).map_err(|e| format!("sort: {}", e.message))?)
+        }
+        _ => None,
+    };
+    let mut jobs = JOBS.search(query.from.as_deref(), query.to.as_deref())?;
+    jobs.retain(|j| {
+        query.host.as_ref().is_none_or(|h| &j.host == h)
+            && query.user.as_ref().is_none_or(|u| &j.user == u)
+            && query.pid.is_none_or(|p| j.pid == p)
+            && query.gpu.is_none_or(|g| j.gpu == g)
+            && filter.as_ref().is_none_or(|f| f.matches_record(j))
+    });
+    jobs.sort_by_key(|j| std::cmp::Reverse(j.first_seen));
+    if let Some(sort_key) = sort_key {
+        sort_key.sort_records(&mut jobs);
+    }
+    jobs.truncate(query.limit.unwrap_or(DEFAULT_LIMIT));
+    Ok(jobs)
+}
+
+fn render(jobs: &[Job], format: Format) -> HttpResponse {
+    if format == Format::Json {
+        return HttpResponse::Ok().json(jobs);
+    }
+    let column = |key, title, note| Column {
+        key,
+        title,
+        note,
+        list: false,
+    };
+    let mut table = InfoTable::new(vec![
+        column("host", "host", None),
+        column("gpu", "gpu", None),
+        column("pid", "pid", None),
+        column("user", "user", None),
+        column("first_seen", "first seen", None),
+        column("last_seen", "last seen", None),
+        column(
+            "peak_memory",
+            "peak mem",
+            Some("highest gpu memory of the job"),
+        ),
+        column(
+            "average_utilization",
+            "avg gpu@u",
+            Some("average utilization of the card while the job ran"),
+        ),
+        column("cmdline", "command", None),
+        column("cwd", "cwd", None),
+        column(
+            "workload",
+            "workload",
+            Some("container or slurm job of the process"),
+        ),
+    ]);
+    for j in jobs {
+        let last_seen = if j.running {
+            String::from("running")
+        } else {
+            j.last_seen.format("%Y-%m-%d %H:%M:%S").to_string()
+        };
+        table.add_row(vec![
+            j.host.clone(),
+            j.gpu.to_string(),
+            j.pid.to_string(),
+            j.user.clone(),
+            j.first_seen.format("%Y-%m-%d %H:%M:%S").to_string(),
+            last_seen,
+            format!("{:.1} GiB", j.peak_memory as f64 / GIB),
+            format!("{:.0} %", j.average_utilization),
+            j.cmdline.clone(),
+            j.cwd.clone(),
+            j.workload.clone(),
+        ]);
+    }
+    let body = match format {
+        Format::Table => format!("{}{}", table.render(format), table.notes()),
+        _ => table.render(format),
+    };
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(body)
+}
+
+#[get("/api/v1/jobs")]
+async fn job_history(req: HttpRequest, query: web::Query<JobQuery>) -> impl Responder {
+    let format = match Format::negotiate(&req, query.format.as_deref()) {
+        Ok(f) => f,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    match search(&query) {
+        Ok(jobs) => render(&jobs, format),
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+/// Kept for the scripts written against the old python server.
+#[get("/searchpid/{hostname}/{pid}")]
+async fn search_pid(path: web::Path<(String, u32)>) -> impl Responder {
+    let (host, pid) = path.into_inner();
+    let now = Local::now();
+    let query = JobQuery {
+        host: Some(host),
+        user: None,
+        pid: Some(pid),
+        gpu: None,
+        from: Some((now - Duration::days(30)).to_rfc3339()),
+        to: None,
+        q: None,
+        sort: None,
+        limit: None,
+        format: None,
+    };
+    match search(&query) {
+        Ok(jobs) => HttpResponse::Ok().json(jobs),
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reservation::parse_time;
+    #[test]
+    fn test_overlaps() {
+        let t = |s: &str| parse_time(s).unwrap();
+        let job = Job {
+            host: String::from("node41"),
+            gpu: 0,
+            gpu_name: String::from("NVIDIA A100"),
+            pid: 31337,
+            user: String::from("alice"),
+            cmdline: String::from("python train.py"),
+            cwd: String::from("/home/alice"),
+            workload: String::new(),
+            first_seen: t("2024-05-07 22:00"),
+            last_seen: t("2024-05-08 03:00"),
+            peak_memory: 0,
+            average_utilization: 0.0,
+            samples: 1,
+            running: false,
+        };
+        // "last tuesday night"
+        assert!(job.overlaps(t("2024-05-07 23:00"), t("2024-05-08 01:00")));
+        assert!(!job.overlaps(t("2024-05-08 04:00"), t("2024-05-08 05:00")));
+    }
+}
@@ -0,0 +1,258 @@
+use log::error;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::alert;
+use crate::alert::Alert;
+use crate::changes::BOOT_TIME_TOLERANCE;
+use crate::events;
+use crate::redis_load;
+use crate::redis_save;
+use crate::ServerInfo;
+
+const LAST_SEEN_KEY: &str = "kernel";
+
+/// Xids caused by the application on the card, not by the card itself
+const APPLICATION_XIDS: [u32; 4] = [13, 31, 43, 45];
+
+/// Xids that usually need a reset or a replaced card
+const XID_DESCRIPTIONS: [(u32, &str); 11] = [
+    (48, "double bit ecc error"),
+    (61, "internal micro-controller breakpoint"),
+    (62, "internal micro-controller halt"),
+    (63, "ecc page retirement or row remapping"),
+    (64, "ecc page retirement or row remapping failure"),
+    (74, "nvlink error"),
+    (79, "fallen off the bus"),
+    (92, "high single-bit ecc error rate"),
+    (94, "contained ecc error"),
+    (95, "uncontained ecc error"),
+    (119, "gsp rpc timeout"),
+];
+
+/// sequence number of the newest kernel message handled per host, kept across server restarts
+static LAST_SEEN: OnceCell<Mutex<BTreeMap<String, LastSeen>>> = OnceCell::new();
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct KernelMessage {
+    pub kind: String,
+    pub seq: u64,
+    #[serde(default)]
+    pub time: i64,
+    pub message: String,
+    #[serde(default)]
+    pub xid: Option<u32>,
+    #[serde(default)]
+    pub pci: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct KernelEvents {
+    #[serde(default)]
+    pub counts: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub recent: Vec<KernelMessage>,
+    /// latest xid of every code and gpu since boot, older clients only send `recent`
+    #[serde(default)]
+    pub xids: Vec<KernelMessage>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+struct LastSeen {
+    boot_time: Option<i64>,
+    seq: u64,
+}
+
+fn last_seen() -> &'static Mutex<BTreeMap<String, LastSeen>> {
+    LAST_SEEN.get_or_init(|| {
+        let last_seen = match redis_load(LAST_SEEN_KEY) {
+            Ok(l) => l,
+            Err(e) => {
+                error!("load kernel last seen error: {}", e);
+                BTreeMap::new()
+            }
+        };
+        Mutex::new(last_seen)
+    })
+}
+
+fn is_hardware_xid(message: &KernelMessage) -> bool {
+    matches!(message.xid, Some(x) if !APPLICATION_XIDS.contains(&x))
+}
+
+/// "xid 79 (fallen off the bus) on 0000:3b:00"
+fn describe_xid(message: &KernelMessage) -> String {
+    let xid = message.xid.unwrap_or_default();
+    let mut text = format!("xid {}", xid);
+    if let Some((_, d)) = XID_DESCRIPTIONS.iter().find(|(x, _)| *x == xid) {
+        text += &format!(" ({})", d);
+    }
+    if let Some(pci) = &message.pci {
+        text += &format!(" on {}", pci);
+    }
+    text
+}
+
+/// Hardware xids since boot, they stay until the host is rebooted.
+pub fn problems(kernel: &KernelEvents) -> Vec<String> {
+    let mut problems: Vec<String> = kernel
+        .xids
+        .iter()
+        .chain(&kernel.recent)
+        .filter(|m| is_hardware_xid(m))
+        .map(describe_xid)
+        .collect();
+    problems.sort();
+    problems.dedup();
+    problems
+}
+
+fn same_boot(previous: &LastSeen, boot_time: Option<i64>, newest: u64) -> bool {
+    // sequence numbers going backwards are a reboot even without boot times
+    newest >= previous.seq
+        && match (previous.boot_time, boot_time) {
+            (Some(p), Some(c)) => (c - p).abs() <= BOOT_TIME_TOLERANCE,
+            _ => true,
+        }
+}
+
+/// Messages not handled yet, all of them after a reboot since the sequence starts over.
+fn new_messages<'a>(
+    previous: Option<&LastSeen>,
+    boot_time: Option<i64>,
+    recent: &'a [KernelMessage],
+) -> Vec<&'a KernelMessage> {
+    let newest = recent.iter().map(|m| m.seq).max().unwrap_or_default();
+    let after = previous
+        .filter(|p| same_boot(p, boot_time, newest))
+        .map(|p| p.seq);
+    recent
+        .iter()
+        .filter(|m| after.is_none_or(|a| m.seq > a))
+        .collect()
+}
+
+/// Called on every update, raises alerts and records events for new kernel faults of the host.
+pub fn check(server_info: &ServerInfo) {
+    let kernel = &server_info.kernel;
+    // an xid pushed out of recent between two updates is still new
+    let mut recent: Vec<KernelMessage> = kernel.recent.clone();
+    recent.extend(
+        kernel
+            .xids
+            .iter()
+            .filter(|x| !kernel.recent.iter().any(|m| m.seq == x.seq))
+            .cloned(),
+    );
+    recent.sort_by_key(|m| m.seq);
+    let newest = match recent.iter().map(|m| m.seq).max() {
+        Some(n) => n,
+        None => return,
+    };
+    let host = &server_info.hostname;
+    let mut last_seen = match last_seen().lock() {
+        Ok(l) => l,
+        Err(_) => return,
+    };
+    let previous = last_seen.get(host).cloned();
+    // keep the first boot time so that the jitter does not add up
+    let boot_time = match &previous {
+        Some(p) if same_boot(p, server_info.boot_time, newest) => p.boot_time,
+        _ => server_info.boot_time,
+    };
+    let new = new_messages(previous.as_ref(), boot_time, &recent);
+    if new.is_empty() {
+        return;
+    }
+    last_seen.insert(
+        host.clone(),
+        LastSeen {
+            boot_time,
+            seq: newest,
+        },
+    );
+    if let Err(e) = redis_save(LAST_SEEN_KEY, &*last_seen) {
+        error!("save kernel last seen error: {}", e);
+    }
+    drop(last_seen);
+    // the first update of a host brings its whole ring buffer, old news is no alert
+    let first_sight = previous.is_none();
+    for m in new {
+        let text = match m.kind.as_str() {
+            "xid" => format!("{}: {}", describe_xid(m), m.message),
+            _ => m.message.clone(),
+        };
+        events::record(host, &m.kind, &text);
+        // application xids and oom kills are the job's fault, the event is enough
+        let alerting = match m.kind.as_str() {
+            "xid" => is_hardware_xid(m),
+            "oom" => false,
+            _ => true,
+        };
+        if alerting && !first_sight {
+            let key = format!(
+                "kernel:{}:{}:{}",
+                host,
+                boot_time.unwrap_or_default(),
+                m.seq
+            );
+            alert::raise(&key, Alert::new(&m.kind, host, "", &text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn message(seq: u64, xid: Option<u32>) -> KernelMessage {
+        KernelMessage {
+            kind: String::from("xid"),
+            seq,
+            xid,
+            pci: Some(String::from("0000:3b:00")),
+            ..Default::default()
+        }
+    }
+    #[test]
+    fn test_new_messages() {
+        let recent = vec![message(10, Some(13)), message(20, Some(79))];
+        let seen = LastSeen {
+            boot_time: Some(1_700_000_000),
+            seq: 10,
+        };
+        let seqs = |m: Vec<&KernelMessage>| m.iter().map(|m| m.seq).collect::<Vec<u64>>();
+        assert_eq!(
+            seqs(new_messages(None, Some(1_700_000_000), &recent)),
+            vec![10, 20]
+        );
+        assert_eq!(
+            seqs(new_messages(Some(&seen), Some(1_700_000_000), &recent)),
+            vec![20]
+        );
+        // rebooted, sequence numbers start over
+        assert_eq!(
+            seqs(new_messages(Some(&seen), Some(1_700_090_000), &recent)),
+            vec![10, 20]
+        );
+        let kernel = KernelEvents {
+            recent,
+            ..Default::default()
+        };
+        assert_eq!(
+            problems(&kernel),
+            vec!["xid 79 (fallen off the bus) on 0000:3b:00"]
+        );
+        // pushed out of recent by other messages
+        let kernel = KernelEvents {
+            xids: vec![message(20, Some(79))],
+            ..Default::default()
+        };
+        assert_eq!(
+            problems(&kernel),
+            vec!["xid 79 (fallen off the bus) on 0000:3b:00"]
+        );
+    }
+}
@@ -3,25 +3,59 @@ use actix_web::get;
 use actix_web::post;
 use actix_web::web;
 use actix_web::App;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use actix_web::HttpServer;
 use actix_web::Responder;
 use chrono::DateTime;
 use chrono::Local;
+use clap::Parser;
 use log::error;
 use log::info;
 use once_cell::sync::OnceCell;
-use prettytable::row;
-use prettytable::Table;
 use redis::Client;
 use redis::Commands;
 use redis::Connection;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::time::Duration;
 use thiserror::Error;
 
+use render::Column;
+use render::Format;
+use render::InfoTable;
+
+mod accounting;
+mod alert;
+mod changes;
+mod cpu;
+mod dashboard;
+mod disk;
+mod events;
+mod free;
+mod health;
+mod history;
+mod idle;
+mod jobs;
+mod kernel;
+mod net;
+mod notify;
+mod nvidia;
+mod policy;
+mod procs;
+mod pull;
+mod query;
+mod render;
+mod reservation;
+mod sensors;
+mod sessions;
+mod stream;
+mod subscription;
+mod workload;
+
 #[derive(Error, Debug)]
 pub enum ServerError {
     #[error("can not connect to redis server from cache")]
@@ -30,9 +64,49 @@ pub enum ServerError {
     RedisError(#[from] redis::RedisError),
     #[error("serde error")]
     SerdeError(#[from] serde_json::Error),
+    #[error("http request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+    #[error("http status error: {status}")]
+    HttpStatusError { status: u16 },
+}
+
+/// Simple program to collect server infomation
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Client endpoint to scrape (e.g. http://192.168.1.34:9101/sample.json), can be repeated
+    #[clap(long)]
+    pull_target: Vec<String>,
+
+    /// Scrape interval (sec)
+    #[clap(long, default_value_t = 10)]
+    pull_interval: u64,
+
+    /// Scrape timeout (sec)
+    #[clap(long, default_value_t = 5)]
+    pull_timeout: u64,
+
+    /// Interval the clients push updates at, their --interval (sec),
+    /// a host is offline and leaves /info after three intervals without update
+    #[clap(long, default_value_t = DEFAULT_INTERVAL)]
+    interval: u64,
+
+    /// Mark a host stale if no update arrived for this long (sec), twice --interval by default
+    #[clap(long)]
+    stale_after: Option<u64>,
+
+    /// A gpu holding memory below this utilization (%) counts as idle
+    #[clap(long, default_value_t = 5.0)]
+    idle_util: f64,
+
+    /// Flag a gpu that stayed idle for this long (min)
+    #[clap(long, default_value_t = 60)]
+    idle_after: i64,
 }
 
 static RD_CONNECTION: OnceCell<Client> = OnceCell::new();
+/// --interval, how often a host sends an update
+static UPDATE_INTERVAL: OnceCell<Duration> = OnceCell::new();
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 struct SingleCardDetail {
@@ -44,27 +118,94 @@ struct SingleCardDetail {
     memory_total: String,
     memory_free: String,
     memory_used: String,
+    #[serde(default)]
+    uuid: String,
+    #[serde(default)]
+    metrics: Option<nvidia::GpuMetrics>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct GpuProcess {
+    gpu_index: usize,
+    pid: u32,
+    user: String,
+    used_memory: String,
+    cwd: String,
+    #[serde(default)]
+    cmdline: String,
+    #[serde(default)]
+    gpu_instance: Option<u32>,
+    #[serde(default)]
+    compute_instance: Option<u32>,
+    #[serde(default)]
+    workload: Option<workload::Workload>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 struct ServerCardsInfo {
     details: Vec<SingleCardDetail>,
     users: Vec<String>,
+    #[serde(default)]
+    processes: Vec<GpuProcess>,
+    #[serde(default)]
+    mig: Vec<nvidia::MigInstance>,
+    #[serde(default)]
+    topology: Option<nvidia::Topology>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 struct ServerInfo {
+    #[serde(default)]
     password: String,
     gpu: ServerCardsInfo,
     hostname: String,
     net: HashMap<String, String>,
+    #[serde(default)]
+    interfaces: Vec<net::Interface>,
     mem: HashMap<String, String>,
     swap: HashMap<String, String>,
     cpu: HashMap<String, f32>,
+    #[serde(default)]
+    cpu_detail: Option<cpu::CpuDetail>,
+    #[serde(default)]
+    disks: Vec<disk::MountUsage>,
+    #[serde(default)]
+    disk_io: Vec<disk::DiskIo>,
+    #[serde(default)]
+    sensors: sensors::Sensors,
+    #[serde(default)]
+    kernel: kernel::KernelEvents,
+    #[serde(default)]
+    processes: Option<procs::ProcessTop>,
+    #[serde(default)]
+    sessions: Vec<sessions::Session>,
     other: HashMap<String, String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    collectors: BTreeMap<String, health::CollectorStatus>,
+    #[serde(default)]
+    uptime_seconds: Option<u64>,
+    /// unix time
+    #[serde(default)]
+    boot_time: Option<i64>,
 }
 
 const PASSWORD: &str = "123456";
+/// a host disappears from redis this many intervals after its last update
+const HOST_TTL_INTERVALS: u32 = 3;
+/// --interval of the clients if the server was not told (sec)
+const DEFAULT_INTERVAL: u64 = 60;
+
+/// Time between two updates of a healthy host, the time to collect a sample comes on top.
+fn update_interval() -> Duration {
+    *UPDATE_INTERVAL.get_or_init(|| Duration::from_secs(DEFAULT_INTERVAL))
+}
+
+/// How long a host stays in redis, and in `/info`, after its last update.
+fn host_ttl() -> Duration {
+    update_interval() * HOST_TTL_INTERVALS
+}
 
 #[get("/")]
 async fn hello() -> impl Responder {
@@ -85,11 +226,53 @@ fn redis_connection() -> Result<Connection, ServerError> {
     Ok(con)
 }
 
+/// Keys of server side state, everything else in redis is a host.
+const STATE_PREFIX: &str = "watchdog:";
+
+fn redis_load<T: DeserializeOwned + Default>(key: &str) -> Result<T, ServerError> {
+    let mut con = redis_connection()?;
+    let v: Option<String> = con.get(format!("{}{}", STATE_PREFIX, key))?;
+    match v {
+        Some(v) => Ok(serde_json::from_str(&v)?),
+        None => Ok(T::default()),
+    }
+}
+
+fn redis_save<T: Serialize>(key: &str, value: &T) -> Result<(), ServerError> {
+    let mut con = redis_connection()?;
+    let v = serde_json::to_string(value)?;
+    let _: () = con.set(format!("{}{}", STATE_PREFIX, key), v)?;
+    Ok(())
+}
+
+/// Next id of a counter, never handed out twice, not even after a restart.
+/// `floor` is the largest id in use, for lists that predate the counter.
+fn redis_next_id(name: &str, floor: u64) -> Result<u64, ServerError> {
+    let mut con = redis_connection()?;
+    let key = format!("{}{}:next_id", STATE_PREFIX, name);
+    let id: u64 = con.incr(&key, 1)?;
+    if id > floor {
+        return Ok(id);
+    }
+    let _: () = con.set(&key, floor + 1)?;
+    Ok(floor + 1)
+}
+
+/// The latest update of one host, None if it has not reported recently.
+fn redis_host(hostname: &str) -> Result<Option<ServerInfo>, ServerError> {
+    let mut con = redis_connection()?;
+    let v: Option<String> = con.get(hostname)?;
+    match v {
+        Some(v) => Ok(Some(serde_json::from_str(&v)?)),
+        None => Ok(None),
+    }
+}
+
 fn redis_database() -> Result<BTreeMap<String, ServerInfo>, ServerError> {
     let mut con = redis_connection()?;
     let keys: Vec<String> = con.keys("*")?;
     let mut database: BTreeMap<String, ServerInfo> = BTreeMap::new();
-    for k in keys {
+    for k in keys.into_iter().filter(|k| !k.starts_with(STATE_PREFIX)) {
         let v: String = con.get(&k)?;
         let v: ServerInfo = serde_json::from_str(&v)?;
         database.insert(k, v);
@@ -97,45 +280,54 @@ fn redis_database() -> Result<BTreeMap<String, ServerInfo>, ServerError> {
     Ok(database)
 }
 
+/// Store the server info in redis, shared by the push and the pull path.
+fn store_server_info(server_info: &ServerInfo) -> Result<(), ServerError> {
+    let mut con = redis_connection()?;
+    let server_time: DateTime<Local> = Local::now();
+    let server_time_str = server_time.format("%H:%M:%S").to_string();
+    let mut server_info_clone = server_info.clone();
+    server_info_clone
+        .other
+        .insert("new_nowtime".to_string(), server_time_str);
+
+    let hostname = &server_info_clone.hostname;
+    let serde_server_info = serde_json::to_string(&server_info_clone)?;
+    let _: () = con.set_ex(hostname, serde_server_info, host_ttl().as_secs())?;
+    on_update(&server_info_clone);
+    Ok(())
+}
+
+/// Everything that reacts to a new update runs from here.
+fn on_update(server_info: &ServerInfo) {
+    stream::publish_update(server_info);
+    health::observe(server_info);
+    changes::check(server_info);
+    kernel::check(server_info);
+    accounting::record(server_info);
+    jobs::record(server_info);
+    sessions::record(server_info);
+    idle::check(server_info);
+    reservation::check(server_info);
+    subscription::check();
+}
+
 #[post("/update")]
 async fn update(server_info: web::Json<ServerInfo>) -> impl Responder {
-    match redis_connection() {
-        Ok(mut con) => {
-            if server_info.password != PASSWORD {
-                HttpResponse::Ok().body(format!("password wrong!"))
-            } else {
-                let server_time: DateTime<Local> = Local::now();
-                let server_time_str = server_time.format("%H:%M:%S").to_string();
-                let mut server_info_clone = server_info.clone();
-                server_info_clone
-                    .other
-                    .insert("new_nowtime".to_string(), server_time_str);
-
-                let hostname = &server_info_clone.hostname;
-                match serde_json::to_string(&server_info_clone) {
-                    Ok(serde_server_info) => {
-                        let _: () = con
-                            .set_ex(hostname, serde_server_info, 60)
-                            .expect("redis set failed");
-
-                        HttpResponse::Ok().body(format!("welcome {}!", hostname))
-                    }
-                    Err(e) => {
-                        error!("Convert struct to string failed: {}", e);
-                        HttpResponse::Ok().body("redis error")
-                    }
-                }
+    if server_info.password != PASSWORD {
+        HttpResponse::Ok().body("password wrong!")
+    } else {
+        match store_server_info(&server_info) {
+            Ok(_) => HttpResponse::Ok().body(format!("welcome {}!", server_info.hostname)),
+            Err(e) => {
+                error!("store server info error: {}", e);
+                HttpResponse::Ok().body("redis error")
             }
         }
-        Err(e) => {
-            error!("get redis connection error: {}", e);
-            HttpResponse::Ok().body("redis error")
-        }
     }
 }
 
-fn database_process(database: BTreeMap<String, ServerInfo>) -> BTreeMap<String, ServerInfo> {
-    let mut hm = BTreeMap::new();
+fn database_process(database: Vec<(String, ServerInfo)>) -> Vec<(String, ServerInfo)> {
+    let mut hm = Vec::new();
     for (name, mut server_info) in database {
         let gpu_users = &server_info.gpu.users;
         let mut new_gpu_users = Vec::new();
@@ -157,134 +349,388 @@ fn database_process(database: BTreeMap<String, ServerInfo>) -> BTreeMap<String,
                 new_gpu_users.push(gpu_user.to_string());
             }
         }
+        // the cwd of a container or batch job says little, name the job instead
+        let processes = &server_info.gpu.processes;
+        if processes.iter().any(|p| p.workload.is_some()) {
+            new_gpu_users = Vec::new();
+            for p in processes {
+                let user = match &p.workload {
+                    Some(w) => workload::describe(w, &p.user),
+                    None => p.user.clone(),
+                };
+                if !new_gpu_users.contains(&user) {
+                    new_gpu_users.push(user);
+                }
+            }
+        }
         server_info.gpu.users = new_gpu_users;
-        hm.insert(name, server_info);
+        hm.push((name, server_info));
     }
     hm
 }
 
-#[get("/info")]
-async fn info() -> impl Responder {
-    let name_title = "name";
-    let ip_title = "addr";
-    let cpu_system_title = "cpu@s";
-    let cpu_user_title = "cpu@u";
-    let cpu_temp_title = "cpu@t";
-    let gpu_name_title = "gpu device";
-    let gpu_util_title = "gpu@u";
-    let gpu_memory_title = "gpu@m";
-    let gpu_temp_title = "gpu@t";
-    let gpu_user_title = "gpu user";
-    let heartbeat_title = "heartbeat";
-
-    let mut table = Table::new();
-    table.add_row(row![
-        c -> name_title,
-        c -> ip_title,
-        c -> cpu_system_title,
-        c -> cpu_user_title,
-        c -> cpu_temp_title,
-        c -> gpu_name_title,
-        c -> gpu_util_title,
-        c -> gpu_memory_title,
-        c -> gpu_temp_title,
-        c -> gpu_user_title,
-        c -> heartbeat_title
-    ]);
+fn info_columns() -> Vec<Column> {
+    let column = |key, title, note, list| Column {
+        key,
+        title,
+        note,
+        list,
+    };
+    vec![
+        column("name", "name", None, false),
+        column("health", "health", None, false),
+        column("addr", "addr", None, true),
+        column(
+            "net",
+            "net",
+            Some("traffic over all interfaces but loopback"),
+            false,
+        ),
+        column(
+            "cpu_system",
+            "cpu@s",
+            Some("cpu system space utilization"),
+            false,
+        ),
+        column(
+            "cpu_user",
+            "cpu@u",
+            Some("cpu user space utilization"),
+            false,
+        ),
+        column(
+            "cpu_temp",
+            "cpu@t",
+            Some("cpu temperature, the hottest package if the host has sensors"),
+            false,
+        ),
+        column(
+            "power",
+            "power",
+            Some("power of all cpu packages (intel rapl)"),
+            false,
+        ),
+        column(
+            "load",
+            "load",
+            Some("load average of 1, 5 and 15 minutes"),
+            false,
+        ),
+        column(
+            "cpu_cores",
+            "cores",
+            Some("load of every core on cpu servers, from idle \"·\" to busy \"█\""),
+            false,
+        ),
+        column(
+            "top_users",
+            "users",
+            Some("users with the most cpu on cpu servers, 100 % is one core"),
+            false,
+        ),
+        column(
+            "top_processes",
+            "top",
+            Some("processes with the most cpu on cpu servers"),
+            false,
+        ),
+        column(
+            "logins",
+            "logins",
+            Some("logged in users, and users with a running tmux or screen"),
+            true,
+        ),
+        column(
+            "disk",
+            "disk",
+            Some("the fullest mount by space or inodes"),
+            false,
+        ),
+        column("gpu_device", "gpu device", None, true),
+        column(
+            "gpu_util",
+            "gpu@u",
+            Some("gpu utilization, \"idle\" marks memory held without use"),
+            true,
+        ),
+        column("gpu_memory", "gpu@m", Some("gpu memory"), true),
+        column(
+            "gpu_power",
+            "gpu@p",
+            Some("p-state and power draw/limit"),
+            true,
+        ),
+        column("gpu_temp", "gpu@t", Some("gpu temperature"), true),
+        column("gpu_user", "gpu user", None, true),
+        column("booking", "booking", Some("active gpu reservations"), true),
+        column("heartbeat", "heartbeat", None, false),
+    ]
+}
 
-    match redis_database() {
-        Ok(database) => {
-            let new_database = database_process(database);
-            for (hostname, server_info) in new_database {
-                if hostname.len() > 0 {
-                    let mut ip_info = String::new();
-                    let new_net: BTreeMap<String, String> = server_info.net.into_iter().collect();
-                    for (interface_name, ip) in new_net {
-                        if !ip.contains("null") && !ip.contains("127.0.0.1") {
-                            ip_info += &format!("{}: {}\n", interface_name, ip);
-                        }
+fn info_table(database: Vec<(String, ServerInfo)>) -> InfoTable {
+    let mut table = InfoTable::new(info_columns());
+    let new_database = database_process(database);
+    let reservations = reservation::active();
+    for (hostname, server_info) in new_database {
+        if !hostname.is_empty() {
+            let idle_gpus = idle::flagged(&hostname);
+            let mut ip_info = String::new();
+            if server_info.interfaces.is_empty() {
+                // clients before the interface details
+                let new_net: BTreeMap<String, String> = server_info.net.into_iter().collect();
+                for (interface_name, ip) in new_net {
+                    if !ip.contains("null") && !ip.contains("127.0.0.1") {
+                        ip_info += &format!("{}: {}\n", interface_name, ip);
                     }
-                    let ip_info = ip_info.trim();
+                }
+            } else {
+                ip_info = net::describe_addresses(&server_info.interfaces);
+            }
+            let ip_info = ip_info.trim();
+            let net_traffic = net::describe_traffic(&server_info.interfaces);
 
-                    let cpu_system = match server_info.cpu.get("system") {
-                        Some(c) => format!("{:.0} %", c * 100.0),
-                        None => String::from("0"),
-                    };
-                    let cpu_user = match server_info.cpu.get("user") {
-                        Some(c) => format!("{:.0} %", c * 100.0),
-                        None => String::from("0"),
-                    };
-                    let cpu_temp = match server_info.cpu.get("temp") {
-                        Some(t) => format!("{:.0} C", t),
-                        None => format!("{:.0} C", 0.0),
-                    };
+            let cpu_system = match server_info.cpu.get("system") {
+                Some(c) => format!("{:.0} %", c * 100.0),
+                None => String::from("0"),
+            };
+            let cpu_user = match server_info.cpu.get("user") {
+                Some(c) => format!("{:.0} %", c * 100.0),
+                None => String::from("0"),
+            };
+            let cpu_temp = match sensors::max_package_temperature(&server_info.sensors) {
+                Some(t) => format!("{:.0} C", t),
+                None => match server_info.cpu.get("temp") {
+                    Some(t) => format!("{:.0} C", t),
+                    None => format!("{:.0} C", 0.0),
+                },
+            };
+            let power = match sensors::total_power(&server_info.sensors) {
+                Some(w) => format!("{:.0} W", w),
+                None => String::from("-"),
+            };
 
-                    let gpu_device = server_info.gpu.details;
-                    let gpu_users = server_info.gpu.users;
-                    let mut gpu_name = String::new();
-                    let mut gpu_util = String::new();
-                    let mut gpu_memory = String::new();
-                    let mut gpu_temp = String::new();
-                    for gd in gpu_device {
-                        gpu_name += &format!("{} ({})\n", gd.name, gd.driver_version);
-                        gpu_util += &format!("{}\n", gd.utilization_gpu);
-                        gpu_memory += &format!("{}/{}\n", gd.memory_used, gd.memory_total);
-                        gpu_temp += &format!("{} C\n", gd.temperature_gpu);
-                    }
-                    let gpu_name = gpu_name.trim();
-                    let gpu_util = gpu_util.trim();
-                    let gpu_memory = gpu_memory.trim();
-                    let gpu_temp = gpu_temp.trim();
-
-                    let mut gpu_user = String::new();
-                    for gu in gpu_users {
-                        gpu_user += &format!("{}\n", gu);
-                    }
-                    let gpu_user = gpu_user.trim();
-
-                    let heartbeat_time = match server_info.other.get("new_nowtime") {
-                        Some(u) => u.to_string(),
-                        None => {
-                            let server_time_str = Local::now().format("%H:%M:%S").to_string();
-                            server_time_str
-                        }
-                    };
+            let load = match &server_info.cpu_detail {
+                Some(d) => cpu::describe_load(d),
+                None => String::from("-"),
+            };
+            let is_cpu_server = server_info.gpu.details.iter().all(|d| d.name.is_empty());
+            let cpu_cores = match &server_info.cpu_detail {
+                Some(d) if is_cpu_server && !d.cores.is_empty() => cpu::heat_strip(&d.cores),
+                _ => String::from("-"),
+            };
+
+            let (top_users, top_processes) = match &server_info.processes {
+                Some(p) if is_cpu_server => (
+                    procs::describe_users(p, procs::INFO_ROWS),
+                    procs::describe_top(p, procs::INFO_ROWS),
+                ),
+                _ => (String::from("-"), String::from("-")),
+            };
+
+            let logins = if server_info.sessions.is_empty() {
+                String::from("-")
+            } else {
+                sessions::describe_users(&server_info.sessions)
+            };
 
-                    table.add_row(row![
-                        c -> hostname,
-                        c -> ip_info,
-                        c -> cpu_system,
-                        c -> cpu_user,
-                        c -> cpu_temp,
-                        c -> gpu_name,
-                        c -> gpu_util,
-                        c -> gpu_memory,
-                        c -> gpu_temp,
-                        c -> gpu_user,
-                        c -> heartbeat_time
-                    ]);
+            let disk = disk::describe_fullest(&server_info.disks);
+
+            let mig = &server_info.gpu.mig;
+            let gpu_device = server_info.gpu.details;
+            let gpu_users = server_info.gpu.users;
+            let mut gpu_name = String::new();
+            let mut gpu_util = String::new();
+            let mut gpu_memory = String::new();
+            let mut gpu_temp = String::new();
+            let mut gpu_power = String::new();
+            let mut booking = String::new();
+            for (i, gd) in gpu_device.into_iter().enumerate() {
+                gpu_name += &format!("{} ({})", gd.name, gd.driver_version);
+                match nvidia::describe_mig(i, mig).as_str() {
+                    "" => gpu_name += "\n",
+                    m => gpu_name += &format!(" {}\n", m),
+                }
+                match idle_gpus.get(&i) {
+                    Some(idle) => {
+                        gpu_util += &format!("{} idle {}\n", gd.utilization_gpu, idle.idle_for())
+                    }
+                    None => gpu_util += &format!("{}\n", gd.utilization_gpu),
+                }
+                gpu_memory += &format!("{}/{}\n", gd.memory_used, gd.memory_total);
+                gpu_temp += &format!("{} C\n", gd.temperature_gpu);
+                match &gd.metrics {
+                    Some(m) => gpu_power += &format!("{}\n", nvidia::describe_power(m)),
+                    None => gpu_power += "-\n",
                 }
+                match reservation::describe(&reservations, &hostname, i).as_str() {
+                    "" => booking += "-\n",
+                    b => booking += &format!("{}\n", b),
+                }
+            }
+            let gpu_name = gpu_name.trim();
+            let gpu_util = gpu_util.trim();
+            let gpu_memory = gpu_memory.trim();
+            let gpu_temp = gpu_temp.trim();
+            let gpu_power = gpu_power.trim();
+            let booking = booking.trim();
+
+            let mut gpu_user = String::new();
+            for gu in gpu_users {
+                gpu_user += &format!("{}\n", gu);
             }
+            let gpu_user = gpu_user.trim();
+
+            let heartbeat_time = match server_info.other.get("new_nowtime") {
+                Some(u) => u.to_string(),
+                None => {
+                    let server_time_str = Local::now().format("%H:%M:%S").to_string();
+                    server_time_str
+                }
+            };
+
+            let health = match health::state(&hostname) {
+                Some(h) => h.to_string(),
+                None => String::from("unknown"),
+            };
+            table.add_row(vec![
+                hostname,
+                health,
+                ip_info.to_string(),
+                net_traffic,
+                cpu_system,
+                cpu_user,
+                cpu_temp,
+                power,
+                load,
+                cpu_cores,
+                top_users,
+                top_processes,
+                logins,
+                disk,
+                gpu_name.to_string(),
+                gpu_util.to_string(),
+                gpu_memory.to_string(),
+                gpu_power.to_string(),
+                gpu_temp.to_string(),
+                gpu_user.to_string(),
+                booking.to_string(),
+                heartbeat_time,
+            ]);
+        }
+    }
+    table
+}
+
+#[derive(Deserialize)]
+struct InfoQuery {
+    format: Option<String>,
+    /// filter, see query.rs
+    q: Option<String>,
+    sort: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Apply `q`, `sort` and `limit`, the returned hosts are not processed.
+fn select_hosts(
+    database: BTreeMap<String, ServerInfo>,
+    query: &InfoQuery,
+) -> Result<Vec<(String, ServerInfo)>, String> {
+    let filter = match &query.q {
+        Some(q) if !q.trim().is_empty() => Some(query::parse(q).map_err(|e| e.explain(q))?),
+        _ => None,
+    };
+    let sort_key = match &query.sort {
+        Some(s) if !s.trim().is_empty() => {
+            Some(query::SortKey::parse(s).map_err(|e| format!("sort: {}", e.message))?)
+        }
+        _ => None,
+    };
+    let database: Vec<(String, ServerInfo)> = database.into_iter().collect();
+    // match on processed gpu users but hand back the raw data
+    let processed = database_process(database.clone());
+    let mut hosts: Vec<((String, ServerInfo), (String, ServerInfo))> = processed
+        .into_iter()
+        .zip(database)
+        .filter(|(p, _)| match &filter {
+            Some(f) => f.matches(&p.0, &p.1),
+            None => true,
+        })
+        .collect();
+    if let Some(sort_key) = sort_key {
+        let mut keyed: Vec<(String, ServerInfo)> = hosts.iter().map(|h| h.0.clone()).collect();
+        sort_key.sort(&mut keyed);
+        let order: Vec<String> = keyed.into_iter().map(|k| k.0).collect();
+        hosts.sort_by_key(|h| order.iter().position(|o| o == &h.0 .0));
+    }
+    let mut hosts: Vec<(String, ServerInfo)> = hosts.into_iter().map(|h| h.1).collect();
+    if let Some(limit) = query.limit {
+        hosts.truncate(limit);
+    }
+    Ok(hosts)
+}
+
+/// Hosts in the selected order as a JSON object.
+struct OrderedHosts(Vec<(String, ServerInfo)>);
+
+impl Serialize for OrderedHosts {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, v)))
+    }
+}
+
+#[get("/info")]
+async fn info(req: HttpRequest, query: web::Query<InfoQuery>) -> impl Responder {
+    let format = match Format::negotiate(&req, query.format.as_deref()) {
+        Ok(f) => f,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    match redis_database() {
+        Ok(database) => {
+            let hosts = match select_hosts(database, &query) {
+                Ok(h) => h,
+                Err(e) => return HttpResponse::BadRequest().body(e),
+            };
+            let table = info_table(hosts);
 
             let date_as_string = Local::now().format("%Y-%m-%d %H:%M:%S");
             let info_str = format!(">> {} [AI Sec Lab]", date_as_string);
             // let powered = "Powered by Rust\n";
-            let version = match option_env!("CARGO_PKG_VERSION") {
-                Some(v) => v,
-                None => "error",
-            };
+            let version = option_env!("CARGO_PKG_VERSION").unwrap_or("error");
             let powered = format!(">> Powered by Jay (v{})", version);
 
-            let mut note = String::from(">> cpu@s: cpu system space utilization\n");
-            note += ">> cpu@u: cpu user space utilization\n";
-            note += ">> cpu@t: cpu temperature\n";
-            note += ">> gpu@u: gpu utilization\n";
-            note += ">> gpu@m: gpu memory\n";
-            note += ">> gpu@t: gpu temperature";
-
-            let lines = format!("{}\n{}{}\n{}", info_str, table.to_string(), note, powered);
+            let lines = match format {
+                Format::Table => {
+                    let pull_table = match pull::status_table() {
+                        Some(t) => format!("{}", t),
+                        None => String::new(),
+                    };
+                    let violation_table = match policy::violation_table() {
+                        Some(t) => format!(">> policy violations\n{}", t),
+                        None => String::new(),
+                    };
+                    format!(
+                        "{}\n{}{}{}{}\n{}",
+                        info_str,
+                        table.render(format),
+                        pull_table,
+                        violation_table,
+                        table.notes(),
+                        powered
+                    )
+                }
+                Format::Html => format!(
+                    "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>watchdog</title></head>\n<body>\n<p>{}</p>\n{}<pre>{}\n{}</pre>\n</body>\n</html>\n",
+                    render::html_escape(&info_str),
+                    table.render(format),
+                    render::html_escape(&table.notes()),
+                    render::html_escape(&powered)
+                ),
+                _ => table.render(format),
+            };
 
-            HttpResponse::Ok().body(lines)
+            HttpResponse::Ok()
+                .content_type(format.content_type())
+                .body(lines)
         }
         Err(e) => {
             error!("get redis database failed: {}", e);
@@ -294,9 +740,12 @@ async fn info() -> impl Responder {
 }
 
 #[get("/info2")]
-async fn info2() -> impl Responder {
+async fn info2(query: web::Query<InfoQuery>) -> impl Responder {
     match redis_database() {
-        Ok(database) => HttpResponse::Ok().json(database),
+        Ok(database) => match select_hosts(database, &query) {
+            Ok(hosts) => HttpResponse::Ok().json(OrderedHosts(hosts)),
+            Err(e) => HttpResponse::BadRequest().body(e),
+        },
         Err(e) => HttpResponse::Ok().body(format!("get database error: {}", e)),
     }
 }
@@ -305,12 +754,24 @@ async fn info2() -> impl Responder {
 async fn main() -> std::io::Result<()> {
     pretty_env_logger::init();
     info!("web is running...");
+    let args = Args::parse();
 
     let client = match redis::Client::open("redis://127.0.0.1/") {
         Ok(c) => c,
         Err(e) => panic!("connect to redis failed: {}", e),
     };
     RD_CONNECTION.set(client).expect("set RD_CONNECTION failed");
+    let _ = UPDATE_INTERVAL.set(Duration::from_secs(args.interval.max(1)));
+
+    // a host is late by up to one interval plus the time it takes to collect a sample
+    let stale_after = args.stale_after.unwrap_or(args.interval * 2);
+    stream::start_stale_watch(stale_after);
+    health::start_watch(stale_after);
+    policy::start_watch(update_interval());
+    idle::configure(args.idle_util, args.idle_after);
+    if !args.pull_target.is_empty() {
+        pull::start(&args.pull_target, args.pull_interval, args.pull_timeout);
+    }
 
     HttpServer::new(|| {
         let cors = Cors::default().allow_any_origin().send_wildcard();
@@ -321,6 +782,31 @@ async fn main() -> std::io::Result<()> {
             .service(update)
             .service(info)
             .service(info2)
+            .service(dashboard::dashboard)
+            .service(dashboard::dashboard_css)
+            .service(dashboard::dashboard_js)
+            .service(stream::stream)
+            .service(free::free_gpus)
+            .service(alert::alerts_api)
+            .service(reservation::list_reservations)
+            .service(reservation::create_reservation)
+            .service(reservation::delete_reservation)
+            .service(subscription::list_subscriptions)
+            .service(subscription::create_subscription)
+            .service(subscription::delete_subscription)
+            .service(accounting::accounting)
+            .service(jobs::job_history)
+            .service(jobs::search_pid)
+            .service(sessions::login_history)
+            .service(idle::idle_gpus)
+            .service(notify::list_contacts)
+            .service(notify::set_contact)
+            .service(notify::delete_contact)
+            .service(policy::get_policies)
+            .service(policy::set_policies)
+            .service(policy::list_violations)
+            .service(health::health_api)
+            .service(events::list_events)
     })
     .bind(("0.0.0.0", 7070))?
     .run()
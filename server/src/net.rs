@@ -0,0 +1,134 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct NetRates {
+    #[serde(default)]
+    pub rx_bytes: f64,
+    #[serde(default)]
+    pub tx_bytes: f64,
+    #[serde(default)]
+    pub rx_packets: f64,
+    #[serde(default)]
+    pub tx_packets: f64,
+    #[serde(default)]
+    pub rx_errors: f64,
+    #[serde(default)]
+    pub tx_errors: f64,
+    #[serde(default)]
+    pub rx_dropped: f64,
+    #[serde(default)]
+    pub tx_dropped: f64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Interface {
+    pub name: String,
+    /// with prefix length, "10.0.0.1/24"
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub mac: String,
+    #[serde(default)]
+    pub state: String,
+    #[serde(default)]
+    pub speed_mbps: Option<u64>,
+    #[serde(default)]
+    pub mtu: Option<u64>,
+    #[serde(default)]
+    pub rates: Option<NetRates>,
+}
+
+fn is_loopback(interface: &Interface) -> bool {
+    interface.name == "lo"
+        || interface
+            .addresses
+            .iter()
+            .any(|a| a.starts_with("127.") || a.starts_with("::1/"))
+}
+
+/// "eth0: 10.0.0.1/24" per line, without loopback and link-local addresses.
+pub fn describe_addresses(interfaces: &[Interface]) -> String {
+    let mut lines = Vec::new();
+    for interface in interfaces.iter().filter(|i| !is_loopback(i)) {
+        for address in &interface.addresses {
+            if !address.starts_with("fe80:") {
+                lines.push(format!("{}: {}", interface.name, address));
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+/// "1.5 MB/s"
+fn rate(bytes_per_sec: f64) -> String {
+    if bytes_per_sec >= 1e9 {
+        format!("{:.1} GB/s", bytes_per_sec / 1e9)
+    } else if bytes_per_sec >= 1e6 {
+        format!("{:.1} MB/s", bytes_per_sec / 1e6)
+    } else {
+        format!("{:.0} KB/s", bytes_per_sec / 1e3)
+    }
+}
+
+/// Received and sent over all interfaces but loopback, "-" before the first rates arrive.
+pub fn describe_traffic(interfaces: &[Interface]) -> String {
+    let rates: Vec<&NetRates> = interfaces
+        .iter()
+        .filter(|i| !is_loopback(i))
+        .filter_map(|i| i.rates.as_ref())
+        .collect();
+    if rates.is_empty() {
+        return String::from("-");
+    }
+    let rx: f64 = rates.iter().map(|r| r.rx_bytes).sum();
+    let tx: f64 = rates.iter().map(|r| r.tx_bytes).sum();
+    let mut text = format!("rx {}\ntx {}", rate(rx), rate(tx));
+    let problems: f64 = rates
+        .iter()
+        .map(|r| r.rx_errors + r.tx_errors + r.rx_dropped + r.tx_dropped)
+        .sum();
+    if problems > 0.0 {
+        text += &format!("\n{:.1} err+drop/s", problems);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_describe() {
+        let interfaces = vec![
+            Interface {
+                name: String::from("lo"),
+                addresses: vec![String::from("127.0.0.1/8")],
+                rates: Some(NetRates {
+                    rx_bytes: 5e9,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            Interface {
+                name: String::from("eth0"),
+                addresses: vec![
+                    String::from("10.0.0.1/24"),
+                    String::from("fe80::1/64"),
+                    String::from("fd00::2/64"),
+                ],
+                rates: Some(NetRates {
+                    rx_bytes: 1.5e6,
+                    tx_bytes: 2e3,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            describe_addresses(&interfaces),
+            "eth0: 10.0.0.1/24\neth0: fd00::2/64"
+        );
+        assert_eq!(describe_traffic(&interfaces), "rx 1.5 MB/s\ntx 2 KB/s");
+        assert_eq!(describe_traffic(&[]), "-");
+    }
+}
@@ -0,0 +1,237 @@
+use actix_web::delete;
+use actix_web::get;
+use actix_web::put;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use log::error;
+use log::info;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::redis_load;
+use crate::redis_save;
+use crate::ServerError;
+use crate::PASSWORD;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const CONTACTS_KEY: &str = "contacts";
+
+/// serializes the read-modify-write of the contact list
+static CONTACTS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Where a notification goes.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Channel {
+    /// POST the notification as JSON
+    Webhook { url: String },
+    /// POST `{"text": ...}`, understood by Slack, Mattermost and Rocket.Chat incoming webhooks
+    Slack { url: String },
+}
+
+impl Channel {
+    pub fn validate(&self) -> Result<(), String> {
+        let url = match self {
+            Channel::Webhook { url } | Channel::Slack { url } => url,
+        };
+        if url.starts_with("http://") || url.starts_with("https://") {
+            Ok(())
+        } else {
+            Err(format!("invalid channel url: {}", url))
+        }
+    }
+
+    /// The channel with the url cut down to scheme and host,
+    /// the path of a webhook url is its secret.
+    pub fn redacted(&self) -> Channel {
+        let redact = |url: &str| {
+            let host_start = url.find("://").map_or(0, |i| i + 3);
+            match url[host_start..].find('/') {
+                Some(i) => format!("{}/...", &url[..host_start + i]),
+                None => url.to_string(),
+            }
+        };
+        match self {
+            Channel::Webhook { url } => Channel::Webhook { url: redact(url) },
+            Channel::Slack { url } => Channel::Slack { url: redact(url) },
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Notification {
+    pub kind: String,
+    pub user: String,
+    pub subject: String,
+    pub message: String,
+}
+
+async fn deliver(channel: &Channel, notification: &Notification) -> Result<(), ServerError> {
+    let client = reqwest::Client::builder().timeout(SEND_TIMEOUT).build()?;
+    let request = match channel {
+        Channel::Webhook { url } => client.post(url).json(notification),
+        Channel::Slack { url } => client.post(url).json(&serde_json::json!({
+            "text": format!("*{}*\n{}", notification.subject, notification.message)
+        })),
+    };
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(ServerError::HttpStatusError {
+            status: response.status().as_u16(),
+        });
+    }
+    Ok(())
+}
+
+/// Send in the background, failures are only logged.
+pub fn send(channel: Channel, notification: Notification) {
+    tokio::spawn(async move {
+        match deliver(&channel, &notification).await {
+            Ok(_) => info!(
+                "notified {} about {}",
+                notification.user, notification.subject
+            ),
+            Err(e) => warn!("notify {} error: {}", notification.user, e),
+        }
+    });
+}
+
+fn load_contacts() -> Result<BTreeMap<String, Channel>, ServerError> {
+    let contacts: BTreeMap<String, Channel> = redis_load(CONTACTS_KEY)?;
+    Ok(contacts)
+}
+
+/// Send to the channel the user registered, returns false if there is none.
+pub fn notify_user(user: &str, notification: Notification) -> bool {
+    match load_contacts() {
+        Ok(contacts) => match contacts.get(user) {
+            Some(channel) => {
+                send(channel.clone(), notification);
+                true
+            }
+            None => false,
+        },
+        Err(e) => {
+            error!("load contacts error: {}", e);
+            false
+        }
+    }
+}
+
+fn redacted(contacts: &BTreeMap<String, Channel>) -> BTreeMap<String, Channel> {
+    contacts
+        .iter()
+        .map(|(user, channel)| (user.clone(), channel.redacted()))
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct ContactQuery {
+    /// the password of /update
+    password: String,
+}
+
+#[get("/api/v1/contacts")]
+async fn list_contacts() -> impl Responder {
+    match load_contacts() {
+        Ok(contacts) => HttpResponse::Ok().json(redacted(&contacts)),
+        Err(e) => {
+            error!("load contacts error: {}", e);
+            HttpResponse::InternalServerError().body("load contacts failed")
+        }
+    }
+}
+
+/// Register where notifications about `user` go.
+#[put("/api/v1/contacts/{user}")]
+async fn set_contact(
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+    channel: web::Json<Channel>,
+) -> impl Responder {
+    let user = path.into_inner();
+    if query.password != PASSWORD {
+        return HttpResponse::Forbidden().body("password wrong!");
+    }
+    if let Err(e) = channel.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let _guard = match CONTACTS_LOCK.lock() {
+        Ok(g) => g,
+        Err(_) => return HttpResponse::InternalServerError().body("contact lock poisoned"),
+    };
+    let mut contacts = match load_contacts() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("load contacts error: {}", e);
+            return HttpResponse::InternalServerError().body("load contacts failed");
+        }
+    };
+    contacts.insert(user, channel.into_inner());
+    match redis_save(CONTACTS_KEY, &contacts) {
+        Ok(_) => HttpResponse::Ok().json(redacted(&contacts)),
+        Err(e) => {
+            error!("save contacts error: {}", e);
+            HttpResponse::InternalServerError().body("save contacts failed")
+        }
+    }
+}
+
+#[delete("/api/v1/contacts/{user}")]
+async fn delete_contact(
+    path: web::Path<String>,
+    query: web::Query<ContactQuery>,
+) -> impl Responder {
+    let user = path.into_inner();
+    if query.password != PASSWORD {
+        return HttpResponse::Forbidden().body("password wrong!");
+    }
+    let _guard = match CONTACTS_LOCK.lock() {
+        Ok(g) => g,
+        Err(_) => return HttpResponse::InternalServerError().body("contact lock poisoned"),
+    };
+    let mut contacts = match load_contacts() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("load contacts error: {}", e);
+            return HttpResponse::InternalServerError().body("load contacts failed");
+        }
+    };
+    if contacts.remove(&user).is_none() {
+        return HttpResponse::NotFound().body(format!("no contact for {}", user));
+    }
+    match redis_save(CONTACTS_KEY, &contacts) {
+        Ok(_) => HttpResponse::Ok().json(redacted(&contacts)),
+        Err(e) => {
+            error!("save contacts error: {}", e);
+            HttpResponse::InternalServerError().body("save contacts failed")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_redacted() {
+        let channel = Channel::Slack {
+            url: String::from("https://hooks.slack.com/services/T000/B000/XXXX"),
+        };
+        match channel.redacted() {
+            Channel::Slack { url } => assert_eq!(url, "https://hooks.slack.com/..."),
+            _ => panic!("channel type changed"),
+        }
+        let channel = Channel::Webhook {
+            url: String::from("http://localhost:8000"),
+        };
+        match channel.redacted() {
+            Channel::Webhook { url } => assert_eq!(url, "http://localhost:8000"),
+            _ => panic!("channel type changed"),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// throttle reasons that mean the card is too hot or short of power, not just idle or capped
+const BAD_THROTTLE_REASONS: [&str; 4] = [
+    "hw_slowdown",
+    "sw_thermal_slowdown",
+    "hw_thermal_slowdown",
+    "hw_power_brake_slowdown",
+];
+
+/// Metrics of one card beyond the basic query, as sent by the client.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct GpuMetrics {
+    #[serde(default)]
+    pub power_draw_watts: Option<f64>,
+    #[serde(default)]
+    pub power_limit_watts: Option<f64>,
+    #[serde(default)]
+    pub sm_clock_mhz: Option<u64>,
+    #[serde(default)]
+    pub memory_clock_mhz: Option<u64>,
+    #[serde(default)]
+    pub fan_percent: Option<f64>,
+    #[serde(default)]
+    pub pstate: String,
+    #[serde(default)]
+    pub pcie_gen: Option<u64>,
+    #[serde(default)]
+    pub pcie_width: Option<u64>,
+    #[serde(default)]
+    pub pcie_rx_bytes_per_sec: Option<f64>,
+    #[serde(default)]
+    pub pcie_tx_bytes_per_sec: Option<f64>,
+    #[serde(default)]
+    pub throttle_reasons: Vec<String>,
+    #[serde(default)]
+    pub ecc_corrected_volatile: Option<u64>,
+    #[serde(default)]
+    pub ecc_uncorrected_volatile: Option<u64>,
+    #[serde(default)]
+    pub ecc_corrected_aggregate: Option<u64>,
+    #[serde(default)]
+    pub ecc_uncorrected_aggregate: Option<u64>,
+    /// "Enabled" or "Disabled", empty on cards without MIG
+    #[serde(default)]
+    pub mig_mode: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct MigInstance {
+    pub gpu_index: usize,
+    pub gpu_instance: u32,
+    pub compute_instance: u32,
+    #[serde(default)]
+    pub device: u32,
+    #[serde(default)]
+    pub profile: String,
+    #[serde(default)]
+    pub uuid: String,
+    #[serde(default)]
+    pub memory_used_mib: Option<u64>,
+    #[serde(default)]
+    pub memory_total_mib: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Topology {
+    /// links[i][j] between card i and j, e.g. "NV12", "PIX", "SYS"
+    #[serde(default)]
+    pub links: Vec<Vec<String>>,
+    #[serde(default)]
+    pub cpu_affinity: Vec<String>,
+    #[serde(default)]
+    pub numa_affinity: Vec<String>,
+}
+
+impl Topology {
+    /// Cards connected to `gpu` by NVLink.
+    pub fn nvlink_peers(&self, gpu: usize) -> Vec<usize> {
+        match self.links.get(gpu) {
+            Some(links) => links
+                .iter()
+                .enumerate()
+                .filter(|(peer, link)| *peer != gpu && link.starts_with("NV"))
+                .map(|(peer, _)| peer)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// "P0 250/300 W"
+pub fn describe_power(metrics: &GpuMetrics) -> String {
+    let watts = |w: Option<f64>| match w {
+        Some(w) => format!("{:.0}", w),
+        None => String::from("?"),
+    };
+    format!(
+        "{} {}/{} W",
+        metrics.pstate,
+        watts(metrics.power_draw_watts),
+        watts(metrics.power_limit_watts)
+    )
+    .trim()
+    .to_string()
+}
+
+/// "MIG 3g.20gb,2x1g.5gb", empty if the card has no MIG devices.
+pub fn describe_mig(gpu: usize, instances: &[MigInstance]) -> String {
+    let mut profiles: Vec<(&str, usize)> = Vec::new();
+    for i in instances.iter().filter(|i| i.gpu_index == gpu) {
+        match profiles.iter_mut().find(|(p, _)| *p == i.profile) {
+            Some((_, n)) => *n += 1,
+            None => profiles.push((&i.profile, 1)),
+        }
+    }
+    if profiles.is_empty() {
+        return String::new();
+    }
+    let profiles: Vec<String> = profiles
+        .into_iter()
+        .map(|(p, n)| match n {
+            1 => p.to_string(),
+            n => format!("{}x{}", n, p),
+        })
+        .collect();
+    format!("MIG {}", profiles.join(","))
+}
+
+/// What is wrong with the card, empty if nothing.
+pub fn problems(metrics: &GpuMetrics) -> Vec<String> {
+    let mut problems: Vec<String> = metrics
+        .throttle_reasons
+        .iter()
+        .filter(|r| BAD_THROTTLE_REASONS.contains(&r.as_str()))
+        .cloned()
+        .collect();
+    if let Some(e) = metrics.ecc_uncorrected_volatile.filter(|e| *e > 0) {
+        problems.push(format!("{} uncorrected ecc errors", e));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_problems() {
+        let mut metrics = GpuMetrics {
+            power_draw_watts: Some(250.3),
+            power_limit_watts: Some(300.0),
+            pstate: String::from("P0"),
+            throttle_reasons: vec![String::from("sw_power_cap")],
+            ecc_uncorrected_volatile: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(describe_power(&metrics), "P0 250/300 W");
+        assert!(problems(&metrics).is_empty());
+        metrics
+            .throttle_reasons
+            .push(String::from("hw_thermal_slowdown"));
+        metrics.ecc_uncorrected_volatile = Some(3);
+        assert_eq!(
+            problems(&metrics),
+            vec!["hw_thermal_slowdown", "3 uncorrected ecc errors"]
+        );
+    }
+    #[test]
+    fn test_mig_and_topology() {
+        let instance = |gpu_index: usize, profile: &str| MigInstance {
+            gpu_index,
+            profile: profile.to_string(),
+            ..Default::default()
+        };
+        let instances = vec![
+            instance(0, "3g.20gb"),
+            instance(0, "1g.5gb"),
+            instance(0, "1g.5gb"),
+            instance(1, "7g.40gb"),
+        ];
+        assert_eq!(describe_mig(0, &instances), "MIG 3g.20gb,2x1g.5gb");
+        assert_eq!(describe_mig(2, &instances), "");
+        let links = |row: &[&str]| row.iter().map(|l| l.to_string()).collect();
+        let topology = Topology {
+            links: vec![
+                links(&["X", "NV12", "SYS"]),
+                links(&["NV12", "X", "SYS"]),
+                links(&["SYS", "SYS", "X"]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(topology.nvlink_peers(0), vec![1]);
+        assert!(topology.nvlink_peers(2).is_empty());
+    }
+}
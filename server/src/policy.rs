@@ -0,0 +1,459 @@
+use actix_web::get;
+use actix_web::put;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Local;
+use log::error;
+use once_cell::sync::OnceCell;
+use prettytable::row;
+use prettytable::Table;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::alert;
+use crate::alert::Alert;
+use crate::notify;
+use crate::notify::Notification;
+use crate::redis_database;
+use crate::redis_load;
+use crate::redis_save;
+use crate::ServerInfo;
+use crate::PASSWORD;
+
+const POLICIES_KEY: &str = "policies";
+const OVER_SINCE_KEY: &str = "policies:over_since";
+
+/// policy, user and scope
+type OverKey = (String, String, String);
+
+/// None until loaded from redis, a failed load is tried again on the next use
+static POLICIES: Mutex<Option<Policies>> = Mutex::new(None);
+/// when a user went over a limit, kept across server restarts
+static OVER_SINCE: OnceCell<Mutex<BTreeMap<OverKey, DateTime<Local>>>> = OnceCell::new();
+static VIOLATIONS: OnceCell<Mutex<Vec<Violation>>> = OnceCell::new();
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Policies {
+    #[serde(default)]
+    pub limits: Vec<Limit>,
+    /// who may use which hosts, users in no group may use all of them
+    #[serde(default)]
+    pub groups: BTreeMap<String, Group>,
+    /// notified about every violation
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+/// Nobody may hold more than `max_gpus` for longer than `max_hours`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Limit {
+    pub name: String,
+    pub max_gpus: usize,
+    /// 0 means right away
+    #[serde(default)]
+    pub max_hours: f64,
+    /// count the gpus of every host on its own instead of the whole cluster
+    #[serde(default)]
+    pub per_host: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Group {
+    pub members: Vec<String>,
+    /// host names, a trailing `*` matches a prefix
+    pub hosts: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Violation {
+    pub policy: String,
+    pub user: String,
+    /// "cluster" or the host name
+    pub scope: String,
+    /// "host:gpu" the user holds
+    pub gpus: Vec<String>,
+    pub since: DateTime<Local>,
+    pub message: String,
+}
+
+impl Violation {
+    fn key(&self) -> String {
+        format!("policy:{}:{}:{}", self.policy, self.user, self.scope)
+    }
+}
+
+fn host_matches(patterns: &[String], host: &str) -> bool {
+    patterns.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => host.starts_with(prefix),
+        None => host == p,
+    })
+}
+
+/// The current policies, None if they could not be loaded.
+fn policies() -> Option<Policies> {
+    let mut cached = POLICIES.lock().ok()?;
+    if cached.is_none() {
+        match redis_load(POLICIES_KEY) {
+            Ok(p) => *cached = Some(p),
+            Err(e) => error!("load policies error: {}", e),
+        }
+    }
+    cached.clone()
+}
+
+fn over_since() -> &'static Mutex<BTreeMap<OverKey, DateTime<Local>>> {
+    OVER_SINCE.get_or_init(|| {
+        // a list, json has no tuple keys
+        let over: Vec<(OverKey, DateTime<Local>)> = match redis_load(OVER_SINCE_KEY) {
+            Ok(o) => o,
+            Err(e) => {
+                error!("load policy over since error: {}", e);
+                Vec::new()
+            }
+        };
+        Mutex::new(over.into_iter().collect())
+    })
+}
+
+fn violations() -> &'static Mutex<Vec<Violation>> {
+    VIOLATIONS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// The cards every user runs processes on, as (host, gpu).
+fn holdings(
+    database: &BTreeMap<String, ServerInfo>,
+) -> BTreeMap<String, BTreeSet<(String, usize)>> {
+    let mut holdings: BTreeMap<String, BTreeSet<(String, usize)>> = BTreeMap::new();
+    for (host, server_info) in database {
+        for process in &server_info.gpu.processes {
+            if process.user.is_empty() {
+                continue;
+            }
+            holdings
+                .entry(process.user.clone())
+                .or_default()
+                .insert((host.clone(), process.gpu_index));
+        }
+    }
+    holdings
+}
+
+/// Check the holdings against the policies, `over` remembers since when a limit is exceeded.
+fn evaluate(
+    policies: &Policies,
+    holdings: &BTreeMap<String, BTreeSet<(String, usize)>>,
+    over: &mut BTreeMap<OverKey, DateTime<Local>>,
+    now: DateTime<Local>,
+) -> Vec<Violation> {
+    let slots = |gpus: &[&(String, usize)]| -> Vec<String> {
+        gpus.iter().map(|(h, g)| format!("{}:{}", h, g)).collect()
+    };
+    let mut still_over = BTreeSet::new();
+    let mut violations = Vec::new();
+    for (user, held) in holdings {
+        for limit in &policies.limits {
+            let mut scopes: BTreeMap<String, Vec<&(String, usize)>> = BTreeMap::new();
+            for gpu in held {
+                let scope = if limit.per_host {
+                    gpu.0.clone()
+                } else {
+                    String::from("cluster")
+                };
+                scopes.entry(scope).or_default().push(gpu);
+            }
+            for (scope, gpus) in scopes {
+                if gpus.len() <= limit.max_gpus {
+                    continue;
+                }
+                let key = (limit.name.clone(), user.clone(), scope.clone());
+                let since = *over.entry(key.clone()).or_insert(now);
+                still_over.insert(key);
+                let hours = (now - since).num_seconds() as f64 / 3600.0;
+                if hours < limit.max_hours {
+                    continue;
+                }
+                violations.push(Violation {
+                    policy: limit.name.clone(),
+                    user: user.clone(),
+                    message: format!(
+                        "{} holds {} gpus on {} for {:.1} h, the limit is {} gpus for {} h",
+                        user,
+                        gpus.len(),
+                        scope,
+                        hours,
+                        limit.max_gpus,
+                        limit.max_hours
+                    ),
+                    scope,
+                    gpus: slots(&gpus),
+                    since,
+                });
+            }
+        }
+        // a user may use the hosts of any of their groups
+        let groups: Vec<(&String, &Group)> = policies
+            .groups
+            .iter()
+            .filter(|(_, g)| g.members.contains(user))
+            .collect();
+        if groups.is_empty() {
+            continue;
+        }
+        let mut outside: BTreeMap<&str, Vec<&(String, usize)>> = BTreeMap::new();
+        for gpu in held {
+            if !groups.iter().any(|(_, g)| host_matches(&g.hosts, &gpu.0)) {
+                outside.entry(gpu.0.as_str()).or_default().push(gpu);
+            }
+        }
+        for (host, gpus) in outside {
+            let names: Vec<&str> = groups.iter().map(|(n, _)| n.as_str()).collect();
+            let key = (
+                String::from("allowed hosts"),
+                user.clone(),
+                host.to_string(),
+            );
+            let since = *over.entry(key.clone()).or_insert(now);
+            still_over.insert(key);
+            violations.push(Violation {
+                policy: String::from("allowed hosts"),
+                user: user.clone(),
+                scope: host.to_string(),
+                gpus: slots(&gpus),
+                since,
+                message: format!(
+                    "{} of group {} runs on {}, which the group may not use",
+                    user,
+                    names.join(","),
+                    host
+                ),
+            });
+        }
+    }
+    over.retain(|key, _| still_over.contains(key));
+    violations
+}
+
+/// Check the policies once every `interval`, a violation lasts hours,
+/// checking on every update would scan all hosts once per host.
+pub fn start_watch(interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = tokio::task::spawn_blocking(check).await {
+                error!("policy check error: {}", e);
+            }
+        }
+    });
+}
+
+/// Notifies the users and admins about new violations.
+fn check() {
+    let policies = match policies() {
+        Some(p) => p,
+        None => return,
+    };
+    if policies.limits.is_empty() && policies.groups.is_empty() {
+        return;
+    }
+    let database = match redis_database() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("get redis database failed: {}", e);
+            return;
+        }
+    };
+    let current = match over_since().lock() {
+        Ok(mut over) => {
+            let before = over.clone();
+            let current = evaluate(&policies, &holdings(&database), &mut over, Local::now());
+            if *over != before {
+                if let Err(e) = redis_save(OVER_SINCE_KEY, &over.iter().collect::<Vec<_>>()) {
+                    error!("save policy over since error: {}", e);
+                }
+            }
+            current
+        }
+        Err(_) => return,
+    };
+    let previous = match violations().lock() {
+        Ok(mut v) => std::mem::replace(&mut *v, current.clone()),
+        Err(_) => return,
+    };
+    for gone in previous
+        .iter()
+        .filter(|p| !current.iter().any(|c| c.key() == p.key()))
+    {
+        alert::resolve(&gone.key());
+    }
+    for violation in current {
+        let alert = Alert::new(
+            "policy",
+            &violation.scope,
+            &violation.user,
+            &violation.message,
+        );
+        if !alert::raise(&violation.key(), alert) {
+            continue;
+        }
+        let mut recipients = vec![violation.user.clone()];
+        recipients.extend(policies.admins.iter().cloned());
+        recipients.sort();
+        recipients.dedup();
+        for recipient in recipients {
+            notify::notify_user(
+                &recipient,
+                Notification {
+                    kind: String::from("policy_violation"),
+                    user: violation.user.clone(),
+                    subject: format!(
+                        "policy \"{}\" violated by {}",
+                        violation.policy, violation.user
+                    ),
+                    message: violation.message.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// Current violations for `/info`, None if there are none.
+pub fn violation_table() -> Option<Table> {
+    let violations = violations().lock().ok()?;
+    if violations.is_empty() {
+        return None;
+    }
+    let mut table = Table::new();
+    table.add_row(row![
+        c -> "policy",
+        c -> "user",
+        c -> "scope",
+        c -> "gpus",
+        c -> "since"
+    ]);
+    for v in violations.iter() {
+        table.add_row(row![
+            v.policy,
+            c -> v.user,
+            c -> v.scope,
+            v.gpus.join("\n"),
+            c -> v.since.format("%Y-%m-%d %H:%M")
+        ]);
+    }
+    Some(table)
+}
+
+#[get("/api/v1/policies")]
+async fn get_policies() -> impl Responder {
+    match policies() {
+        Some(p) => HttpResponse::Ok().json(p),
+        None => HttpResponse::InternalServerError().body("load policies failed"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PolicyQuery {
+    /// the password of /update
+    password: String,
+}
+
+/// Replace all policies.
+#[put("/api/v1/policies")]
+async fn set_policies(
+    query: web::Query<PolicyQuery>,
+    new_policies: web::Json<Policies>,
+) -> impl Responder {
+    if query.password != PASSWORD {
+        return HttpResponse::Forbidden().body("password wrong!");
+    }
+    let new_policies = new_policies.into_inner();
+    let mut names = BTreeSet::new();
+    if new_policies
+        .limits
+        .iter()
+        .any(|l| l.name.trim().is_empty() || !names.insert(l.name.clone()))
+    {
+        return HttpResponse::BadRequest().body("every limit needs a unique name");
+    }
+    if let Err(e) = redis_save(POLICIES_KEY, &new_policies) {
+        error!("save policies error: {}", e);
+        return HttpResponse::InternalServerError().body("save policies failed");
+    }
+    match POLICIES.lock() {
+        Ok(mut p) => {
+            *p = Some(new_policies.clone());
+            HttpResponse::Ok().json(new_policies)
+        }
+        Err(_) => HttpResponse::InternalServerError().body("policy lock poisoned"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ViolationQuery {
+    user: Option<String>,
+}
+
+#[get("/api/v1/policies/violations")]
+async fn list_violations(query: web::Query<ViolationQuery>) -> impl Responder {
+    let list: Vec<Violation> = match violations().lock() {
+        Ok(v) => v
+            .iter()
+            .filter(|v| query.user.as_ref().is_none_or(|u| &v.user == u))
+            .cloned()
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    HttpResponse::Ok().json(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    #[test]
+    fn test_evaluate() {
+        let policies = Policies {
+            limits: vec![Limit {
+                name: String::from("4 gpus for 48h"),
+                max_gpus: 4,
+                max_hours: 48.0,
+                per_host: false,
+            }],
+            groups: BTreeMap::from([(
+                String::from("students"),
+                Group {
+                    members: vec![String::from("bob")],
+                    hosts: vec![String::from("node1*")],
+                },
+            )]),
+            admins: Vec::new(),
+        };
+        let mut holdings = BTreeMap::new();
+        holdings.insert(
+            String::from("alice"),
+            (0..5).map(|g| (String::from("node2"), g)).collect(),
+        );
+        holdings.insert(
+            String::from("bob"),
+            BTreeSet::from([(String::from("node2"), 7)]),
+        );
+        let mut over = BTreeMap::new();
+        let start = Local::now();
+        let v = evaluate(&policies, &holdings, &mut over, start);
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].policy, "allowed hosts");
+        let v = evaluate(&policies, &holdings, &mut over, start + Duration::hours(49));
+        assert_eq!(v.len(), 2);
+        assert_eq!(v[0].user, "alice");
+        assert_eq!(v[0].since, start);
+        holdings.remove("alice");
+        evaluate(&policies, &holdings, &mut over, start + Duration::hours(50));
+        assert_eq!(over.len(), 1);
+    }
+}
@@ -0,0 +1,136 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::workload;
+use crate::workload::Workload;
+
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+/// rows of the users and top columns in /info
+pub const INFO_ROWS: usize = 3;
+/// longest command shown in /info
+const MAX_COMMAND: usize = 32;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    #[serde(default)]
+    pub user: String,
+    #[serde(default)]
+    pub command: String,
+    /// of one core
+    #[serde(default)]
+    pub cpu_percent: f64,
+    #[serde(default)]
+    pub rss_bytes: u64,
+    #[serde(default)]
+    pub runtime_seconds: u64,
+    #[serde(default)]
+    pub workload: Option<Workload>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct UserUsage {
+    pub user: String,
+    #[serde(default)]
+    pub cpu_percent: f64,
+    #[serde(default)]
+    pub rss_bytes: u64,
+    #[serde(default)]
+    pub processes: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ProcessTop {
+    #[serde(default)]
+    pub top_cpu: Vec<ProcessUsage>,
+    #[serde(default)]
+    pub top_memory: Vec<ProcessUsage>,
+    #[serde(default)]
+    pub users: Vec<UserUsage>,
+}
+
+/// "alice 590 % 12.3 GiB" per line, the `rows` users with the most cpu.
+pub fn describe_users(top: &ProcessTop, rows: usize) -> String {
+    top.users
+        .iter()
+        .take(rows)
+        .map(|u| {
+            format!(
+                "{} {:.0} % {:.1} GiB",
+                u.user,
+                u.cpu_percent,
+                u.rss_bytes as f64 / GIB
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// "390 % alice python train.py" per line, the `rows` processes with the most cpu.
+pub fn describe_top(top: &ProcessTop, rows: usize) -> String {
+    top.top_cpu
+        .iter()
+        .take(rows)
+        .map(|p| {
+            let owner = match &p.workload {
+                Some(w) => workload::describe(w, &p.user),
+                None => p.user.clone(),
+            };
+            // "/opt/conda/bin/python train.py" -> "python train.py"
+            let (program, arguments) = p.command.split_once(' ').unwrap_or((&p.command, ""));
+            let program = program.rsplit('/').next().unwrap_or(program);
+            let command: String = format!("{} {}", program, arguments)
+                .trim()
+                .chars()
+                .take(MAX_COMMAND)
+                .collect();
+            format!("{:.0} % {} {}", p.cpu_percent, owner, command)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_describe() {
+        let top = ProcessTop {
+            top_cpu: vec![ProcessUsage {
+                pid: 4242,
+                user: String::from("alice"),
+                command: String::from("/opt/conda/bin/python train.py --data /scratch/alice"),
+                cpu_percent: 390.4,
+                workload: Some(Workload {
+                    kind: String::from("slurm"),
+                    id: String::from("12345"),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            users: vec![
+                UserUsage {
+                    user: String::from("alice"),
+                    cpu_percent: 590.0,
+                    rss_bytes: 12 * GIB as u64,
+                    processes: 2,
+                },
+                UserUsage {
+                    user: String::from("root"),
+                    cpu_percent: 0.5,
+                    rss_bytes: GIB as u64 / 2,
+                    processes: 120,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            describe_users(&top, 3),
+            "alice 590 % 12.0 GiB\nroot 0 % 0.5 GiB"
+        );
+        assert_eq!(
+            describe_top(&top, 3),
+            "390 % job 12345 (alice) python train.py --data /scratch/"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use chrono::Local;
+use log::error;
+use log::info;
+use log::warn;
+use once_cell::sync::OnceCell;
+use prettytable::row;
+use prettytable::Table;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::store_server_info;
+use crate::ServerError;
+use crate::ServerInfo;
+
+static PULL_STATUS: OnceCell<Mutex<BTreeMap<String, ScrapeStatus>>> = OnceCell::new();
+
+#[derive(Clone, Default)]
+struct ScrapeStatus {
+    hostname: String,
+    last_scrape: String,
+    last_success: String,
+    duration_ms: u128,
+    error: String,
+    consecutive_errors: u64,
+    total_errors: u64,
+}
+
+fn pull_status() -> &'static Mutex<BTreeMap<String, ScrapeStatus>> {
+    PULL_STATUS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+async fn scrape(client: &reqwest::Client, target: &str) -> Result<ServerInfo, ServerError> {
+    let response = client.get(target).send().await?;
+    if !response.status().is_success() {
+        return Err(ServerError::HttpStatusError {
+            status: response.status().as_u16(),
+        });
+    }
+    let server_info: ServerInfo = response.json().await?;
+    Ok(server_info)
+}
+
+async fn scrape_loop(target: String, interval: Duration, timeout: Duration) {
+    let client = match reqwest::Client::builder().timeout(timeout).build() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("build pull client for {} error: {}", target, e);
+            return;
+        }
+    };
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let start = Instant::now();
+        // ingest exactly like a pushed /update
+        let result = match scrape(&client, &target).await {
+            Ok(server_info) => match store_server_info(&server_info) {
+                Ok(_) => Ok(server_info.hostname),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        let now = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let mut status_map = match pull_status().lock() {
+            Ok(s) => s,
+            Err(e) => {
+                error!("lock pull status error: {}", e);
+                continue;
+            }
+        };
+        let status = status_map.entry(target.clone()).or_default();
+        status.last_scrape = now.clone();
+        status.duration_ms = start.elapsed().as_millis();
+        match result {
+            Ok(hostname) => {
+                status.hostname = hostname;
+                status.last_success = now;
+                status.error = String::new();
+                status.consecutive_errors = 0;
+            }
+            Err(e) => {
+                warn!("scrape {} error: {}", target, e);
+                status.error = e.to_string();
+                status.consecutive_errors += 1;
+                status.total_errors += 1;
+            }
+        }
+    }
+}
+
+/// Spawn one scrape task per target.
+pub fn start(targets: &[String], interval: u64, timeout: u64) {
+    let interval = Duration::from_secs(interval.max(1));
+    let timeout = Duration::from_secs(timeout.max(1));
+    for target in targets {
+        info!("pull mode: scraping {} every {:?}", target, interval);
+        if let Ok(mut s) = pull_status().lock() {
+            s.insert(target.clone(), ScrapeStatus::default());
+        }
+        tokio::spawn(scrape_loop(target.clone(), interval, timeout));
+    }
+}
+
+/// Last scrape status of every target, None if pull mode is off.
+pub fn status_table() -> Option<Table> {
+    let status_map = pull_status().lock().ok()?;
+    if status_map.is_empty() {
+        return None;
+    }
+    let mut table = Table::new();
+    table.add_row(row![
+        c -> "pull target",
+        c -> "name",
+        c -> "status",
+        c -> "last scrape",
+        c -> "last success",
+        c -> "time",
+        c -> "errors",
+        c -> "last error"
+    ]);
+    for (target, status) in status_map.iter() {
+        let state = if status.last_scrape.is_empty() {
+            String::from("pending")
+        } else if status.consecutive_errors == 0 {
+            String::from("ok")
+        } else {
+            format!("failed x{}", status.consecutive_errors)
+        };
+        table.add_row(row![
+            target,
+            c -> status.hostname,
+            c -> state,
+            c -> status.last_scrape,
+            c -> status.last_success,
+            c -> format!("{} ms", status.duration_ms),
+            c -> status.total_errors,
+            status.error
+        ]);
+    }
+    Some(table)
+}
@@ -0,0 +1,733 @@
+//! Small filter language for `?q=`, for example
+//! `gpu.mem_free > 10GiB and label.rack = "A"` or `host ~ "node3*" and gpu.util < 5`.
+//!
+//! GPU fields hold one value per card, a comparison on them is true when any card matches,
+//! `!=` and `!~` are true when no card has the value.
+//!
+//! History APIs evaluate the same language over their own rows, see [`Record`].
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::sensors;
+use crate::ServerInfo;
+
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    pub message: String,
+    /// char offset in the query string
+    pub position: usize,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.position + 1)
+    }
+}
+
+impl QueryError {
+    fn new(message: &str, position: usize) -> QueryError {
+        QueryError {
+            message: message.to_string(),
+            position,
+        }
+    }
+
+    /// The query with a caret under the failing position.
+    pub fn explain(&self, query: &str) -> String {
+        format!("{}\n{}\n{}^\n", self, query, " ".repeat(self.position))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Host,
+    CpuUser,
+    CpuSystem,
+    CpuIdle,
+    CpuTemp,
+    CpuIowait,
+    CpuSteal,
+    CpuLoad,
+    DiskUsed,
+    Power,
+    MemUsed,
+    MemTotal,
+    MemFree,
+    SwapUsed,
+    SwapTotal,
+    GpuCount,
+    GpuName,
+    GpuDriver,
+    GpuUtil,
+    GpuMemUtil,
+    GpuTemp,
+    GpuMemTotal,
+    GpuMemFree,
+    GpuMemUsed,
+    GpuUser,
+    GpuPower,
+    GpuEcc,
+}
+
+const FIELDS: [(&str, Field); 27] = [
+    ("host", Field::Host),
+    ("cpu.user", Field::CpuUser),
+    ("cpu.system", Field::CpuSystem),
+    ("cpu.idle", Field::CpuIdle),
+    ("cpu.temp", Field::CpuTemp),
+    ("cpu.iowait", Field::CpuIowait),
+    ("cpu.steal", Field::CpuSteal),
+    ("cpu.load", Field::CpuLoad),
+    ("disk.used", Field::DiskUsed),
+    ("power", Field::Power),
+    ("mem.used", Field::MemUsed),
+    ("mem.total", Field::MemTotal),
+    ("mem.free", Field::MemFree),
+    ("swap.used", Field::SwapUsed),
+    ("swap.total", Field::SwapTotal),
+    ("gpu.count", Field::GpuCount),
+    ("gpu.name", Field::GpuName),
+    ("gpu.driver", Field::GpuDriver),
+    ("gpu.util", Field::GpuUtil),
+    ("gpu.mem_util", Field::GpuMemUtil),
+    ("gpu.temp", Field::GpuTemp),
+    ("gpu.mem_total", Field::GpuMemTotal),
+    ("gpu.mem_free", Field::GpuMemFree),
+    ("gpu.mem_used", Field::GpuMemUsed),
+    ("gpu.user", Field::GpuUser),
+    ("gpu.power", Field::GpuPower),
+    ("gpu.ecc", Field::GpuEcc),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldRef {
+    Known(Field),
+    Label(String),
+    /// field of a history row
+    Column(&'static str),
+}
+
+/// A history row `?q=` and `sort=` work on, e.g. a job or an accounting record.
+pub trait Record {
+    /// the field names, checked when the query is parsed
+    const FIELDS: &'static [&'static str];
+    fn values(&self, field: &str) -> Vec<Value>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Glob,
+    NotGlob,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(FieldRef, Op, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Text(String),
+    Op(Op),
+    LParen,
+    RParen,
+}
+
+/// "10GiB" -> bytes, "5%" -> 5
+pub fn parse_number(input: &str) -> Option<f64> {
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let scale = match unit.to_lowercase().as_str() {
+        "" | "%" | "c" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "t" | "tb" => 1e12,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some(number * scale)
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, QueryError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = match c {
+            '(' => {
+                i += 1;
+                Token::LParen
+            }
+            ')' => {
+                i += 1;
+                Token::RParen
+            }
+            '"' | '\'' => {
+                i += 1;
+                let mut text = String::new();
+                loop {
+                    match chars.get(i) {
+                        Some(&q) if q == c => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            text.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&ch) => {
+                            text.push(ch);
+                            i += 1;
+                        }
+                        None => return Err(QueryError::new("unterminated string", start)),
+                    }
+                }
+                i += 1;
+                Token::Text(text)
+            }
+            '=' | '!' | '>' | '<' | '~' => {
+                let next = chars.get(i + 1).copied();
+                let (op, len) = match (c, next) {
+                    ('=', Some('=')) => (Op::Eq, 2),
+                    ('=', _) => (Op::Eq, 1),
+                    ('!', Some('=')) => (Op::Ne, 2),
+                    ('!', Some('~')) => (Op::NotGlob, 2),
+                    ('>', Some('=')) => (Op::Ge, 2),
+                    ('>', _) => (Op::Gt, 1),
+                    ('<', Some('=')) => (Op::Le, 2),
+                    ('<', _) => (Op::Lt, 1),
+                    ('~', _) => (Op::Glob, 1),
+                    _ => return Err(QueryError::new("unknown operator '!'", start)),
+                };
+                i += len;
+                Token::Op(op)
+            }
+            _ => {
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !"()=!<>~\"'".contains(chars[i])
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match parse_number(&word) {
+                    Some(n) if c.is_ascii_digit() || c == '-' || c == '.' => Token::Number(n),
+                    // an address or a date, e.g. host = 10.0.0.5
+                    None if c.is_ascii_digit() => Token::Text(word),
+                    _ => Token::Ident(word),
+                }
+            }
+        };
+        tokens.push((token, start));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+    resolve: &'a dyn Fn(&str) -> Option<FieldRef>,
+    /// field names for the error message
+    known: &'a [&'a str],
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|t| t.1).unwrap_or(self.end)
+    }
+
+    fn keyword(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(i)) if i.eq_ignore_ascii_case(word))
+    }
+
+    fn or_expr(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.and_expr()?;
+        while self.keyword("or") {
+            self.pos += 1;
+            let right = self.and_expr()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.not_expr()?;
+        while self.keyword("and") {
+            self.pos += 1;
+            let right = self.not_expr()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn not_expr(&mut self) -> Result<Expr, QueryError> {
+        if self.keyword("not") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not_expr()?)));
+        }
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let expr = self.or_expr()?;
+            if self.peek() != Some(&Token::RParen) {
+                return Err(QueryError::new("expected ')'", self.position()));
+            }
+            self.pos += 1;
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, QueryError> {
+        let field_pos = self.position();
+        let field = match self.peek() {
+            Some(Token::Ident(name)) => (self.resolve)(name)
+                .ok_or_else(|| QueryError::new(&unknown_field(name, self.known), field_pos))?,
+            _ => return Err(QueryError::new("expected a field name", field_pos)),
+        };
+        self.pos += 1;
+        let op_pos = self.position();
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => {
+                return Err(QueryError::new(
+                    "expected an operator (=, !=, >, >=, <, <=, ~, !~)",
+                    op_pos,
+                ))
+            }
+        };
+        self.pos += 1;
+        let value_pos = self.position();
+        let value = match self.peek() {
+            Some(Token::Number(n)) => Value::Number(*n),
+            Some(Token::Text(t)) => Value::Text(t.clone()),
+            Some(Token::Ident(i)) => Value::Text(i.clone()),
+            _ => return Err(QueryError::new("expected a value", value_pos)),
+        };
+        if matches!(op, Op::Gt | Op::Ge | Op::Lt | Op::Le) && !matches!(value, Value::Number(_)) {
+            return Err(QueryError::new(
+                "ordering comparison needs a number",
+                value_pos,
+            ));
+        }
+        self.pos += 1;
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+fn unknown_field(name: &str, known: &[&str]) -> String {
+    format!("unknown field '{}' (known: {})", name, known.join(", "))
+}
+
+fn host_fields() -> Vec<&'static str> {
+    let mut known: Vec<&str> = FIELDS.iter().map(|f| f.0).collect();
+    known.push("label.<key>");
+    known
+}
+
+fn resolve_column<R: Record>(name: &str) -> Option<FieldRef> {
+    R::FIELDS
+        .iter()
+        .find(|f| **f == name)
+        .map(|f| FieldRef::Column(f))
+}
+
+pub fn resolve_field(name: &str) -> Option<FieldRef> {
+    if let Some(key) = name.strip_prefix("label.") {
+        if !key.is_empty() {
+            return Some(FieldRef::Label(key.to_string()));
+        }
+    }
+    FIELDS
+        .iter()
+        .find(|f| f.0 == name)
+        .map(|f| FieldRef::Known(f.1))
+}
+
+pub fn parse(input: &str) -> Result<Expr, QueryError> {
+    parse_with(input, &resolve_field, &host_fields())
+}
+
+/// A query over the rows of a history API.
+pub fn parse_for<R: Record>(input: &str) -> Result<Expr, QueryError> {
+    parse_with(input, &resolve_column::<R>, R::FIELDS)
+}
+
+fn parse_with(
+    input: &str,
+    resolve: &dyn Fn(&str) -> Option<FieldRef>,
+    known: &[&str],
+) -> Result<Expr, QueryError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: input.chars().count(),
+        resolve,
+        known,
+    };
+    let expr = parser.or_expr()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(QueryError::new(
+            "expected 'and', 'or' or end of query",
+            parser.position(),
+        ));
+    }
+    Ok(expr)
+}
+
+fn percent(value: Option<&f32>) -> Vec<Value> {
+    value
+        .map(|v| vec![Value::Number(*v as f64 * 100.0)])
+        .unwrap_or_default()
+}
+
+fn bytes(hm: &std::collections::HashMap<String, String>, key: &str) -> Vec<Value> {
+    hm.get(key)
+        .and_then(|v| v.parse::<f64>().ok())
+        .map(|v| vec![Value::Number(v)])
+        .unwrap_or_default()
+}
+
+fn number_or_text(input: &str) -> Value {
+    let trimmed: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    match parse_number(&trimmed) {
+        Some(n) => Value::Number(n),
+        None => Value::Text(input.to_string()),
+    }
+}
+
+/// Values of one field for a host, gpu users must already be processed.
+pub fn field_values(hostname: &str, server_info: &ServerInfo, field: &FieldRef) -> Vec<Value> {
+    let field = match field {
+        FieldRef::Label(key) => {
+            return server_info
+                .labels
+                .get(key)
+                .map(|v| vec![Value::Text(v.clone())])
+                .unwrap_or_default()
+        }
+        FieldRef::Known(f) => *f,
+        FieldRef::Column(_) => return Vec::new(),
+    };
+    let cards = server_info
+        .gpu
+        .details
+        .iter()
+        .filter(|d| !d.name.is_empty());
+    match field {
+        Field::Host => vec![Value::Text(hostname.to_string())],
+        Field::CpuUser => percent(server_info.cpu.get("user")),
+        Field::CpuSystem => percent(server_info.cpu.get("system")),
+        Field::CpuIdle => percent(server_info.cpu.get("idle")),
+        Field::CpuTemp => sensors::max_package_temperature(&server_info.sensors)
+            .or(server_info.cpu.get("temp").map(|t| *t as f64))
+            .map(|t| vec![Value::Number(t)])
+            .unwrap_or_default(),
+        Field::CpuIowait => percent(server_info.cpu.get("iowait")),
+        Field::CpuSteal => percent(server_info.cpu.get("steal")),
+        Field::CpuLoad => server_info
+            .cpu_detail
+            .as_ref()
+            .map(|d| vec![Value::Number(d.load_average[0] as f64)])
+            .unwrap_or_default(),
+        Field::DiskUsed => server_info
+            .disks
+            .iter()
+            .map(|m| Value::Number(m.used_ratio() * 100.0))
+            .collect(),
+        Field::Power => sensors::total_power(&server_info.sensors)
+            .map(|w| vec![Value::Number(w)])
+            .unwrap_or_default(),
+        Field::MemUsed => bytes(&server_info.mem, "used_bytes"),
+        Field::MemTotal => bytes(&server_info.mem, "total_bytes"),
+        Field::MemFree => {
+            match (
+                bytes(&server_info.mem, "used_bytes").first(),
+                bytes(&server_info.mem, "total_bytes").first(),
+            ) {
+                (Some(Value::Number(u)), Some(Value::Number(t))) => vec![Value::Number(t - u)],
+                _ => Vec::new(),
+            }
+        }
+        Field::SwapUsed => bytes(&server_info.swap, "used_bytes"),
+        Field::SwapTotal => bytes(&server_info.swap, "total_bytes"),
+        Field::GpuCount => vec![Value::Number(cards.count() as f64)],
+        Field::GpuName => cards.map(|d| Value::Text(d.name.clone())).collect(),
+        Field::GpuDriver => cards
+            .map(|d| Value::Text(d.driver_version.clone()))
+            .collect(),
+        Field::GpuUtil => cards.map(|d| number_or_text(&d.utilization_gpu)).collect(),
+        Field::GpuMemUtil => cards
+            .map(|d| number_or_text(&d.utilization_memory))
+            .collect(),
+        Field::GpuTemp => cards.map(|d| number_or_text(&d.temperature_gpu)).collect(),
+        Field::GpuMemTotal => cards.map(|d| number_or_text(&d.memory_total)).collect(),
+        Field::GpuMemFree => cards.map(|d| number_or_text(&d.memory_free)).collect(),
+        Field::GpuMemUsed => cards.map(|d| number_or_text(&d.memory_used)).collect(),
+        Field::GpuUser => server_info
+            .gpu
+            .users
+            .iter()
+            .filter(|u| u.as_str() != "null")
+            .map(|u| Value::Text(u.clone()))
+            .collect(),
+        Field::GpuPower => cards
+            .filter_map(|d| d.metrics.as_ref()?.power_draw_watts)
+            .map(Value::Number)
+            .collect(),
+        Field::GpuEcc => cards
+            .filter_map(|d| d.metrics.as_ref()?.ecc_uncorrected_volatile)
+            .map(|e| Value::Number(e as f64))
+            .collect(),
+    }
+}
+
+/// `*` matches any run of characters, `?` a single one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+fn compare(left: &Value, op: Op, right: &Value) -> bool {
+    let text = |v: &Value| match v {
+        Value::Number(n) => n.to_string(),
+        Value::Text(t) => t.clone(),
+    };
+    match op {
+        Op::Glob => glob_match(&text(right), &text(left)),
+        Op::NotGlob => !glob_match(&text(right), &text(left)),
+        _ => {
+            let ordering = match (left, right) {
+                (Value::Number(l), Value::Number(r)) => l.partial_cmp(r),
+                (Value::Text(l), Value::Text(r)) => Some(l.cmp(r)),
+                // a label or host name holding a number, e.g. label.rack = 1
+                _ if matches!(op, Op::Eq | Op::Ne) => Some(text(left).cmp(&text(right))),
+                // "Err" from nvidia-smi is never ordered against a number
+                _ => None,
+            };
+            match (op, ordering) {
+                (Op::Ne, None) => true,
+                (_, None) => false,
+                (Op::Eq, Some(o)) => o == Ordering::Equal,
+                (Op::Ne, Some(o)) => o != Ordering::Equal,
+                (Op::Gt, Some(o)) => o == Ordering::Greater,
+                (Op::Ge, Some(o)) => o != Ordering::Less,
+                (Op::Lt, Some(o)) => o == Ordering::Less,
+                (Op::Le, Some(o)) => o != Ordering::Greater,
+                _ => false,
+            }
+        }
+    }
+}
+
+fn record_values<R: Record>(record: &R, field: &FieldRef) -> Vec<Value> {
+    match field {
+        FieldRef::Column(name) => record.values(name),
+        _ => Vec::new(),
+    }
+}
+
+impl Expr {
+    pub fn matches(&self, hostname: &str, server_info: &ServerInfo) -> bool {
+        self.eval(&|field| field_values(hostname, server_info, field))
+    }
+
+    pub fn matches_record<R: Record>(&self, record: &R) -> bool {
+        self.eval(&|field| record_values(record, field))
+    }
+
+    fn eval(&self, values_of: &dyn Fn(&FieldRef) -> Vec<Value>) -> bool {
+        match self {
+            Expr::And(l, r) => l.eval(values_of) && r.eval(values_of),
+            Expr::Or(l, r) => l.eval(values_of) || r.eval(values_of),
+            Expr::Not(e) => !e.eval(values_of),
+            Expr::Compare(field, op, value) => {
+                let values = values_of(field);
+                match op {
+                    // "no card is used by alice" rather than "some card is not"
+                    Op::Ne | Op::NotGlob => values.iter().all(|v| compare(v, *op, value)),
+                    _ => values.iter().any(|v| compare(v, *op, value)),
+                }
+            }
+        }
+    }
+}
+
+/// `sort=gpu.mem_free` or `sort=-gpu.mem_free` for descending.
+pub struct SortKey {
+    field: FieldRef,
+    descending: bool,
+}
+
+impl SortKey {
+    pub fn parse(input: &str) -> Result<SortKey, QueryError> {
+        SortKey::parse_with(input, &resolve_field, &host_fields())
+    }
+
+    /// A sort key over the rows of a history API.
+    pub fn parse_for<R: Record>(input: &str) -> Result<SortKey, QueryError> {
+        SortKey::parse_with(input, &resolve_column::<R>, R::FIELDS)
+    }
+
+    fn parse_with(
+        input: &str,
+        resolve: &dyn Fn(&str) -> Option<FieldRef>,
+        known: &[&str],
+    ) -> Result<SortKey, QueryError> {
+        let (name, descending) = match input.strip_prefix('-') {
+            Some(n) => (n, true),
+            None => (input, false),
+        };
+        let field =
+            resolve(name.trim()).ok_or_else(|| QueryError::new(&unknown_field(name, known), 0))?;
+        Ok(SortKey { field, descending })
+    }
+
+    /// GPU fields sort by their largest value.
+    fn key(values: Vec<Value>) -> Option<Value> {
+        values.into_iter().max_by(|a, b| match (a, b) {
+            (Value::Number(x), Value::Number(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+            (Value::Number(_), Value::Text(_)) => Ordering::Greater,
+            (Value::Text(_), Value::Number(_)) => Ordering::Less,
+            (Value::Text(x), Value::Text(y)) => x.cmp(y),
+        })
+    }
+
+    fn order(&self, ka: &Option<Value>, kb: &Option<Value>) -> Ordering {
+        let ordering = match (ka, kb) {
+            (Some(Value::Number(x)), Some(Value::Number(y))) => {
+                x.partial_cmp(y).unwrap_or(Ordering::Equal)
+            }
+            (Some(Value::Text(x)), Some(Value::Text(y))) => x.cmp(y),
+            // rows without the field always go last
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            _ => Ordering::Equal,
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    pub fn sort(&self, hosts: &mut [(String, ServerInfo)]) {
+        hosts.sort_by(|a, b| {
+            let ka = SortKey::key(field_values(&a.0, &a.1, &self.field));
+            let kb = SortKey::key(field_values(&b.0, &b.1, &self.field));
+            self.order(&ka, &kb)
+        });
+    }
+
+    /// Stable, rows with equal keys keep their order.
+    pub fn sort_records<R: Record>(&self, records: &mut [R]) {
+        records.sort_by(|a, b| {
+            let ka = SortKey::key(record_values(a, &self.field));
+            let kb = SortKey::key(record_values(b, &self.field));
+            self.order(&ka, &kb)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse_query() {
+        let expr = parse(r#"gpu.mem_free > 10GiB and label.rack = "A""#).unwrap();
+        assert_eq!(
+            expr,
+            Expr::And(
+                Box::new(Expr::Compare(
+                    FieldRef::Known(Field::GpuMemFree),
+                    Op::Gt,
+                    Value::Number(10.0 * 1024.0 * 1024.0 * 1024.0)
+                )),
+                Box::new(Expr::Compare(
+                    FieldRef::Label(String::from("rack")),
+                    Op::Eq,
+                    Value::Text(String::from("A"))
+                ))
+            )
+        );
+        assert!(parse(r#"host ~ "node3*" and (gpu.util < 5 or not gpu.user = alice)"#).is_ok());
+    }
+    #[test]
+    fn test_parse_error() {
+        let e = parse("gpu.util <").unwrap_err();
+        assert_eq!(e.message, "expected a value");
+        assert_eq!(e.position, 10);
+        let e = parse("gpu.utl < 5").unwrap_err();
+        assert!(e.message.starts_with("unknown field 'gpu.utl'"));
+        let e = parse("host ~ \"node").unwrap_err();
+        assert_eq!(e.position, 7);
+    }
+    #[test]
+    fn test_compare_mixed() {
+        let rack = Value::Text(String::from("1"));
+        let expr = parse("label.rack = 1").unwrap();
+        assert!(matches!(&expr, Expr::Compare(_, Op::Eq, Value::Number(_))));
+        assert!(compare(&rack, Op::Eq, &Value::Number(1.0)));
+        assert!(!compare(&rack, Op::Ne, &Value::Number(1.0)));
+        assert!(compare(&rack, Op::Ne, &Value::Number(2.0)));
+        let err = Value::Text(String::from("[N/A]"));
+        assert!(!compare(&err, Op::Gt, &Value::Number(5.0)));
+        assert!(parse("host = 10.0.0.5").is_ok());
+    }
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("node3*", "node31"));
+        assert!(glob_match("n?de*1", "node41"));
+        assert!(!glob_match("node3*", "node41"));
+    }
+}
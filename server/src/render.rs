@@ -0,0 +1,273 @@
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use prettytable::Cell;
+use prettytable::Row;
+use prettytable::Table;
+use serde_json::Map;
+use serde_json::Value;
+use std::fmt::Write as _;
+
+/// One column of the `/info` table.
+pub struct Column {
+    pub key: &'static str,
+    pub title: &'static str,
+    /// explanation printed below the plain table
+    pub note: Option<&'static str>,
+    /// cells hold one line per item (interface, gpu, user...)
+    pub list: bool,
+}
+
+/// Rows shared by every output format of `/info`, cells use `\n` between items.
+pub struct InfoTable {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+    Markdown,
+    Html,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name.trim().to_lowercase().as_str() {
+            "table" | "text" | "txt" => Some(Format::Table),
+            "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            "markdown" | "md" => Some(Format::Markdown),
+            "html" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    fn from_mime(mime: &str) -> Option<Format> {
+        match mime.split(';').next().unwrap_or_default().trim() {
+            "text/plain" => Some(Format::Table),
+            "application/json" => Some(Format::Json),
+            "text/csv" => Some(Format::Csv),
+            "text/markdown" => Some(Format::Markdown),
+            "text/html" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    /// `?format=` wins over the `Accept` header, plain table by default.
+    pub fn negotiate(req: &HttpRequest, format: Option<&str>) -> Result<Format, String> {
+        if let Some(f) = format {
+            return Format::from_name(f).ok_or(format!(
+                "unknown format: {} (table, json, csv, markdown, html)",
+                f
+            ));
+        }
+        let accept = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|a| a.to_str().ok())
+            .unwrap_or_default();
+        // browsers send "text/html,..." and curl "*/*", the first known type is used
+        let format = accept
+            .split(',')
+            .find_map(Format::from_mime)
+            .unwrap_or(Format::Table);
+        Ok(format)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Table => "text/plain; charset=utf-8",
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Markdown => "text/markdown; charset=utf-8",
+            Format::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+fn csv_escape(input: &str) -> String {
+    if input.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", input.replace('"', "\"\""))
+    } else {
+        input.to_string()
+    }
+}
+
+pub fn html_escape(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl InfoTable {
+    pub fn new(columns: Vec<Column>) -> InfoTable {
+        InfoTable {
+            columns,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Table => self.to_table().to_string(),
+            Format::Json => self.to_json().to_string(),
+            Format::Csv => self.to_csv(),
+            Format::Markdown => self.to_markdown(),
+            Format::Html => self.to_html(),
+        }
+    }
+
+    /// ">> cpu@s: cpu system space utilization" lines
+    pub fn notes(&self) -> String {
+        self.columns
+            .iter()
+            .filter_map(|c| c.note.map(|n| format!(">> {}: {}", c.title, n)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        let title = self
+            .columns
+            .iter()
+            .map(|c| Cell::new(c.title).style_spec("c"))
+            .collect();
+        table.add_row(Row::new(title));
+        for row in &self.rows {
+            let cells = row.iter().map(|v| Cell::new(v).style_spec("c")).collect();
+            table.add_row(Row::new(cells));
+        }
+        table
+    }
+
+    pub fn to_json(&self) -> Value {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| {
+                let mut object = Map::new();
+                for (column, value) in self.columns.iter().zip(row) {
+                    let value = if column.list {
+                        Value::from(
+                            value
+                                .lines()
+                                .filter(|l| !l.is_empty())
+                                .collect::<Vec<&str>>(),
+                        )
+                    } else {
+                        Value::from(value.as_str())
+                    };
+                    object.insert(column.key.to_string(), value);
+                }
+                Value::Object(object)
+            })
+            .collect();
+        Value::Array(rows)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut output = String::new();
+        let header: Vec<String> = self.columns.iter().map(|c| csv_escape(c.key)).collect();
+        let _ = writeln!(output, "{}", header.join(","));
+        for row in &self.rows {
+            // one line per host keeps the file easy to sort in a spreadsheet
+            let cells: Vec<String> = row
+                .iter()
+                .map(|v| csv_escape(&v.lines().collect::<Vec<&str>>().join("; ")))
+                .collect();
+            let _ = writeln!(output, "{}", cells.join(","));
+        }
+        output
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let cell = |v: &str| {
+            v.replace('|', "\\|")
+                .lines()
+                .collect::<Vec<&str>>()
+                .join("<br>")
+        };
+        let mut output = String::new();
+        let titles: Vec<String> = self.columns.iter().map(|c| cell(c.title)).collect();
+        let _ = writeln!(output, "| {} |", titles.join(" | "));
+        let rule: Vec<&str> = self.columns.iter().map(|_| "---").collect();
+        let _ = writeln!(output, "| {} |", rule.join(" | "));
+        for row in &self.rows {
+            let cells: Vec<String> = row.iter().map(|v| cell(v)).collect();
+            let _ = writeln!(output, "| {} |", cells.join(" | "));
+        }
+        output
+    }
+
+    pub fn to_html(&self) -> String {
+        let cell = |v: &str| {
+            v.lines()
+                .map(html_escape)
+                .collect::<Vec<String>>()
+                .join("<br>")
+        };
+        let mut output = String::from("<table>\n<thead>\n<tr>");
+        for column in &self.columns {
+            let _ = write!(output, "<th>{}</th>", html_escape(column.title));
+        }
+        output += "</tr>\n</thead>\n<tbody>\n";
+        for row in &self.rows {
+            output += "<tr>";
+            for value in row {
+                let _ = write!(output, "<td>{}</td>", cell(value));
+            }
+            output += "</tr>\n";
+        }
+        output += "</tbody>\n</table>\n";
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn sample_table() -> InfoTable {
+        let mut table = InfoTable::new(vec![
+            Column {
+                key: "name",
+                title: "name",
+                note: None,
+                list: false,
+            },
+            Column {
+                key: "gpu_user",
+                title: "gpu user",
+                note: Some("users of every gpu"),
+                list: true,
+            },
+        ]);
+        table.add_row(vec![String::from("node1"), String::from("alice\nbob")]);
+        table
+    }
+    #[test]
+    fn test_render_formats() {
+        let table = sample_table();
+        assert_eq!(table.to_csv(), "name,gpu_user\nnode1,alice; bob\n");
+        assert!(table.to_markdown().contains("| node1 | alice<br>bob |"));
+        assert!(table.to_html().contains("<td>alice<br>bob</td>"));
+        assert_eq!(
+            table.to_json().to_string(),
+            r#"[{"gpu_user":["alice","bob"],"name":"node1"}]"#
+        );
+        assert_eq!(table.notes(), ">> gpu user: users of every gpu");
+    }
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
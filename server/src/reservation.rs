@@ -0,0 +1,363 @@
+use actix_web::delete;
+use actix_web::get;
+use actix_web::post;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use log::error;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::alert;
+use crate::alert::Alert;
+use crate::redis_host;
+use crate::redis_load;
+use crate::redis_next_id;
+use crate::redis_save;
+use crate::ServerError;
+use crate::ServerInfo;
+use crate::PASSWORD;
+
+const RESERVATIONS_KEY: &str = "reservations";
+
+/// serializes the read-modify-write of the reservation list
+static RESERVATIONS_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Reservation {
+    pub id: u64,
+    pub host: String,
+    pub gpu: usize,
+    pub user: String,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub note: String,
+    pub created: DateTime<Local>,
+}
+
+impl Reservation {
+    fn overlaps(
+        &self,
+        host: &str,
+        gpu: usize,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> bool {
+        self.host == host && self.gpu == gpu && self.start < end && start < self.end
+    }
+
+    pub fn is_active(&self, now: DateTime<Local>) -> bool {
+        self.start <= now && now < self.end
+    }
+}
+
+fn load() -> Result<Vec<Reservation>, ServerError> {
+    let reservations: Vec<Reservation> = redis_load(RESERVATIONS_KEY)?;
+    Ok(reservations)
+}
+
+/// Drop finished bookings and save.
+fn save(reservations: &mut Vec<Reservation>) -> Result<(), ServerError> {
+    let now = Local::now();
+    reservations.retain(|r| r.end > now);
+    redis_save(RESERVATIONS_KEY, reservations)
+}
+
+/// Bookings active right now.
+pub fn active() -> Vec<Reservation> {
+    let now = Local::now();
+    match load() {
+        Ok(r) => r.into_iter().filter(|r| r.is_active(now)).collect(),
+        Err(e) => {
+            error!("load reservations error: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// "alice until 18:00" for the gpu or an empty string.
+pub fn describe(reservations: &[Reservation], host: &str, gpu: usize) -> String {
+    match reservations.iter().find(|r| r.host == host && r.gpu == gpu) {
+        Some(r) => format!("{} until {}", r.user, r.end.format("%m-%d %H:%M")),
+        None => String::new(),
+    }
+}
+
+/// Alert when somebody else runs on a reserved gpu.
+pub fn check(server_info: &ServerInfo) {
+    let reservations: Vec<Reservation> = active()
+        .into_iter()
+        .filter(|r| r.host == server_info.hostname)
+        .collect();
+    for r in reservations {
+        for process in &server_info.gpu.processes {
+            if process.gpu_index != r.gpu || process.user.is_empty() || process.user == r.user {
+                continue;
+            }
+            let message = format!(
+                "{} runs pid {} on gpu {} reserved by {} until {} ({})",
+                process.user,
+                process.pid,
+                r.gpu,
+                r.user,
+                r.end.format("%Y-%m-%d %H:%M"),
+                r.note
+            );
+            alert::raise(
+                &format!("reservation:{}:{}", r.id, process.pid),
+                Alert::new("reservation", &r.host, &process.user, &message),
+            );
+        }
+    }
+}
+
+/// RFC 3339, local "YYYY-MM-DD HH:MM[:SS]" or "YYYY-MM-DD" (midnight).
+pub fn parse_time(input: &str) -> Option<DateTime<Local>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(input.trim()) {
+        return Some(t.with_timezone(&Local));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(input.trim(), format) {
+            return Local.from_local_datetime(&t).single();
+        }
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d") {
+        return Local.from_local_datetime(&d.and_hms_opt(0, 0, 0)?).single();
+    }
+    None
+}
+
+#[derive(Deserialize)]
+pub struct ReservationRequest {
+    host: String,
+    gpu: usize,
+    user: String,
+    /// now if empty
+    start: Option<String>,
+    end: String,
+    #[serde(default)]
+    note: String,
+    /// the password of /update
+    password: String,
+}
+
+impl ReservationRequest {
+    /// Host and user without the blanks of a hand-written request.
+    fn trimmed(mut self) -> Self {
+        self.host = self.host.trim().to_string();
+        self.user = self.user.trim().to_string();
+        self
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReservationQuery {
+    host: Option<String>,
+    user: Option<String>,
+}
+
+#[get("/api/v1/reservations")]
+async fn list_reservations(query: web::Query<ReservationQuery>) -> impl Responder {
+    match load() {
+        Ok(reservations) => {
+            let now = Local::now();
+            let list: Vec<Reservation> = reservations
+                .into_iter()
+                .filter(|r| r.end > now)
+                .filter(|r| query.host.as_ref().is_none_or(|h| &r.host == h))
+                .filter(|r| query.user.as_ref().is_none_or(|u| &r.user == u))
+                .collect();
+            HttpResponse::Ok().json(list)
+        }
+        Err(e) => {
+            error!("load reservations error: {}", e);
+            HttpResponse::InternalServerError().body("load reservations failed")
+        }
+    }
+}
+
+#[post("/api/v1/reservations")]
+async fn create_reservation(request: web::Json<ReservationRequest>) -> impl Responder {
+    let request = request.into_inner().trimmed();
+    let now = Local::now();
+    let start = match &request.start {
+        Some(s) if !s.trim().is_empty() => match parse_time(s) {
+            Some(t) => t,
+            None => return HttpResponse::BadRequest().body(format!("invalid start: {}", s)),
+        },
+        _ => now,
+    };
+    let end = match parse_time(&request.end) {
+        Some(t) => t,
+        None => return HttpResponse::BadRequest().body(format!("invalid end: {}", request.end)),
+    };
+    if end <= start || end <= now {
+        return HttpResponse::BadRequest().body("end must be after start and in the future");
+    }
+    if request.password != PASSWORD {
+        return HttpResponse::Forbidden().body("password wrong!");
+    }
+    if request.host.is_empty() || request.user.is_empty() {
+        return HttpResponse::BadRequest().body("host and user are required");
+    }
+    match redis_host(&request.host) {
+        Ok(Some(info)) => {
+            let cards = info
+                .gpu
+                .details
+                .iter()
+                .filter(|d| !d.name.is_empty())
+                .count();
+            if request.gpu >= cards {
+                return HttpResponse::BadRequest().body(format!(
+                    "{} has {} gpu(s), there is no gpu {}",
+                    request.host, cards, request.gpu
+                ));
+            }
+        }
+        Ok(None) => {
+            return HttpResponse::BadRequest().body(format!(
+                "unknown host {}, it sent no update recently",
+                request.host
+            ))
+        }
+        Err(e) => {
+            error!("load host error: {}", e);
+            return HttpResponse::InternalServerError().body("load host failed");
+        }
+    }
+
+    let _guard = match RESERVATIONS_LOCK.lock() {
+        Ok(g) => g,
+        Err(_) => return HttpResponse::InternalServerError().body("reservation lock poisoned"),
+    };
+    let mut reservations = match load() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("load reservations error: {}", e);
+            return HttpResponse::InternalServerError().body("load reservations failed");
+        }
+    };
+    if let Some(conflict) = reservations
+        .iter()
+        .find(|r| r.end > now && r.overlaps(&request.host, request.gpu, start, end))
+    {
+        return HttpResponse::Conflict().json(conflict);
+    }
+    // an id of a finished booking is not handed out again, a late cancel finds nothing
+    let id = match redis_next_id(
+        RESERVATIONS_KEY,
+        reservations.iter().map(|r| r.id).max().unwrap_or(0),
+    ) {
+        Ok(id) => id,
+        Err(e) => {
+            error!("next reservation id error: {}", e);
+            return HttpResponse::InternalServerError().body("save reservations failed");
+        }
+    };
+    let reservation = Reservation {
+        id,
+        host: request.host,
+        gpu: request.gpu,
+        user: request.user,
+        start,
+        end,
+        note: request.note,
+        created: now,
+    };
+    reservations.push(reservation.clone());
+    match save(&mut reservations) {
+        Ok(_) => HttpResponse::Created().json(reservation),
+        Err(e) => {
+            error!("save reservations error: {}", e);
+            HttpResponse::InternalServerError().body("save reservations failed")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CancelQuery {
+    /// must be the holder of the booking
+    user: String,
+    /// the password of /update
+    password: String,
+}
+
+#[delete("/api/v1/reservations/{id}")]
+async fn delete_reservation(
+    path: web::Path<u64>,
+    query: web::Query<CancelQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if query.password != PASSWORD {
+        return HttpResponse::Forbidden().body("password wrong!");
+    }
+    let _guard = match RESERVATIONS_LOCK.lock() {
+        Ok(g) => g,
+        Err(_) => return HttpResponse::InternalServerError().body("reservation lock poisoned"),
+    };
+    let mut reservations = match load() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("load reservations error: {}", e);
+            return HttpResponse::InternalServerError().body("load reservations failed");
+        }
+    };
+    match reservations.iter().position(|r| r.id == id) {
+        Some(i) if reservations[i].user == query.user => {
+            let removed = reservations.remove(i);
+            match save(&mut reservations) {
+                Ok(_) => HttpResponse::Ok().json(removed),
+                Err(e) => {
+                    error!("save reservations error: {}", e);
+                    HttpResponse::InternalServerError().body("save reservations failed")
+                }
+            }
+        }
+        Some(_) => HttpResponse::Forbidden().body("only the holder can cancel a reservation"),
+        None => HttpResponse::NotFound().body(format!("no reservation {}", id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_overlaps() {
+        let t = |s: &str| parse_time(s).unwrap();
+        let r = Reservation {
+            id: 1,
+            host: String::from("node41"),
+            gpu: 0,
+            user: String::from("alice"),
+            start: t("2024-05-01 10:00"),
+            end: t("2024-05-01 12:00"),
+            note: String::new(),
+            created: t("2024-05-01 09:00"),
+        };
+        assert!(r.overlaps("node41", 0, t("2024-05-01 11:00"), t("2024-05-01 13:00")));
+        assert!(!r.overlaps("node41", 0, t("2024-05-01 12:00"), t("2024-05-01 13:00")));
+        assert!(!r.overlaps("node41", 1, t("2024-05-01 11:00"), t("2024-05-01 13:00")));
+        let request: ReservationRequest = serde_json::from_str(
+            r#"{"host": " node41", "gpu": 0, "user": "bob ", "end": "2024-05-01 13:00", "password": ""}"#,
+        )
+        .unwrap();
+        let request = request.trimmed();
+        assert_eq!(request.user, "bob");
+        assert!(r.overlaps(
+            &request.host,
+            request.gpu,
+            t("2024-05-01 11:00"),
+            t("2024-05-01 13:00")
+        ));
+        assert!(parse_time("2024-05-01T10:00:00+08:00").is_some());
+        assert!(parse_time("2024-05-01").is_some());
+        assert!(parse_time("tomorrow").is_none());
+    }
+}
@@ -0,0 +1,110 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SensorReading {
+    pub chip: String,
+    pub label: String,
+    pub value: f64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct PowerZone {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub top_level: bool,
+    pub watts: f64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Sensors {
+    #[serde(default)]
+    pub temperatures: Vec<SensorReading>,
+    #[serde(default)]
+    pub fans: Vec<SensorReading>,
+    #[serde(default)]
+    pub voltages: Vec<SensorReading>,
+    #[serde(default)]
+    pub power: Vec<PowerZone>,
+}
+
+/// Whether the reading is the temperature of a whole cpu package.
+fn is_cpu_package(reading: &SensorReading) -> bool {
+    match reading.chip.as_str() {
+        // intel, one per socket
+        "coretemp" => reading.label.starts_with("Package id"),
+        // amd
+        "k10temp" | "zenpower" => reading.label == "Tctl" || reading.label == "Tdie",
+        _ => false,
+    }
+}
+
+/// Hottest cpu package in celsius.
+pub fn max_package_temperature(sensors: &Sensors) -> Option<f64> {
+    sensors
+        .temperatures
+        .iter()
+        .filter(|r| is_cpu_package(r))
+        .map(|r| r.value)
+        .reduce(f64::max)
+}
+
+/// Watts of all packages, the zones inside them are already part of it.
+/// Other top level zones are left out, "psys" covers the whole platform
+/// including the packages.
+pub fn total_power(sensors: &Sensors) -> Option<f64> {
+    let packages: Vec<f64> = sensors
+        .power
+        .iter()
+        .filter(|z| z.top_level && z.name.starts_with("package-"))
+        .map(|z| z.watts)
+        .collect();
+    if packages.is_empty() {
+        None
+    } else {
+        Some(packages.iter().sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn reading(chip: &str, label: &str, value: f64) -> SensorReading {
+        SensorReading {
+            chip: chip.to_string(),
+            label: label.to_string(),
+            value,
+        }
+    }
+    fn zone(name: &str, top_level: bool, watts: f64) -> PowerZone {
+        PowerZone {
+            name: name.to_string(),
+            top_level,
+            watts,
+            ..Default::default()
+        }
+    }
+    #[test]
+    fn test_summary() {
+        let sensors = Sensors {
+            temperatures: vec![
+                reading("coretemp", "Package id 0", 61.0),
+                reading("coretemp", "Package id 1", 66.0),
+                reading("coretemp", "Core 3", 80.0),
+                reading("nvme", "Composite", 45.0),
+            ],
+            power: vec![
+                zone("package-0", true, 80.0),
+                zone("core", false, 50.0),
+                zone("package-1", true, 95.5),
+                zone("psys", true, 210.0),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(max_package_temperature(&sensors), Some(66.0));
+        assert_eq!(total_power(&sensors), Some(175.5));
+        assert_eq!(total_power(&Sensors::default()), None);
+    }
+}
@@ -0,0 +1,312 @@
+use actix_web::get;
+use actix_web::web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Local;
+use log::error;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::history::History;
+use crate::history::Tracked;
+use crate::render::Column;
+use crate::render::Format;
+use crate::render::InfoTable;
+use crate::ServerInfo;
+
+const DEFAULT_LIMIT: usize = 200;
+
+/// host, user, tty and pid
+type SessionKey = (String, String, String, u32);
+
+static LOGINS: History<Login> = History::new("logins");
+
+/// A login or a tmux or screen server, as sent by the client.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Session {
+    pub user: String,
+    #[serde(default)]
+    pub kind: String,
+    #[serde(default)]
+    pub tty: String,
+    #[serde(default)]
+    pub from: String,
+    /// unix time
+    #[serde(default)]
+    pub login_time: i64,
+    #[serde(default)]
+    pub pid: u32,
+}
+
+/// One session on one host, from login to the last update it showed up in.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Login {
+    pub host: String,
+    pub user: String,
+    pub kind: String,
+    pub tty: String,
+    pub from: String,
+    pub login_time: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+    #[serde(default)]
+    pub running: bool,
+    #[serde(default)]
+    pub pid: u32,
+}
+
+impl Tracked for Login {
+    type Key = SessionKey;
+
+    fn key(&self) -> SessionKey {
+        (
+            self.host.clone(),
+            self.user.clone(),
+            self.tty.clone(),
+            self.pid,
+        )
+    }
+
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn started(&self) -> DateTime<Local> {
+        self.login_time
+    }
+
+    fn last_seen(&self) -> DateTime<Local> {
+        self.last_seen
+    }
+
+    fn set_running(&mut self, running: bool) {
+        self.running = running;
+    }
+}
+
+impl Login {
+    /// Whether the session is this login, seen again.
+    fn is(&self, host: &str, session: &Session) -> bool {
+        self.host == host
+            && self.user == session.user
+            && self.tty == session.tty
+            && self.pid == session.pid
+            && self.login_time.timestamp() == session.login_time
+    }
+}
+
+/// "alice (2 logins, tmux)" per line, one line per user.
+pub fn describe_users(sessions: &[Session]) -> String {
+    let mut users: BTreeMap<&str, (usize, Vec<&str>)> = BTreeMap::new();
+    for s in sessions {
+        let user = users.entry(&s.user).or_default();
+        if s.kind == "login" {
+            user.0 += 1;
+        } else if !user.1.contains(&s.kind.as_str()) {
+            user.1.push(&s.kind);
+        }
+    }
+    users
+        .into_iter()
+        .map(|(user, (logins, multiplexers))| {
+            let mut what: Vec<String> = match logins {
+                0 => Vec::new(),
+                1 => vec![String::from("1 login")],
+                n => vec![format!("{} logins", n)],
+            };
+            what.extend(multiplexers.iter().map(|m| m.to_string()));
+            format!("{} ({})", user, what.join(", "))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Called on every update, follows the sessions of the host and archives the ended ones.
+pub fn record(server_info: &ServerInfo) {
+    let now = Local::now();
+    let host = &server_info.hostname;
+    let mut logins = match LOGINS.running().lock() {
+        Ok(l) => l,
+        Err(_) => return,
+    };
+    let new: Vec<&Session> = server_info
+        .sessions
+        .iter()
+        .filter(|s| !logins.contains_key(&(host.clone(), s.user.clone(), s.tty.clone(), s.pid)))
+        .collect();
+    // a host that missed updates must not split a session into two rows
+    if !new.is_empty() {
+        match LOGINS.resume(now, |l| new.iter().any(|s| l.is(host, s))) {
+            Ok(resumed) => {
+                for login in resumed {
+                    logins.insert(login.key(), login);
+                }
+            }
+            Err(e) => error!("resume logins error: {}", e),
+        }
+    }
+    for s in &server_info.sessions {
+        let key = (host.clone(), s.user.clone(), s.tty.clone(), s.pid);
+        let login = logins.entry(key).or_insert_with(|| Login {
+            host: host.clone(),
+            user: s.user.clone(),
+            kind: s.kind.clone(),
+            tty: s.tty.clone(),
+            from: s.from.clone(),
+            login_time: DateTime::from_timestamp(s.login_time, 0)
+                .map(|t| t.with_timezone(&Local))
+                .unwrap_or(now),
+            last_seen: now,
+            running: true,
+            pid: s.pid,
+        });
+        login.last_seen = now;
+    }
+    let ended = LOGINS.end_gone(&mut logins, host, now, true);
+    if server_info.sessions.is_empty() && ended.is_empty() {
+        return;
+    }
+    LOGINS.save(&logins, ended);
+}
+
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    host: Option<String>,
+    user: Option<String>,
+    /// defaults to 7 days ago
+    from: Option<String>,
+    /// defaults to now
+    to: Option<String>,
+    limit: Option<usize>,
+    format: Option<String>,
+}
+
+/// Current and archived logins that match, newest first.
+fn search(query: &LoginQuery) -> Result<Vec<Login>, String> {
+    let mut logins = LOGINS.search(query.from.as_deref(), query.to.as_deref())?;
+    logins.retain(|l| {
+        query.host.as_ref().is_none_or(|h| &l.host == h)
+            && query.user.as_ref().is_none_or(|u| &l.user == u)
+    });
+    logins.sort_by_key(|l| std::cmp::Reverse(l.login_time));
+    logins.truncate(query.limit.unwrap_or(DEFAULT_LIMIT));
+    Ok(logins)
+}
+
+fn render(logins: &[Login], format: Format) -> HttpResponse {
+    if format == Format::Json {
+        return HttpResponse::Ok().json(logins);
+    }
+    let column = |key, title, note| Column {
+        key,
+        title,
+        note,
+        list: false,
+    };
+    let mut table = InfoTable::new(vec![
+        column("host", "host", None),
+        column("user", "user", None),
+        column(
+            "kind",
+            "kind",
+            Some("ssh or console login, tmux or screen server"),
+        ),
+        column("tty", "tty", None),
+        column("from", "from", None),
+        column("login_time", "login", None),
+        column("last_seen", "logout", None),
+    ]);
+    for l in logins {
+        let last_seen = if l.running {
+            String::from("still logged in")
+        } else {
+            l.last_seen.format("%Y-%m-%d %H:%M:%S").to_string()
+        };
+        table.add_row(vec![
+            l.host.clone(),
+            l.user.clone(),
+            l.kind.clone(),
+            l.tty.clone(),
+            l.from.clone(),
+            l.login_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            last_seen,
+        ]);
+    }
+    let body = match format {
+        Format::Table => format!("{}{}", table.render(format), table.notes()),
+        _ => table.render(format),
+    };
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(body)
+}
+
+#[get("/api/v1/logins")]
+async fn login_history(req: HttpRequest, query: web::Query<LoginQuery>) -> impl Responder {
+    let format = match Format::negotiate(&req, query.format.as_deref()) {
+        Ok(f) => f,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    match search(&query) {
+        Ok(logins) => render(&logins, format),
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_describe_users() {
+        let session = |user: &str, kind: &str| Session {
+            user: user.to_string(),
+            kind: kind.to_string(),
+            ..Default::default()
+        };
+        let sessions = vec![
+            session("bob", "login"),
+            session("alice", "login"),
+            session("alice", "tmux"),
+            session("alice", "login"),
+            session("carol", "screen"),
+        ];
+        assert_eq!(
+            describe_users(&sessions),
+            "alice (2 logins, tmux)\nbob (1 login)\ncarol (screen)"
+        );
+    }
+    #[test]
+    fn test_is() {
+        let login = Login {
+            host: String::from("node1"),
+            user: String::from("alice"),
+            kind: String::from("login"),
+            tty: String::from("pts/0"),
+            from: String::new(),
+            login_time: DateTime::from_timestamp(1_700_000_000, 0)
+                .unwrap()
+                .with_timezone(&Local),
+            last_seen: Local::now(),
+            running: false,
+            pid: 4242,
+        };
+        let session = Session {
+            user: String::from("alice"),
+            kind: String::from("login"),
+            tty: String::from("pts/0"),
+            login_time: 1_700_000_000,
+            pid: 4242,
+            ..Default::default()
+        };
+        assert!(login.is("node1", &session));
+        assert!(!login.is("node2", &session));
+        // a new login on the same tty is another session
+        let later = Session {
+            login_time: 1_700_000_600,
+            ..session
+        };
+        assert!(!login.is("node1", &later));
+    }
+}
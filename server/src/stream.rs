@@ -0,0 +1,283 @@
+use actix_web::get;
+use actix_web::http::header;
+use actix_web::web;
+use actix_web::web::Bytes;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use chrono::Local;
+use log::error;
+use log::info;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::ServerInfo;
+
+const CHANNEL_CAPACITY: usize = 256;
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+static STREAM: OnceCell<broadcast::Sender<StreamEvent>> = OnceCell::new();
+static LAST_SEEN: OnceCell<Mutex<BTreeMap<String, HostSeen>>> = OnceCell::new();
+
+struct HostSeen {
+    time: Instant,
+    labels: HashMap<String, String>,
+    stale: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct StreamEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    host: String,
+    time: String,
+    labels: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<ServerInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl StreamEvent {
+    fn new(kind: &str, host: &str, labels: &HashMap<String, String>) -> StreamEvent {
+        StreamEvent {
+            kind: kind.to_string(),
+            host: host.to_string(),
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            labels: labels.clone(),
+            data: None,
+            message: None,
+        }
+    }
+}
+
+fn sender() -> &'static broadcast::Sender<StreamEvent> {
+    STREAM.get_or_init(|| broadcast::channel(CHANNEL_CAPACITY).0)
+}
+
+fn last_seen() -> &'static Mutex<BTreeMap<String, HostSeen>> {
+    LAST_SEEN.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+fn publish(event: StreamEvent) {
+    // an error only means that nobody is listening right now
+    let _ = sender().send(event);
+}
+
+/// Called for every stored update, pushed or pulled.
+pub fn publish_update(server_info: &ServerInfo) {
+    let mut server_info = server_info.clone();
+    server_info.password = String::new();
+    if let Ok(mut seen) = last_seen().lock() {
+        seen.insert(
+            server_info.hostname.clone(),
+            HostSeen {
+                time: Instant::now(),
+                labels: server_info.labels.clone(),
+                stale: false,
+            },
+        );
+    }
+    let mut event = StreamEvent::new("update", &server_info.hostname, &server_info.labels);
+    event.data = Some(server_info);
+    publish(event);
+}
+
+/// Forward an alert to the stream listeners of its host.
+pub fn publish_alert(host: &str, message: &str) {
+    publish_message("alert", host, message);
+}
+
+/// Send an event of type `kind` with a text message, e.g. a host event.
+pub fn publish_message(kind: &str, host: &str, message: &str) {
+    let labels = match last_seen().lock() {
+        Ok(seen) => seen.get(host).map(|s| s.labels.clone()).unwrap_or_default(),
+        Err(_) => HashMap::new(),
+    };
+    let mut event = StreamEvent::new(kind, host, &labels);
+    event.message = Some(message.to_string());
+    publish(event);
+}
+
+/// Emit a `stale` event once for every host that stopped sending updates.
+pub fn start_stale_watch(stale_after: u64) {
+    let stale_after = Duration::from_secs(stale_after.max(1));
+    info!("hosts are stale after {:?} without update", stale_after);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            ticker.tick().await;
+            let mut events = Vec::new();
+            if let Ok(mut seen) = last_seen().lock() {
+                for (host, host_seen) in seen.iter_mut() {
+                    if !host_seen.stale && host_seen.time.elapsed() > stale_after {
+                        host_seen.stale = true;
+                        events.push(StreamEvent::new("stale", host, &host_seen.labels));
+                    }
+                }
+            }
+            for event in events {
+                publish(event);
+            }
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    /// comma separated host names, a trailing `*` matches a prefix
+    host: Option<String>,
+    /// comma separated `key=value` pairs, all of them must match
+    label: Option<String>,
+}
+
+struct StreamFilter {
+    hosts: Vec<String>,
+    labels: Vec<(String, String)>,
+}
+
+impl StreamFilter {
+    fn new(query: &StreamQuery) -> StreamFilter {
+        let split = |input: &Option<String>| -> Vec<String> {
+            match input {
+                Some(i) => i
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+        let labels = split(&query.label)
+            .iter()
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+        StreamFilter {
+            hosts: split(&query.host),
+            labels,
+        }
+    }
+    fn matches(&self, event: &StreamEvent) -> bool {
+        let host_match = self.hosts.is_empty()
+            || self.hosts.iter().any(|h| match h.strip_suffix('*') {
+                Some(prefix) => event.host.starts_with(prefix),
+                None => &event.host == h,
+            });
+        let label_match = self
+            .labels
+            .iter()
+            .all(|(k, v)| event.labels.get(k) == Some(v));
+        host_match && label_match
+    }
+}
+
+/// Wait for the next event passing the filter, None on keepalive timeout.
+async fn next_event(
+    rx: &mut broadcast::Receiver<StreamEvent>,
+    filter: &StreamFilter,
+) -> Result<Option<String>, RecvError> {
+    let deadline = tokio::time::sleep(KEEPALIVE);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return Ok(None),
+            received = rx.recv() => match received {
+                Ok(event) => {
+                    if filter.matches(&event) {
+                        match serde_json::to_string(&event) {
+                            Ok(e) => return Ok(Some(e)),
+                            Err(e) => error!("serialize stream event error: {}", e),
+                        }
+                    }
+                }
+                // slow consumer, skip the missed events
+                Err(RecvError::Lagged(_)) => continue,
+                Err(e) => return Err(e),
+            },
+        }
+    }
+}
+
+fn sse_response(filter: StreamFilter) -> HttpResponse {
+    let rx = sender().subscribe();
+    let body = futures_util::stream::unfold((rx, filter), |(mut rx, filter)| async move {
+        let chunk = match next_event(&mut rx, &filter).await {
+            Ok(Some(event)) => format!("data: {}\n\n", event),
+            Ok(None) => String::from(": keepalive\n\n"),
+            Err(_) => return None,
+        };
+        Some((
+            Ok::<Bytes, actix_web::Error>(Bytes::from(chunk)),
+            (rx, filter),
+        ))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(body)
+}
+
+fn ws_response(
+    req: &HttpRequest,
+    body: web::Payload,
+    filter: StreamFilter,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, mut session, mut msg_stream) = actix_ws::handle(req, body)?;
+    let mut rx = sender().subscribe();
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                event = next_event(&mut rx, &filter) => {
+                    let sent = match event {
+                        Ok(Some(e)) => session.text(e).await,
+                        Ok(None) => session.ping(b"").await,
+                        Err(_) => break,
+                    };
+                    if sent.is_err() {
+                        break;
+                    }
+                }
+                msg = msg_stream.recv() => match msg {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+/// Server-Sent Events by default, WebSocket when the client asks for an upgrade.
+#[get("/api/v1/stream")]
+async fn stream(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<StreamQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let filter = StreamFilter::new(&query);
+    let upgrade = req
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|u| u.to_str().ok())
+        .map(|u| u.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+    if upgrade {
+        ws_response(&req, body, filter)
+    } else {
+        Ok(sse_response(filter))
+    }
+}
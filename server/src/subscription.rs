@@ -0,0 +1,354 @@
+use actix_web::delete;
+use actix_web::get;
+use actix_web::post;
+use actix_web::web;
+use actix_web::HttpResponse;
+use actix_web::Responder;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Local;
+use log::error;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::free;
+use crate::notify;
+use crate::notify::Channel;
+use crate::notify::Notification;
+use crate::query::parse_number;
+use crate::redis_database;
+use crate::redis_load;
+use crate::redis_next_id;
+use crate::redis_save;
+use crate::reservation;
+use crate::reservation::Reservation;
+use crate::ServerError;
+use crate::ServerInfo;
+use crate::PASSWORD;
+
+const SUBSCRIPTIONS_KEY: &str = "subscriptions";
+/// default lifetime of a subscription that never fires (hours)
+const DEFAULT_EXPIRES_IN: i64 = 24;
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// serializes the read-modify-write of the subscription list
+static SUBSCRIPTIONS_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Subscription {
+    pub id: u64,
+    pub user: String,
+    pub count: usize,
+    /// bytes
+    pub min_mem: u64,
+    pub model: Option<String>,
+    /// host names, a trailing `*` matches a prefix, empty means all hosts
+    pub hosts: Vec<String>,
+    pub same_host: bool,
+    pub channel: Channel,
+    pub created: DateTime<Local>,
+    pub expires: DateTime<Local>,
+}
+
+impl Subscription {
+    fn host_matches(&self, host: &str) -> bool {
+        self.hosts.is_empty()
+            || self.hosts.iter().any(|h| match h.strip_suffix('*') {
+                Some(prefix) => host.starts_with(prefix),
+                None => host == h,
+            })
+    }
+
+    /// The cards that fulfil the subscription, empty if not yet.
+    fn satisfied(
+        &self,
+        database: &BTreeMap<String, ServerInfo>,
+        reservations: &[Reservation],
+    ) -> Vec<String> {
+        let database: BTreeMap<String, ServerInfo> = database
+            .iter()
+            .filter(|(host, _)| self.host_matches(host))
+            .map(|(host, server_info)| (host.clone(), server_info.clone()))
+            .collect();
+        let candidates = free::candidates(&database, self.min_mem, self.model.as_deref(), 100.0)
+            .into_iter()
+            // a card somebody runs on is not free, nor one somebody else has booked
+            .filter(|c| c.processes == 0)
+            .filter(|c| {
+                reservations
+                    .iter()
+                    .all(|r| r.user == self.user || r.host != c.host || r.gpu != c.gpu)
+            })
+            .collect::<Vec<free::Candidate>>();
+        free::choose(&candidates, self.count, self.same_host)
+            .iter()
+            .map(|c| c.slot())
+            .collect()
+    }
+
+    fn describe(&self) -> String {
+        let mut description = format!(
+            "{} GPU(s) with >= {:.1} GiB free",
+            self.count,
+            self.min_mem as f64 / GIB
+        );
+        if let Some(m) = &self.model {
+            description += &format!(", model ~ {}", m);
+        }
+        if !self.hosts.is_empty() {
+            description += &format!(", on {}", self.hosts.join(","));
+        }
+        if self.same_host {
+            description += ", on the same host";
+        }
+        description
+    }
+}
+
+fn load() -> Result<Vec<Subscription>, ServerError> {
+    let subscriptions: Vec<Subscription> = redis_load(SUBSCRIPTIONS_KEY)?;
+    Ok(subscriptions)
+}
+
+/// Called on every update, notifies and removes the subscriptions that are fulfilled.
+pub fn check() {
+    let _guard = match SUBSCRIPTIONS_LOCK.lock() {
+        Ok(g) => g,
+        Err(_) => return,
+    };
+    let subscriptions = match load() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("load subscriptions error: {}", e);
+            return;
+        }
+    };
+    if subscriptions.is_empty() {
+        return;
+    }
+    let database = match redis_database() {
+        Ok(d) => d,
+        Err(e) => {
+            error!("get redis database failed: {}", e);
+            return;
+        }
+    };
+    let reservations = reservation::active();
+    let now = Local::now();
+    let before = subscriptions.len();
+    let mut remaining = Vec::new();
+    for subscription in subscriptions {
+        if subscription.expires <= now {
+            continue;
+        }
+        let slots = subscription.satisfied(&database, &reservations);
+        if slots.is_empty() {
+            remaining.push(subscription);
+            continue;
+        }
+        notify::send(
+            subscription.channel.clone(),
+            Notification {
+                kind: String::from("gpus_free"),
+                user: subscription.user.clone(),
+                subject: format!("GPUs free: {}", slots.join(" ")),
+                message: format!(
+                    "{} asked for {}, free now: {}",
+                    subscription.user,
+                    subscription.describe(),
+                    slots.join(" ")
+                ),
+            },
+        );
+    }
+    if remaining.len() != before {
+        if let Err(e) = redis_save(SUBSCRIPTIONS_KEY, &remaining) {
+            error!("save subscriptions error: {}", e);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SubscriptionRequest {
+    user: String,
+    count: Option<usize>,
+    /// "20GiB", "20000MiB" or plain bytes
+    min_mem: Option<String>,
+    model: Option<String>,
+    #[serde(default)]
+    hosts: Vec<String>,
+    #[serde(default)]
+    same_host: bool,
+    channel: Channel,
+    /// hours
+    expires_in: Option<i64>,
+    /// the password of /update
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct SubscriptionQuery {
+    user: Option<String>,
+}
+
+#[get("/api/v1/subscriptions")]
+async fn list_subscriptions(query: web::Query<SubscriptionQuery>) -> impl Responder {
+    match load() {
+        Ok(subscriptions) => {
+            let now = Local::now();
+            let list: Vec<Subscription> = subscriptions
+                .into_iter()
+                .filter(|s| s.expires > now)
+                .filter(|s| query.user.as_ref().is_none_or(|u| &s.user == u))
+                .map(|mut s| {
+                    s.channel = s.channel.redacted();
+                    s
+                })
+                .collect();
+            HttpResponse::Ok().json(list)
+        }
+        Err(e) => {
+            error!("load subscriptions error: {}", e);
+            HttpResponse::InternalServerError().body("load subscriptions failed")
+        }
+    }
+}
+
+#[post("/api/v1/subscriptions")]
+async fn create_subscription(request: web::Json<SubscriptionRequest>) -> impl Responder {
+    if request.password != PASSWORD {
+        return HttpResponse::Forbidden().body("password wrong!");
+    }
+    if request.user.trim().is_empty() {
+        return HttpResponse::BadRequest().body("user is required");
+    }
+    if let Err(e) = request.channel.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let min_mem = match &request.min_mem {
+        Some(m) => match parse_number(m.trim()) {
+            Some(n) => n as u64,
+            None => return HttpResponse::BadRequest().body(format!("invalid min_mem: {}", m)),
+        },
+        None => 0,
+    };
+    let now = Local::now();
+    let expires_in = request.expires_in.unwrap_or(DEFAULT_EXPIRES_IN).max(1);
+
+    let _guard = match SUBSCRIPTIONS_LOCK.lock() {
+        Ok(g) => g,
+        Err(_) => return HttpResponse::InternalServerError().body("subscription lock poisoned"),
+    };
+    let mut subscriptions = match load() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("load subscriptions error: {}", e);
+            return HttpResponse::InternalServerError().body("load subscriptions failed");
+        }
+    };
+    subscriptions.retain(|s| s.expires > now);
+    // an id of a fired or expired subscription is not handed out again,
+    // a late cancel must not remove the subscription of somebody else
+    let floor = subscriptions.iter().map(|s| s.id).max().unwrap_or(0);
+    let id = match redis_next_id(SUBSCRIPTIONS_KEY, floor) {
+        Ok(id) => id,
+        Err(e) => {
+            error!("next subscription id error: {}", e);
+            return HttpResponse::InternalServerError().body("save subscriptions failed");
+        }
+    };
+    let subscription = Subscription {
+        id,
+        user: request.user.trim().to_string(),
+        count: request.count.unwrap_or(1).max(1),
+        min_mem,
+        model: request.model.clone().filter(|m| !m.trim().is_empty()),
+        hosts: request.hosts.clone(),
+        same_host: request.same_host,
+        channel: request.channel.clone(),
+        created: now,
+        expires: now + Duration::hours(expires_in),
+    };
+    subscriptions.push(subscription.clone());
+    match redis_save(SUBSCRIPTIONS_KEY, &subscriptions) {
+        Ok(_) => HttpResponse::Created().json(subscription),
+        Err(e) => {
+            error!("save subscriptions error: {}", e);
+            HttpResponse::InternalServerError().body("save subscriptions failed")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CancelQuery {
+    /// must be the owner of the subscription
+    user: String,
+    /// the password of /update
+    password: String,
+}
+
+#[delete("/api/v1/subscriptions/{id}")]
+async fn delete_subscription(
+    path: web::Path<u64>,
+    query: web::Query<CancelQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if query.password != PASSWORD {
+        return HttpResponse::Forbidden().body("password wrong!");
+    }
+    let _guard = match SUBSCRIPTIONS_LOCK.lock() {
+        Ok(g) => g,
+        Err(_) => return HttpResponse::InternalServerError().body("subscription lock poisoned"),
+    };
+    let mut subscriptions = match load() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("load subscriptions error: {}", e);
+            return HttpResponse::InternalServerError().body("load subscriptions failed");
+        }
+    };
+    match subscriptions.iter().position(|s| s.id == id) {
+        Some(i) if subscriptions[i].user == query.user => {
+            let removed = subscriptions.remove(i);
+            match redis_save(SUBSCRIPTIONS_KEY, &subscriptions) {
+                Ok(_) => HttpResponse::Ok().json(removed),
+                Err(e) => {
+                    error!("save subscriptions error: {}", e);
+                    HttpResponse::InternalServerError().body("save subscriptions failed")
+                }
+            }
+        }
+        Some(_) => HttpResponse::Forbidden().body("only the owner can cancel a subscription"),
+        None => HttpResponse::NotFound().body(format!("no subscription {}", id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_host_matches() {
+        let now = Local::now();
+        let mut subscription = Subscription {
+            id: 1,
+            user: String::from("alice"),
+            count: 1,
+            min_mem: 0,
+            model: None,
+            hosts: Vec::new(),
+            same_host: false,
+            channel: Channel::Webhook {
+                url: String::from("http://localhost/hook"),
+            },
+            created: now,
+            expires: now,
+        };
+        assert!(subscription.host_matches("node1"));
+        subscription.hosts = vec![String::from("gpu*"), String::from("node1")];
+        assert!(subscription.host_matches("gpu07"));
+        assert!(subscription.host_matches("node1"));
+        assert!(!subscription.host_matches("node12"));
+    }
+}
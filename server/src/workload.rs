@@ -0,0 +1,65 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The container or slurm job of a process, as sent by the client.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Workload {
+    pub kind: String,
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    /// owner of a slurm job, the process may run as somebody else
+    #[serde(default)]
+    pub user: String,
+}
+
+/// "job 12345 (alice)", "docker trainer (alice)" or "docker 4f3c2a1b0e9d (alice)",
+/// `user` runs the process and stands in for an unknown job owner.
+pub fn describe(workload: &Workload, user: &str) -> String {
+    let user = if workload.user.is_empty() {
+        user
+    } else {
+        &workload.user
+    };
+    let mut text = match workload.kind.as_str() {
+        "slurm" => format!("job {}", workload.id),
+        kind if !workload.name.is_empty() => format!("{} {}", kind, workload.name),
+        // short id as printed by `docker ps`
+        kind => format!("{} {}", kind, workload.id.get(..12).unwrap_or(&workload.id)),
+    };
+    if !user.is_empty() {
+        text += &format!(" ({})", user);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_describe() {
+        let workload = |kind: &str, id: &str, name: &str| Workload {
+            kind: kind.to_string(),
+            id: id.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            describe(&workload("slurm", "12345", ""), "alice"),
+            "job 12345 (alice)"
+        );
+        let job = Workload {
+            user: String::from("bob"),
+            ..workload("slurm", "678", "")
+        };
+        assert_eq!(describe(&job, "root"), "job 678 (bob)");
+        assert_eq!(
+            describe(&workload("docker", "4f3c2a1b0e9d8c7b", "trainer"), ""),
+            "docker trainer"
+        );
+        assert_eq!(
+            describe(&workload("kubernetes", "4f3c2a1b0e9d8c7b", ""), "root"),
+            "kubernetes 4f3c2a1b0e9d (root)"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+"use strict";
+
+const REFRESH_MS = 5000;
+const EVENT_LIMIT = 30;
+
+function level(ratio) {
+  if (ratio >= 0.85) {
+    return "crit";
+  }
+  if (ratio >= 0.5) {
+    return "warn";
+  }
+  return "ok";
+}
+
+function tempLevel(celsius) {
+  if (celsius >= 85) {
+    return "crit";
+  }
+  if (celsius >= 70) {
+    return "warn";
+  }
+  return "ok";
+}
+
+function el(tag, className, text) {
+  const node = document.createElement(tag);
+  if (className) {
+    node.className = className;
+  }
+  if (text !== undefined) {
+    node.textContent = text;
+  }
+  return node;
+}
+
+function metric(label, ratio, valueText, cls) {
+  const row = el("div", "metric");
+  row.appendChild(el("span", "label", label));
+  const bar = el("div", "bar");
+  const fill = el("div", cls || level(ratio));
+  fill.style.width = Math.min(Math.max(ratio, 0), 1) * 100 + "%";
+  bar.appendChild(fill);
+  row.appendChild(bar);
+  row.appendChild(el("span", "value", valueText));
+  return row;
+}
+
+// "36 %" -> 36, "24564 MiB" -> 24564
+function number(text) {
+  const value = parseFloat(text);
+  return isNaN(value) ? null : value;
+}
+
+function gib(bytes) {
+  return (bytes / 1024 / 1024 / 1024).toFixed(1) + " GiB";
+}
+
+// same rule as database_process() on the server
+function owner(gpuUser) {
+  if (gpuUser.includes("/")) {
+    const parts = gpuUser.split("/");
+    return parts.length > 2 ? parts[2] : parts[parts.length - 1];
+  }
+  if (gpuUser.includes("no running processes found")) {
+    return null;
+  }
+  return gpuUser;
+}
+
+function addresses(net) {
+  return Object.keys(net)
+    .sort()
+    .filter((name) => !net[name].includes("null") && !net[name].includes("127.0.0.1"))
+    .map((name) => name + ": " + net[name])
+    .join("  ");
+}
+
+function memoryMetric(label, hm) {
+  const used = number(hm.used_bytes);
+  const total = number(hm.total_bytes);
+  if (used !== null && total) {
+    return metric(label, used / total, gib(used) + " / " + gib(total));
+  }
+  if (hm.used !== undefined) {
+    return metric(label, 0, hm.used + " / " + hm.total);
+  }
+  return null;
+}
+
+function gpuCard(detail) {
+  const gpu = el("div", "gpu");
+  const name = el("div", "name");
+  name.appendChild(el("span", "", detail.name));
+  const temp = number(detail.temperature_gpu);
+  if (temp !== null) {
+    name.appendChild(el("span", tempLevel(temp), temp + " C"));
+  }
+  gpu.appendChild(name);
+  const util = number(detail.utilization_gpu);
+  if (util !== null) {
+    gpu.appendChild(metric("util", util / 100, util + " %"));
+  }
+  const used = number(detail.memory_used);
+  const total = number(detail.memory_total);
+  if (used !== null && total) {
+    gpu.appendChild(
+      metric("memory", used / total, (used / 1024).toFixed(1) + " / " + (total / 1024).toFixed(1) + " GiB")
+    );
+  }
+  return gpu;
+}
+
+// 93784 -> "1d 2h 3m"
+function duration(seconds) {
+  const days = Math.floor(seconds / 86400);
+  const hours = Math.floor((seconds % 86400) / 3600);
+  const minutes = Math.floor((seconds % 3600) / 60);
+  return days + "d " + hours + "h " + minutes + "m";
+}
+
+function hostCard(hostname, info) {
+  const card = el("section", "card");
+  const title = el("h2");
+  title.appendChild(el("span", "", hostname));
+  title.appendChild(el("span", "heartbeat", info.other.new_nowtime || ""));
+  card.appendChild(title);
+  card.appendChild(el("div", "addr", addresses(info.net)));
+  if (info.uptime_seconds !== undefined && info.uptime_seconds !== null) {
+    card.appendChild(el("div", "uptime", "up " + duration(info.uptime_seconds)));
+  }
+
+  const cpuUsed = (info.cpu.user || 0) + (info.cpu.system || 0) + (info.cpu.nice || 0);
+  card.appendChild(metric("cpu", cpuUsed, (cpuUsed * 100).toFixed(0) + " %"));
+  if (info.cpu.temp !== undefined) {
+    const t = info.cpu.temp;
+    card.appendChild(metric("cpu temp", t / 100, t.toFixed(0) + " C", tempLevel(t)));
+  }
+  for (const [label, hm] of [["mem", info.mem], ["swap", info.swap]]) {
+    const m = memoryMetric(label, hm);
+    if (m) {
+      card.appendChild(m);
+    }
+  }
+
+  for (const detail of info.gpu.details) {
+    if (detail.name) {
+      card.appendChild(gpuCard(detail));
+    }
+  }
+
+  const users = el("div", "users");
+  for (const gpuUser of info.gpu.users) {
+    const name = owner(gpuUser);
+    if (name && name !== "null") {
+      users.appendChild(el("span", name === "driver failed" ? "user failed" : "user", name));
+    }
+  }
+  if (users.childElementCount > 0) {
+    card.appendChild(users);
+  }
+  return card;
+}
+
+function matches(hostname, info, filter) {
+  if (!filter) {
+    return true;
+  }
+  const haystack = [hostname].concat(info.gpu.users.map((u) => owner(u) || "")).join(" ").toLowerCase();
+  return haystack.includes(filter.toLowerCase());
+}
+
+let lastDatabase = {};
+let lastEvents = [];
+
+function renderEvents(filter) {
+  const list = document.getElementById("event-list");
+  list.replaceChildren();
+  for (const event of lastEvents) {
+    const text = [event.host, event.kind, event.message].join(" ").toLowerCase();
+    if (filter && !text.includes(filter.toLowerCase())) {
+      continue;
+    }
+    const item = el("li", "event " + event.kind);
+    item.appendChild(el("span", "time", new Date(event.time).toLocaleString()));
+    item.appendChild(el("span", "host", event.host));
+    item.appendChild(el("span", "kind", event.kind));
+    item.appendChild(el("span", "", event.message));
+    list.appendChild(item);
+  }
+}
+
+function render() {
+  const filter = document.getElementById("filter").value.trim();
+  const hosts = document.getElementById("hosts");
+  hosts.replaceChildren();
+  for (const hostname of Object.keys(lastDatabase).sort()) {
+    const info = lastDatabase[hostname];
+    if (hostname && matches(hostname, info, filter)) {
+      hosts.appendChild(hostCard(hostname, info));
+    }
+  }
+  renderEvents(filter);
+}
+
+async function refresh() {
+  const error = document.getElementById("error");
+  try {
+    const response = await fetch("/info2");
+    const body = await response.text();
+    lastDatabase = JSON.parse(body);
+    const events = await fetch("/api/v1/events?limit=" + EVENT_LIMIT);
+    if (events.ok) {
+      lastEvents = await events.json();
+    }
+    error.hidden = true;
+    document.getElementById("updated").textContent = "updated " + new Date().toLocaleTimeString();
+    render();
+  } catch (e) {
+    error.textContent = "load /info2 failed: " + e;
+    error.hidden = false;
+  }
+}
+
+// apply pushed updates immediately, the periodic refresh drops expired hosts
+function subscribe() {
+  if (!window.EventSource) {
+    return;
+  }
+  const source = new EventSource("/api/v1/stream");
+  source.onmessage = (message) => {
+    if (!document.getElementById("auto-refresh").checked) {
+      return;
+    }
+    const event = JSON.parse(message.data);
+    if (event.type === "update" && event.data) {
+      lastDatabase[event.host] = event.data;
+      render();
+    } else {
+      refresh();
+    }
+  };
+}
+
+document.getElementById("filter").addEventListener("input", render);
+setInterval(() => {
+  if (document.getElementById("auto-refresh").checked) {
+    refresh();
+  }
+}, REFRESH_MS);
+refresh();
+subscribe();